@@ -0,0 +1,57 @@
+"""Writes tiny.nnue, a random 768 -> 8x2 -> 1 network for checking inference,
+and prints reference evaluations computed independently of the engine."""
+import random
+import struct
+
+HIDDEN = 8
+QA, QB, SCALE = 255, 64, 400
+PIECES = "pnbrqk"
+
+rng = random.Random(20240406)
+feature_weights = [rng.randint(-64, 64) for _ in range(768 * HIDDEN)]
+feature_bias = [rng.randint(0, 64) for _ in range(HIDDEN)]
+output_weights = [rng.randint(-32, 32) for _ in range(2 * HIDDEN)]
+output_bias = rng.randint(-500, 500)
+
+with open("tiny.nnue", "wb") as f:
+    f.write(b"TNN1")
+    f.write(struct.pack("<I", HIDDEN))
+    f.write(struct.pack("<%dh" % len(feature_weights), *feature_weights))
+    f.write(struct.pack("<%dh" % len(feature_bias), *feature_bias))
+    f.write(struct.pack("<%dh" % len(output_weights), *output_weights))
+    f.write(struct.pack("<i", output_bias))
+
+
+def evaluate(fen):
+    placement, turn = fen.split()[:2]
+    white = list(feature_bias)
+    black = list(feature_bias)
+    for rank_from_top, row in enumerate(placement.split("/")):
+        file = 0
+        for ch in row:
+            if ch.isdigit():
+                file += int(ch)
+                continue
+            square = (7 - rank_from_top) * 8 + file
+            piece = PIECES.index(ch.lower())
+            is_white = ch.isupper()
+            w_feature = (0 if is_white else 384) + piece * 64 + square
+            b_feature = (384 if is_white else 0) + piece * 64 + (square ^ 56)
+            for i in range(HIDDEN):
+                white[i] += feature_weights[w_feature * HIDDEN + i]
+                black[i] += feature_weights[b_feature * HIDDEN + i]
+            file += 1
+    us, them = (white, black) if turn == "w" else (black, white)
+    out = sum(min(max(a, 0), QA) * output_weights[i] for i, a in enumerate(us))
+    out += sum(min(max(a, 0), QA) * output_weights[HIDDEN + i] for i, a in enumerate(them))
+    raw = (out + output_bias) * SCALE
+    # integer division truncating toward zero, like Rust
+    return abs(raw) // (QA * QB) * (1 if raw >= 0 else -1)
+
+
+for fen in [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1",
+]:
+    print(evaluate(fen), fen)
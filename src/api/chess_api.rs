@@ -5,6 +5,8 @@ use crate::chess::cmove::Move;
 use crate::chess::{piece, board};
 use crate::chess::position::Position;
 use crate::chess::{color::Color, move_api::MoveAPI, piece::Piece, fen};
+use crate::chess::board::Board;
+use crate::chess::variant::Outcome;
 
 #[allow(dead_code)]
 pub const WHITE: &str = "White";
@@ -81,6 +83,10 @@ impl ChessGame {
         self.move_api.get_turn_color()
     }
 
+    pub fn get_board(&self) -> Board {
+        *self.move_api.get_board_ref()
+    }
+
     pub fn get_piece(&self, position: Position) -> Option<Piece> {
         // todo move this logic to board
         let board = self.move_api
@@ -124,21 +130,11 @@ impl GameAPI for ChessGame {
     }
 
     fn get_outcome(&mut self) -> Message {
-        let is_checkmate = self.move_api.is_checkmate();
-        let is_stalemate = self.move_api.is_stalemate();
-
-        if is_checkmate {
-            return Message::Info(format!(
-                "{:?}",
-                self.move_api.get_turn_color().get_opposite()
-            ));
+        match self.move_api.get_outcome() {
+            Outcome::Win(color) => Message::Info(format!("{:?}", color)),
+            Outcome::Draw => Message::Info("Stalemate".to_string()),
+            Outcome::Ongoing => Message::Info("IsOngoing".to_string()),
         }
-
-        if is_stalemate {
-            return Message::Info("Stalemate".to_string());
-        }
-
-        Message::Info("IsOngoing".to_string())
     }
 
     fn get_legal_moves(&mut self) -> Vec<Move> {
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::Instant;
+
+use crate::chess::color::Color;
+use crate::chess::fen;
+use crate::chess::move_api::MoveAPI;
+use crate::chess::pgn::{self, PgnGame};
+use crate::chess::polyglot;
+use crate::chess::variant::Variant;
+use crate::game::book::{BookEntry, OpeningBook, DEFAULT_BOOK_DEPTH};
+
+/// Results of one move in one position, counted for the side that played it
+#[derive(Copy, Clone, Default)]
+struct MoveStats {
+    count: u32,
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+impl MoveStats {
+    fn add(&mut self, score: f64) {
+        self.count += 1;
+        if score == 1.0 {
+            self.wins += 1;
+        } else if score == 0.0 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+
+    /// Twice the points scored, the weighting Polyglot uses for its books
+    fn get_weight(&self) -> u32 {
+        2 * self.wins + self.draws
+    }
+}
+
+/// Filters on which games and moves make it into the book
+struct BuildOptions {
+    max_ply: usize,
+    min_games: u32,
+    min_rating: u32,
+}
+
+/// Usage: build-book <output file> <pgn file>... [--max-ply N] [--min-games N] [--min-rating N]
+///
+/// Replays the finished games of the PGN files up to `max-ply` half-moves
+/// (default 16), keeps the moves played in at least `min-games` games
+/// (default 1) and only reads games where both players are rated at least
+/// `min-rating` (default 0, unrated players count as 0).
+/// Moves are weighted by the points they scored, moves that only lost are left out
+pub fn run(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: build-book <output file> <pgn file>... [--max-ply N] [--min-games N] [--min-rating N]");
+        return;
+    }
+
+    let mut options = BuildOptions {
+        max_ply: DEFAULT_BOOK_DEPTH,
+        min_games: 1,
+        min_rating: 0,
+    };
+    let mut paths = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        if !arg.starts_with("--") {
+            paths.push(arg.clone());
+            continue;
+        }
+
+        let value = match rest.next().and_then(|v| v.parse::<u32>().ok()) {
+            Some(value) => value,
+            None => {
+                println!("{} needs a number", arg);
+                return;
+            }
+        };
+        match arg.as_str() {
+            "--max-ply" => options.max_ply = value as usize,
+            "--min-games" => options.min_games = value,
+            "--min-rating" => options.min_rating = value,
+            _ => {
+                println!("Unknown option {}", arg);
+                return;
+            }
+        }
+    }
+
+    if paths.is_empty() {
+        println!("No PGN files given");
+        return;
+    }
+
+    let now = Instant::now();
+    let mut stats: HashMap<(u64, u16), MoveStats> = HashMap::new();
+    let mut used = 0;
+    let mut skipped = 0;
+    let mut unreadable = 0;
+
+    for path in &paths {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                println!("Could not read {}: {}", path, e);
+                return;
+            }
+        };
+
+        for game in pgn::parse_games(&text) {
+            if !is_wanted(&game, &options) {
+                skipped += 1;
+                continue;
+            }
+            if add_game(&game, &options, &mut stats) {
+                used += 1;
+            } else {
+                unreadable += 1;
+            }
+        }
+    }
+    println!(
+        "Read {} games in {:.1}s, {} filtered out, {} that could not be read",
+        used + skipped + unreadable,
+        now.elapsed().as_secs_f64(),
+        skipped,
+        unreadable
+    );
+
+    print_stats(&stats);
+
+    let book = OpeningBook::from_entries(get_entries(&stats, options.min_games));
+    match book.save(&args[0]) {
+        Ok(()) => println!("Wrote {} entries to {}", book.len(), args[0]),
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+/// Finished standard games between players rated at least `min_rating`
+fn is_wanted(game: &PgnGame, options: &BuildOptions) -> bool {
+    if game.get_white_score().is_none() {
+        return false;
+    }
+
+    let is_standard = game
+        .get_tag("Variant")
+        .is_none_or(|variant| variant.eq_ignore_ascii_case("standard"));
+    if !is_standard {
+        return false;
+    }
+
+    let rating = |tag| game.get_tag(tag).and_then(|r| r.parse::<u32>().ok()).unwrap_or(0);
+    rating("WhiteElo").min(rating("BlackElo")) >= options.min_rating
+}
+
+/// Count the moves of the game's first `max_ply` plies,
+/// false if the starting position or a move could not be read
+fn add_game(game: &PgnGame, options: &BuildOptions, stats: &mut HashMap<(u64, u16), MoveStats>) -> bool {
+    let white_score = game.get_white_score().unwrap();
+    let start = game.get_tag("FEN").unwrap_or(fen::STARTING_BOARD);
+    if fen::validate(start, Variant::Standard).is_err() {
+        return false;
+    }
+    let mut move_api = MoveAPI::new(start);
+
+    for san in game.moves.iter().take(options.max_ply) {
+        let board = *move_api.get_board_ref();
+        let chess_move = match pgn::find_san_move(&mut move_api, san) {
+            Some(m) => m,
+            None => return false,
+        };
+
+        let score = match board.turn {
+            Color::White => white_score,
+            Color::Black => 1.0 - white_score,
+        };
+        let key = (polyglot::hash(&board), polyglot::encode_move(&board, &chess_move));
+        stats.entry(key).or_default().add(score);
+
+        move_api.exec_move(chess_move);
+    }
+    true
+}
+
+/// Book entries for the moves played at least `min_games` times,
+/// weights are scaled down together when they do not fit the format
+fn get_entries(stats: &HashMap<(u64, u16), MoveStats>, min_games: u32) -> Vec<BookEntry> {
+    let kept: Vec<(&(u64, u16), &MoveStats)> = stats
+        .iter()
+        .filter(|(_, s)| s.count >= min_games && s.get_weight() > 0)
+        .collect();
+
+    let max_weight = kept.iter().map(|(_, s)| s.get_weight()).max().unwrap_or(0);
+    let scale = max_weight.div_ceil(u16::MAX as u32).max(1);
+
+    kept.into_iter()
+        .map(|((key, chess_move), s)| BookEntry {
+            key: *key,
+            chess_move: *chess_move,
+            weight: (s.get_weight() / scale).max(1) as u16,
+            learn: 0,
+        })
+        .collect()
+}
+
+/// Statistics of the moves from the starting position
+fn print_stats(stats: &HashMap<(u64, u16), MoveStats>) {
+    let root = polyglot::hash(MoveAPI::new(fen::STARTING_BOARD).get_board_ref());
+    let mut moves: Vec<(u16, MoveStats)> = stats
+        .iter()
+        .filter(|((key, _), _)| *key == root)
+        .map(|((_, chess_move), s)| (*chess_move, *s))
+        .collect();
+    moves.sort_by_key(|(_, s)| std::cmp::Reverse(s.count));
+
+    println!("move   games   +   =   -  weight");
+    for (chess_move, s) in moves {
+        println!(
+            "{:6} {:5} {:3} {:3} {:3} {:7}",
+            polyglot::move_to_string(chess_move),
+            s.count,
+            s.wins,
+            s.draws,
+            s.losses,
+            s.get_weight()
+        );
+    }
+    println!("{} position-move pairs", stats.len());
+}
@@ -4,11 +4,19 @@ pub mod castle_utils;
 pub mod cmove;
 pub mod color;
 pub mod direction;
+pub mod dtm;
 pub mod fen;
 pub mod move_api;
 pub mod move_executor;
 pub mod move_generator;
 pub mod move_table;
+pub mod nnue;
+pub mod pgn;
 pub mod piece;
+pub mod polyglot;
 pub mod position;
+pub mod syzygy;
+pub mod unmove_generator;
+pub mod variant;
+pub mod zobrist;
 
@@ -5,7 +5,8 @@
 /// TODO: revisit dead code format specifiers
 use super::bitboard_util::{clear_bit, get_bit, mask, put_bit};
 use super::color::Color;
-use super::{fen, piece};
+use super::{fen, piece, zobrist};
+use super::variant::Variant;
 use super::piece::fen_to_piece;
 use super::piece::piece_to_fen;
 use super::piece::Piece;
@@ -73,13 +74,68 @@ pub struct Board {
     pub is_white_castle_long: bool,
     pub is_black_castle_short: bool,
     pub is_black_castle_long: bool,
+
+    /// Starting column of the castling rook for each castle right,
+    /// ordered white short, white long, black short, black long
+    pub castle_rook_cols: [i8; 4],
+
+    /// Castling moves are encoded as the king capturing its own rook
+    /// instead of the king moving two squares
+    pub is_chess960: bool,
+
+    pub variant: Variant,
+
+    /// Checks given by each side, only counted in variants that need them
+    pub white_checks: u8,
+    pub black_checks: u8,
+
+    /// Pieces each side holds in hand (Crazyhouse), indexed by Piece
+    pub white_pocket: [u8; 5],
+    pub black_pocket: [u8; 5],
+
+    /// Pieces promoted from pawns, captured they go back to the pocket as pawns
+    pub promoted: u64,
 }
 
+/// Pieces that can be held in a pocket
+pub const POCKET_PIECES: [Piece; 5] = [
+    Piece::Pawn,
+    Piece::Knight,
+    Piece::Bishop,
+    Piece::Rook,
+    Piece::Queen,
+];
+
+/// Index into Board::castle_rook_cols
+fn castle_index(castle_length: Castle, color: Color) -> usize {
+    match (castle_length, color) {
+        (Castle::Short, Color::White) => 0,
+        (Castle::Long, Color::White) => 1,
+        (Castle::Short, Color::Black) => 2,
+        (Castle::Long, Color::Black) => 3,
+    }
+}
+
+const CASTLES: [(Castle, Color); 4] = [
+    (Castle::Short, Color::White),
+    (Castle::Long, Color::White),
+    (Castle::Short, Color::Black),
+    (Castle::Long, Color::Black),
+];
+
 impl Board {
     ///
     ///
     ///
     pub fn new(fen_string: &str) -> Board {
+        Board::with_variant(fen_string, Variant::Standard)
+    }
+
+    /// Read a FEN for the given variant. Three-check counters may follow
+    /// the move clocks as `+N+M`, the checks given by White and by Black.
+    /// Crazyhouse pockets follow the placement in brackets, e.g. `[QNp]`,
+    /// and promoted pieces are marked with a `~` after their letter
+    pub fn with_variant(fen_string: &str, variant: Variant) -> Board {
         // todo: add error checks!
         let split: Vec<&str> = fen_string.split_whitespace().collect();
 
@@ -92,11 +148,19 @@ impl Board {
         let mut white_pieces: u64 = 0;
         let mut black_pieces: u64 = 0;
         let mut ep_target: u64 = 0;
+        let mut promoted: u64 = 0;
+
+        let (placement, pocket) = match split[0].find(fen::POCKET_START) {
+            Some(i) => (&split[0][..i], split[0][i + 1..].trim_end_matches(fen::POCKET_END)),
+            None => (split[0], ""),
+        };
 
         let mut row = 0;
         let mut col = 0;
-        for ch in split[0].chars() {
-            if ch == fen::ROW_END {
+        for ch in placement.chars() {
+            if ch == fen::PROMOTED {
+                promoted = put_bit(promoted, Position::new(row, col - 1).to_index());
+            } else if ch == fen::ROW_END {
                 row += 1;
                 col = 0;
             } else if ch.to_digit(10) != None {
@@ -127,6 +191,19 @@ impl Board {
             }
         }
 
+        let mut white_pocket = [0; 5];
+        let mut black_pocket = [0; 5];
+        for ch in pocket.chars() {
+            let (piece, color) = fen_to_piece(ch);
+            if piece == Piece::King {
+                continue;
+            }
+            match color {
+                Color::White => white_pocket[piece as usize] += 1,
+                Color::Black => black_pocket[piece as usize] += 1,
+            }
+        }
+
         let turn: Color;
         if split[1] == fen::B_TURN.to_string() {
             turn = Color::Black;
@@ -135,29 +212,84 @@ impl Board {
         }
 
         // Castling Arguments
-        let mut is_white_castle_short = false;
-        let mut is_white_castle_long = false;
-        let mut is_black_castle_short = false;
-        let mut is_black_castle_long = false;
+        // KQkq take the outermost rook on that side of the king (X-FEN),
+        // file letters name the rook's file directly (Shredder-FEN)
+        let mut castle_rights = [false; 4];
+        let mut castle_rook_cols: [i8; 4] = [7, 0, 7, 0];
         for ch in split[2].chars() {
-            match ch {
-                fen::W_CASTLE_SHORT => is_white_castle_short = true,
-                fen::W_CASTLE_LONG => is_white_castle_long = true,
-                fen::B_CASTLE_SHORT => is_black_castle_short = true,
-                fen::B_CASTLE_LONG => is_black_castle_long = true,
-                fen::NONE => break,
+            let color = if ch.is_ascii_uppercase() {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let back_rank = match color {
+                Color::White => 7,
+                Color::Black => 0,
+            };
+            let is_own_rook = |col: i8| {
+                let index = Position::new(back_rank, col).to_index();
+                get_bit(rooks & Board::get_color_bitboard(white_pieces, black_pieces, color), index) != 0
+            };
+            let king_col = Board::find_king_col(kings, white_pieces, black_pieces, color).unwrap_or(4);
+
+            let (castle, rook_col) = match ch {
+                fen::W_CASTLE_SHORT | fen::B_CASTLE_SHORT => {
+                    let col = (king_col + 1..8).rev().find(|c| is_own_rook(*c));
+                    (Castle::Short, col.unwrap_or(7))
+                }
+                fen::W_CASTLE_LONG | fen::B_CASTLE_LONG => {
+                    let col = (0..king_col).find(|c| is_own_rook(*c));
+                    (Castle::Long, col.unwrap_or(0))
+                }
+                'A'..='H' | 'a'..='h' => {
+                    let col = (ch.to_ascii_lowercase() as u8 - b'a') as i8;
+                    if col > king_col {
+                        (Castle::Short, col)
+                    } else {
+                        (Castle::Long, col)
+                    }
+                }
                 _ => break,
+            };
+
+            castle_rights[castle_index(castle, color)] = true;
+            castle_rook_cols[castle_index(castle, color)] = rook_col;
+        }
+        let [is_white_castle_short, is_white_castle_long, is_black_castle_short, is_black_castle_long] =
+            castle_rights;
+
+        // Any castle right away from the standard squares means Chess960
+        let mut is_chess960 = false;
+        for (castle, color) in CASTLES {
+            if !castle_rights[castle_index(castle, color)] {
+                continue;
             }
+            let king_col = Board::find_king_col(kings, white_pieces, black_pieces, color);
+            let standard_col = match castle {
+                Castle::Short => 7,
+                Castle::Long => 0,
+            };
+            is_chess960 |= king_col != Some(4) || castle_rook_cols[castle_index(castle, color)] != standard_col;
         }
 
         // En Passant Argument
         if split[3] != fen::NONE.to_string() {
-            let target_pos = position::algebraic_to_position(&split[3].to_string());
+            let target_pos = position::algebraic_to_position(split[3]);
             ep_target = mask(target_pos.to_index());
         }
 
-        let fullmove_clock = split[4].parse::<u16>().unwrap();
-        let halfmove_clock = split[5].parse::<u16>().unwrap();
+        let halfmove_clock = split[4].parse::<u16>().unwrap();
+        let fullmove_clock = split[5].parse::<u16>().unwrap();
+
+        let mut white_checks = 0;
+        let mut black_checks = 0;
+        for field in &split[6..] {
+            let counts: Vec<&str> = field.split('+').collect();
+            if let [_, white, black] = counts[..] {
+                white_checks = white.parse::<u8>().unwrap_or(0);
+                black_checks = black.parse::<u8>().unwrap_or(0);
+            }
+        }
 
         Board {
             pawns,
@@ -176,9 +308,34 @@ impl Board {
             is_white_castle_long,
             is_black_castle_short,
             is_black_castle_long,
+            castle_rook_cols,
+            is_chess960,
+            variant,
+            white_checks,
+            black_checks,
+            white_pocket,
+            black_pocket,
+            promoted,
         }
     }
 
+    fn get_color_bitboard(white_pieces: u64, black_pieces: u64, color: Color) -> u64 {
+        match color {
+            Color::White => white_pieces,
+            Color::Black => black_pieces,
+        }
+    }
+
+    /// Column of the king if it stands on its back rank
+    fn find_king_col(kings: u64, white_pieces: u64, black_pieces: u64, color: Color) -> Option<i8> {
+        let back_rank = match color {
+            Color::White => 7,
+            Color::Black => 0,
+        };
+        let king_board = kings & Board::get_color_bitboard(white_pieces, black_pieces, color);
+        (0..8).find(|col| get_bit(king_board, Position::new(back_rank, *col).to_index()) != 0)
+    }
+
     ///
     ///
     ///
@@ -248,8 +405,13 @@ impl Board {
     }
 
 
+    /// Value of the pieces of a type on the board and in the pocket
     pub fn get_piece_score(&self, piece: Piece, color: Color) -> f64 {
-        self.get_color_piece_board(piece, color).count_ones() as f64 * piece::get_piece_value(piece)
+        let in_pocket = match piece {
+            Piece::King => 0,
+            _ => self.get_pocket(piece, color) as u32,
+        };
+        (self.get_color_piece_board(piece, color).count_ones() + in_pocket) as f64 * piece::get_piece_value(piece)
     }
 
     pub fn get_material_score(&self, color: Color) -> f64 {
@@ -275,6 +437,15 @@ impl Board {
         }
     }
 
+    /// Square the castling rook starts from
+    pub fn get_castle_rook(&self, castle_length: Castle, color: Color) -> usize {
+        let back_rank = match color {
+            Color::White => 7,
+            Color::Black => 0,
+        };
+        Position::new(back_rank, self.castle_rook_cols[castle_index(castle_length, color)]).to_index()
+    }
+
     ///
     ///
     ///
@@ -287,6 +458,55 @@ impl Board {
         }
     }
 
+    pub fn get_checks(&self, color: Color) -> u8 {
+        match color {
+            Color::White => self.white_checks,
+            Color::Black => self.black_checks,
+        }
+    }
+
+    pub fn get_pocket(&self, piece: Piece, color: Color) -> u8 {
+        match color {
+            Color::White => self.white_pocket[piece as usize],
+            Color::Black => self.black_pocket[piece as usize],
+        }
+    }
+
+    pub fn add_to_pocket(&mut self, piece: Piece, color: Color) {
+        match color {
+            Color::White => self.white_pocket[piece as usize] += 1,
+            Color::Black => self.black_pocket[piece as usize] += 1,
+        }
+    }
+
+    pub fn remove_from_pocket(&mut self, piece: Piece, color: Color) {
+        match color {
+            Color::White => self.white_pocket[piece as usize] -= 1,
+            Color::Black => self.black_pocket[piece as usize] -= 1,
+        }
+    }
+
+    pub fn add_check(&mut self, color: Color) {
+        match color {
+            Color::White => self.white_checks += 1,
+            Color::Black => self.black_checks += 1,
+        }
+    }
+
+    /// Zobrist hash of the position
+    pub fn get_hash(&self) -> u64 {
+        zobrist::hash(self)
+    }
+
+    /// Half-moves played since the game's first move, from the fullmove number
+    pub fn get_ply(&self) -> usize {
+        let moves_before = self.fullmove_clock.max(1) as usize - 1;
+        match self.turn {
+            Color::White => 2 * moves_before,
+            Color::Black => 2 * moves_before + 1,
+        }
+    }
+
     #[allow(dead_code)]
     ///
     ///
@@ -302,6 +522,9 @@ impl Board {
                         out.push((count + b'0') as char);
                     }
                     out.push(square_array[row][col]);
+                    if self.variant.has_drops() && get_bit(self.promoted, row * 8 + col) != 0 {
+                        out.push(fen::PROMOTED);
+                    }
                     count = 0;
                 } else {
                     count += 1;
@@ -313,6 +536,18 @@ impl Board {
             out.push(fen::ROW_END);
         }
         out.pop(); // remove trailing row separator
+
+        if self.variant.has_drops() {
+            out.push(fen::POCKET_START);
+            for color in [Color::White, Color::Black] {
+                for piece in POCKET_PIECES.iter().rev() {
+                    for _ in 0..self.get_pocket(*piece, color) {
+                        out.push(piece_to_fen(*piece, color));
+                    }
+                }
+            }
+            out.push(fen::POCKET_END);
+        }
         out.push(EMPTY);
 
         let turn = self.turn;
@@ -323,25 +558,40 @@ impl Board {
         }
 
         out.push(EMPTY);
-        let castle_bools = [
-            self.is_white_castle_short,
-            self.is_white_castle_long,
-            self.is_black_castle_short,
-            self.is_black_castle_long,
-        ];
-        let castle_chars = vec![
-            fen::W_CASTLE_SHORT,
-            fen::W_CASTLE_LONG,
-            fen::B_CASTLE_SHORT,
-            fen::B_CASTLE_LONG,
-        ];
-
+        // X-FEN: KQkq unless another rook stands further out on the same side
         let mut can_castle = false;
-        for i in 0..4 {
-            if castle_bools[i] {
-                can_castle = true;
-                out.push(castle_chars[i]);
+        for (castle, color) in CASTLES {
+            if !self.get_castle(castle, color) {
+                continue;
             }
+            can_castle = true;
+
+            let rook_col = self.castle_rook_cols[castle_index(castle, color)];
+            let back_rank = self.get_castle_rook(castle, color) - rook_col as usize;
+            let outer_cols = match castle {
+                Castle::Short => rook_col + 1..8,
+                Castle::Long => 0..rook_col,
+            };
+            let rooks = self.get_color_piece_board(Piece::Rook, color);
+            let is_outermost = outer_cols
+                .into_iter()
+                .all(|col| get_bit(rooks, back_rank + col as usize) == 0);
+
+            let ch = if is_outermost {
+                match (castle, color) {
+                    (Castle::Short, Color::White) => fen::W_CASTLE_SHORT,
+                    (Castle::Long, Color::White) => fen::W_CASTLE_LONG,
+                    (Castle::Short, Color::Black) => fen::B_CASTLE_SHORT,
+                    (Castle::Long, Color::Black) => fen::B_CASTLE_LONG,
+                }
+            } else {
+                let file = (b'a' + rook_col as u8) as char;
+                match color {
+                    Color::White => file.to_ascii_uppercase(),
+                    Color::Black => file,
+                }
+            };
+            out.push(ch);
         }
         if !can_castle {
             out.push(fen::NONE);
@@ -357,13 +607,17 @@ impl Board {
 
         out.push(EMPTY);
 
-        let fm: String = self.fullmove_clock.to_string();
-        out.push_str(&fm);
+        let hm: String = self.halfmove_clock.to_string();
+        out.push_str(&hm);
 
         out.push(EMPTY);
 
-        let hm: String = self.halfmove_clock.to_string();
-        out.push_str(&hm);
+        let fm: String = self.fullmove_clock.to_string();
+        out.push_str(&fm);
+
+        if self.variant.counts_checks() {
+            out += &format!(" +{}+{}", self.white_checks, self.black_checks);
+        }
         out
     }
 
@@ -1,3 +1,4 @@
+use super::color::Color;
 use super::piece::{Piece, self};
 use super::position::Position;
 
@@ -6,15 +7,16 @@ use super::position::Position;
 /// Start and End are represented with a Position class
 /// piece: the moving piece
 /// promotion: optionally the moving piece's new type
+/// is_drop: the piece comes from the pocket (Crazyhouse),
+/// a drop has no start square so start repeats end
 pub struct Move {
     pub start: Position,
     pub end: Position,
     pub piece: Piece, // redundant but useful
     pub promotion: Option<Piece>,
+    pub is_drop: bool,
 }
 
-/// Possible promotion types
-const PROMOTION_PIECES: [Piece; 4] = [Piece::Queen, Piece::Knight, Piece::Rook, Piece::Bishop];
 
 
 impl Move {
@@ -25,22 +27,64 @@ impl Move {
             end,
             promotion,
             piece,
+            is_drop: false,
         }
     }
 
-    /// Return a Vec of 4 moves with given start and end positions
+    /// Drop a piece from the pocket onto `end`
+    pub fn drop(piece: Piece, end: Position) -> Move {
+        Move {
+            start: end,
+            end,
+            promotion: None,
+            piece,
+            is_drop: true,
+        }
+    }
+
+    /// Return a Vec of moves with given start and end positions
     /// (One for each promotion type)
-    pub fn make_promotions(start: Position, end: Position) -> Vec<Move> {
+    pub fn make_promotions(start: Position, end: Position, pieces: &[Piece]) -> Vec<Move> {
         let mut out = Vec::new();
-        for piece in PROMOTION_PIECES {
-            out.push(Move::new(start, end, Piece::Pawn, Some(piece)));
+        for piece in pieces {
+            out.push(Move::new(start, end, Piece::Pawn, Some(*piece)));
         }
 
         out
     }
 
+    /// Square the moving piece lands on for a move by `color`.
+    /// En passant moves end on the captured pawn, next to the start square
+    pub fn get_landing(&self, color: Color) -> Position {
+        if self.piece != Piece::Pawn || self.is_drop || self.start.get_row() != self.end.get_row() {
+            return self.end;
+        }
+        let forward = match color {
+            Color::White => -1,
+            Color::Black => 1,
+        };
+        Position::new(self.end.get_row() + forward, self.end.get_col())
+    }
+
+    /// Coordinate notation of a move by `color`, as UCI and xboard
+    /// write it: e2e4, e7e8q, P@e4 for drops
+    pub fn get_coordinates(&self, color: Color) -> String {
+        if self.is_drop {
+            return format!("{}@{}", piece::piece_to_fen(self.piece, Color::White), self.end.to_string());
+        }
+        let promotion = self
+            .promotion
+            .map(|p| piece::piece_to_fen(p, Color::Black).to_string())
+            .unwrap_or_default();
+        format!("{}{}{}", self.start.to_string(), self.get_landing(color).to_string(), promotion)
+    }
+
     #[allow(dead_code)]
     pub fn to_string(&self) -> String {
+        if self.is_drop {
+            let letter = piece::piece_to_fen(self.piece, Color::White);
+            return format!("{}@{}", letter, self.end.to_string());
+        }
         return format!("{:?}", self.piece)
             + ":"
             + &self.start.to_string()
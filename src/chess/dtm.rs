@@ -0,0 +1,559 @@
+//! Distance-to-mate tables the project generates itself.
+//!
+//! A table covers one material signature like KQvKR with White holding the
+//! stronger side, boards where Black holds it are probed with the colors
+//! swapped. Every position takes one byte: 0 is a draw, 255 a position that
+//! is illegal or stored under a symmetric image, anything else is the
+//! distance to mate in plies plus one. Odd distances are wins for the side
+//! to move, even ones losses, 0 being checkmated.
+//!
+//! Symmetry keeps the tables small: without pawns the white king stands in
+//! the a1-d1-d4 triangle, with pawns on files a to d. Of the symmetric
+//! images of a position the one with the lowest index is stored.
+//! Castling, en passant and the fifty move rule are left out.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use super::bitboard_util::{clear_bit, mask};
+use super::board::{Board, Castle};
+use super::cmove::Move;
+use super::color::Color;
+use super::move_api::MoveAPI;
+use super::piece::{piece_to_fen, Piece};
+use super::variant::Variant;
+
+/// Directory the server reads tables from when a request does not name one
+pub const DEFAULT_DTM_DIR: &str = "dtm";
+
+pub const DTM_EXTENSION: &str = "dtm";
+
+const MAGIC: [u8; 4] = *b"TDTM";
+const VERSION: u8 = 1;
+
+pub const DRAW: u8 = 0;
+pub const ILLEGAL: u8 = 255;
+
+/// Longest distance a byte holds
+pub const MAX_PLIES: usize = 253;
+
+/// Pieces besides the kings a table may hold
+const MAX_PIECES: usize = 2;
+
+/// Order of the pieces in a signature name
+const PIECE_ORDER: [Piece; 5] = [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight, Piece::Pawn];
+
+const EMPTY_FEN: &str = "8/8/8/8/8/8/8/8 w - - 0 1";
+
+/// Squares count from a1 = 0 to h8 = 63, bitboards from a8
+fn to_bitboard_index(square: usize) -> usize {
+    square ^ 56
+}
+
+fn file_of(square: usize) -> usize {
+    square & 7
+}
+
+fn rank_of(square: usize) -> usize {
+    square >> 3
+}
+
+fn is_adjacent(a: usize, b: usize) -> bool {
+    file_of(a).abs_diff(file_of(b)) <= 1 && rank_of(a).abs_diff(rank_of(b)) <= 1
+}
+
+/// A symmetry of the board: mirror files, mirror ranks, swap files and ranks
+#[derive(Copy, Clone)]
+struct Transform {
+    flip_file: bool,
+    flip_rank: bool,
+    transpose: bool,
+}
+
+impl Transform {
+    fn apply(&self, square: usize) -> usize {
+        let mut out = square;
+        if self.flip_file {
+            out ^= 7;
+        }
+        if self.flip_rank {
+            out ^= 56;
+        }
+        if self.transpose {
+            out = (file_of(out) << 3) | rank_of(out);
+        }
+        out
+    }
+}
+
+/// Slots of the white king and the square of each slot
+struct KingSlots {
+    slots: [Option<usize>; 64],
+    squares: Vec<usize>,
+}
+
+impl KingSlots {
+    /// The a1-d1-d4 triangle, or files a to d with pawns
+    fn new(has_pawns: bool) -> KingSlots {
+        let mut slots = [None; 64];
+        let mut squares = Vec::new();
+        for (square, slot) in slots.iter_mut().enumerate() {
+            let is_slot = if has_pawns {
+                file_of(square) <= 3
+            } else {
+                file_of(square) <= 3 && rank_of(square) <= file_of(square)
+            };
+            if is_slot {
+                *slot = Some(squares.len());
+                squares.push(square);
+            }
+        }
+        KingSlots { slots, squares }
+    }
+}
+
+fn king_slots(has_pawns: bool) -> &'static KingSlots {
+    static PAWNLESS: OnceLock<KingSlots> = OnceLock::new();
+    static PAWNS: OnceLock<KingSlots> = OnceLock::new();
+    let slots = if has_pawns { &PAWNS } else { &PAWNLESS };
+    slots.get_or_init(|| KingSlots::new(has_pawns))
+}
+
+fn empty_board() -> Board {
+    static EMPTY: OnceLock<Board> = OnceLock::new();
+    *EMPTY.get_or_init(|| Board::new(EMPTY_FEN))
+}
+
+/// Squares of a piece type, ascending
+fn get_squares(board: &Board, piece: Piece, color: Color) -> Vec<usize> {
+    let mut out = Vec::new();
+    let mut bitboard = board.get_color_piece_board(piece, color);
+    while bitboard != 0 {
+        let index = bitboard.leading_zeros() as usize;
+        bitboard = clear_bit(bitboard, index);
+        out.push(to_bitboard_index(index));
+    }
+    out.sort_unstable();
+    out
+}
+
+/// The same position with the colors swapped and the board mirrored
+/// top to bottom, so that pawns keep running the right way
+pub fn flip_colors(board: &Board) -> Board {
+    let mut out = *board;
+    for piece in [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King] {
+        out.set_piece_board(piece, board.get_piece_board(piece).swap_bytes());
+    }
+    out.white_pieces = board.black_pieces.swap_bytes();
+    out.black_pieces = board.white_pieces.swap_bytes();
+    out.ep_target = board.ep_target.swap_bytes();
+    out.turn = board.turn.get_opposite();
+    out
+}
+
+/// The pieces besides the kings, White's first, in name order
+#[derive(Clone, PartialEq)]
+pub struct Signature {
+    pieces: Vec<(Piece, Color)>,
+}
+
+impl Signature {
+    /// Read a name like KQvKR, sides may come in either order
+    pub fn parse(name: &str) -> Result<Signature, String> {
+        let sides: Vec<&str> = name.split('v').collect();
+        if sides.len() != 2 || sides.iter().any(|side| !side.starts_with('K')) {
+            return Err(format!("{} is not a signature like KQvKR", name));
+        }
+
+        let mut pieces = Vec::new();
+        for (side, color) in sides.iter().zip([Color::White, Color::Black]) {
+            for letter in side[1..].chars() {
+                let piece = PIECE_ORDER
+                    .iter()
+                    .find(|piece| piece_to_fen(**piece, Color::White) == letter)
+                    .ok_or(format!("{} is not a piece of {}", letter, name))?;
+                pieces.push((*piece, color));
+            }
+        }
+
+        let signature = Signature::from_pieces(pieces);
+        if signature.pieces.len() > MAX_PIECES {
+            return Err(format!("{} has more than {} men", name, MAX_PIECES + 2));
+        }
+        if signature.count(Piece::Pawn, Color::White) > 0 && signature.count(Piece::Pawn, Color::Black) > 0 {
+            return Err(format!("{} has pawns on both sides, en passant is not supported", name));
+        }
+        Ok(signature)
+    }
+
+    pub fn from_board(board: &Board) -> Signature {
+        let mut pieces = Vec::new();
+        for color in [Color::White, Color::Black] {
+            for piece in PIECE_ORDER {
+                let count = board.get_color_piece_board(piece, color).count_ones();
+                pieces.extend((0..count).map(|_| (piece, color)));
+            }
+        }
+        Signature { pieces }
+    }
+
+    fn from_pieces(mut pieces: Vec<(Piece, Color)>) -> Signature {
+        let get_order = |piece: Piece| PIECE_ORDER.iter().position(|p| *p == piece).unwrap();
+        pieces.sort_by_key(|(piece, color)| (*color != Color::White, get_order(*piece)));
+        Signature { pieces }
+    }
+
+    pub fn get_name(&self) -> String {
+        let mut out = String::new();
+        for color in [Color::White, Color::Black] {
+            out.push('K');
+            for (piece, _) in self.pieces.iter().filter(|(_, c)| *c == color) {
+                out.push(piece_to_fen(*piece, Color::White));
+            }
+            if color == Color::White {
+                out.push('v');
+            }
+        }
+        out
+    }
+
+    fn count(&self, piece: Piece, color: Color) -> usize {
+        self.pieces.iter().filter(|p| **p == (piece, color)).count()
+    }
+
+    /// Piece values of a side, then its piece count, decide which side is stronger
+    fn get_strength(&self, color: Color) -> (u32, usize) {
+        let get_value = |piece: Piece| match piece {
+            Piece::Queen => 9,
+            Piece::Rook => 5,
+            Piece::Bishop | Piece::Knight => 3,
+            _ => 1,
+        };
+        let pieces = self.pieces.iter().filter(|(_, c)| *c == color);
+        (pieces.clone().map(|(piece, _)| get_value(*piece)).sum(), pieces.count())
+    }
+
+    /// Whether Black holds the stronger side, such a signature
+    /// is stored with the colors swapped
+    pub fn is_flipped(&self) -> bool {
+        self.get_strength(Color::Black) > self.get_strength(Color::White)
+    }
+
+    /// The signature with the colors swapped
+    pub fn flip(&self) -> Signature {
+        let pieces = self.pieces.iter().map(|(piece, color)| (*piece, color.get_opposite())).collect();
+        Signature::from_pieces(pieces)
+    }
+
+    /// The signature the table is stored under
+    pub fn normalize(&self) -> Signature {
+        if self.is_flipped() { self.flip() } else { self.clone() }
+    }
+
+    pub fn has_pawns(&self) -> bool {
+        self.pieces.iter().any(|(piece, _)| *piece == Piece::Pawn)
+    }
+
+    /// Signatures a capture or a promotion leads to, stored
+    /// under their own names, without the bare kings
+    pub fn get_successors(&self) -> Vec<Signature> {
+        let mut out: Vec<Signature> = Vec::new();
+        for (i, (piece, color)) in self.pieces.iter().enumerate() {
+            let mut captured = self.pieces.clone();
+            captured.remove(i);
+            out.push(Signature::from_pieces(captured));
+
+            if *piece == Piece::Pawn {
+                for promotion in [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight] {
+                    let mut promoted = self.pieces.clone();
+                    promoted[i] = (promotion, *color);
+                    out.push(Signature::from_pieces(promoted));
+                }
+            }
+        }
+
+        let mut normalized: Vec<Signature> = Vec::new();
+        for signature in out.iter().filter(|s| !s.pieces.is_empty()).map(Signature::normalize) {
+            if !normalized.contains(&signature) {
+                normalized.push(signature);
+            }
+        }
+        normalized
+    }
+
+    fn get_transforms(&self) -> Vec<Transform> {
+        let mut out = Vec::new();
+        for flip_file in [false, true] {
+            for flip_rank in [false, true] {
+                for transpose in [false, true] {
+                    // pawns only keep their meaning mirrored left to right
+                    if self.has_pawns() && (flip_rank || transpose) {
+                        continue;
+                    }
+                    out.push(Transform { flip_file, flip_rank, transpose });
+                }
+            }
+        }
+        out
+    }
+
+    /// Entries of the table
+    pub fn get_size(&self) -> usize {
+        2 * king_slots(self.has_pawns()).squares.len() * 64usize.pow(self.pieces.len() as u32 + 1)
+    }
+
+    /// Squares of the white king, the black king and the other pieces in order
+    fn get_board_squares(&self, board: &Board) -> Vec<usize> {
+        let mut out = vec![
+            get_squares(board, Piece::King, Color::White)[0],
+            get_squares(board, Piece::King, Color::Black)[0],
+        ];
+        for (i, (piece, color)) in self.pieces.iter().enumerate() {
+            // pieces of one type come together, take all of them with the first
+            if i == 0 || self.pieces[i - 1] != (*piece, *color) {
+                out.extend(get_squares(board, *piece, *color));
+            }
+        }
+        out
+    }
+
+    /// Index of a board of this signature, the lowest of its symmetric images
+    pub fn get_index(&self, board: &Board) -> usize {
+        let squares = self.get_board_squares(board);
+        let slots = king_slots(self.has_pawns());
+        let side = (board.turn == Color::Black) as usize;
+
+        let mut best = usize::MAX;
+        for transform in self.get_transforms() {
+            let mut image: Vec<usize> = squares.iter().map(|s| transform.apply(*s)).collect();
+            let slot = match slots.slots[image[0]] {
+                Some(slot) => slot,
+                None => continue,
+            };
+            // identical pieces are stored in ascending order
+            if image.len() == 4 && self.pieces[0] == self.pieces[1] && image[2] > image[3] {
+                image.swap(2, 3);
+            }
+
+            let mut index = side * slots.squares.len() + slot;
+            for square in &image[1..] {
+                index = index * 64 + square;
+            }
+            best = best.min(index);
+        }
+        best
+    }
+
+    /// The board of an index, None when it is illegal or not the stored
+    /// image of its position. Checks are not looked at
+    pub fn get_board(&self, index: usize) -> Option<Board> {
+        let slots = king_slots(self.has_pawns());
+        let mut rest = index;
+        let mut squares = Vec::new();
+        for _ in 0..self.pieces.len() + 1 {
+            squares.push(rest % 64);
+            rest /= 64;
+        }
+        squares.push(slots.squares[rest % slots.squares.len()]);
+        squares.reverse();
+        let is_black = rest / slots.squares.len() == 1;
+
+        for (i, square) in squares.iter().enumerate() {
+            if squares[..i].contains(square) {
+                return None;
+            }
+        }
+        if is_adjacent(squares[0], squares[1]) {
+            return None;
+        }
+
+        let mut board = empty_board();
+        board.turn = if is_black { Color::Black } else { Color::White };
+        let kings = [(Piece::King, Color::White), (Piece::King, Color::Black)];
+        for ((piece, color), square) in kings.iter().chain(self.pieces.iter()).zip(squares) {
+            if *piece == Piece::Pawn && (rank_of(square) == 0 || rank_of(square) == 7) {
+                return None;
+            }
+            let bit = mask(to_bitboard_index(square));
+            board.set_piece_board(*piece, board.get_piece_board(*piece) | bit);
+            board.set_color_board(*color, board.get_pieces(*color) | bit);
+        }
+
+        if self.get_index(&board) != index {
+            return None;
+        }
+        Some(board)
+    }
+}
+
+/// Result of a position for the side to move, distances in plies
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Dtm {
+    Win(u32),
+    Draw,
+    Loss(u32),
+}
+
+impl Dtm {
+    fn from_value(value: u8) -> Option<Dtm> {
+        match value {
+            ILLEGAL => None,
+            DRAW => Some(Dtm::Draw),
+            _ if value.is_multiple_of(2) => Some(Dtm::Win(value as u32 - 1)),
+            _ => Some(Dtm::Loss(value as u32 - 1)),
+        }
+    }
+
+    /// The result one ply earlier, for the side that moved into this position
+    pub fn before(self) -> Dtm {
+        match self {
+            Dtm::Win(plies) => Dtm::Loss(plies + 1),
+            Dtm::Draw => Dtm::Draw,
+            Dtm::Loss(plies) => Dtm::Win(plies + 1),
+        }
+    }
+
+    pub fn get_description(self) -> String {
+        match self {
+            Dtm::Win(plies) => format!("mate in {} ({} plies)", plies.div_ceil(2), plies),
+            Dtm::Draw => String::from("draw"),
+            Dtm::Loss(0) => String::from("checkmated"),
+            Dtm::Loss(plies) => format!("mated in {} ({} plies)", plies / 2, plies),
+        }
+    }
+}
+
+/// Distance to mate of every position of one signature
+pub struct DtmTable {
+    signature: Signature,
+    values: Vec<u8>,
+}
+
+impl DtmTable {
+    /// `values` holds one byte per index of `signature`
+    pub fn new(signature: Signature, values: Vec<u8>) -> DtmTable {
+        DtmTable { signature, values }
+    }
+
+    pub fn load(path: &Path) -> Result<DtmTable, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        if bytes.len() < 6 || bytes[..4] != MAGIC || bytes[4] != VERSION {
+            return Err(format!("{} is not a table of this version", path.display()));
+        }
+
+        let name_end = 6 + bytes[5] as usize;
+        let name = bytes.get(6..name_end).map(String::from_utf8_lossy).unwrap_or_default();
+        let signature = Signature::parse(&name)?;
+        let values = bytes[name_end..].to_vec();
+        if values.len() != signature.get_size() {
+            return Err(format!("{} has {} entries instead of {}", path.display(), values.len(), signature.get_size()));
+        }
+        Ok(DtmTable::new(signature, values))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let name = self.signature.get_name();
+        let mut bytes = Vec::with_capacity(6 + name.len() + self.values.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&self.values);
+        fs::write(path, bytes).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    pub fn get_signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Result of a board of the table's own signature
+    pub fn probe_board(&self, board: &Board) -> Option<Dtm> {
+        Dtm::from_value(self.values[self.signature.get_index(board)])
+    }
+
+    /// Results of all stored positions
+    pub fn get_results(&self) -> impl Iterator<Item = (usize, Dtm)> + '_ {
+        self.values.iter().enumerate().filter_map(|(index, value)| Some((index, Dtm::from_value(*value)?)))
+    }
+}
+
+/// Tables by signature name
+pub struct DtmTablebases {
+    tables: HashMap<String, DtmTable>,
+}
+
+impl DtmTablebases {
+    pub fn new() -> DtmTablebases {
+        DtmTablebases { tables: HashMap::new() }
+    }
+
+    /// Load every table of a directory, a missing directory gives no tables
+    pub fn open(dir: &str) -> DtmTablebases {
+        let mut out = DtmTablebases::new();
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some(DTM_EXTENSION) {
+                    continue;
+                }
+                match DtmTable::load(&path) {
+                    Ok(table) => out.insert(table),
+                    Err(msg) => eprintln!("{}", msg),
+                }
+            }
+        }
+        out
+    }
+
+    pub fn insert(&mut self, table: DtmTable) {
+        self.tables.insert(table.signature.get_name(), table);
+    }
+
+    pub fn get(&self, signature: &Signature) -> Option<&DtmTable> {
+        self.tables.get(&signature.get_name())
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// Result of a board for the side to move. Bare kings are a draw,
+    /// boards with castling rights or without a table give None
+    pub fn probe(&self, board: &Board) -> Option<Dtm> {
+        let castles = [
+            (Castle::Short, Color::White),
+            (Castle::Long, Color::White),
+            (Castle::Short, Color::Black),
+            (Castle::Long, Color::Black),
+        ];
+        if board.variant != Variant::Standard || castles.iter().any(|(c, color)| board.get_castle(*c, *color)) {
+            return None;
+        }
+
+        let signature = Signature::from_board(board);
+        if signature.pieces.is_empty() {
+            return Some(Dtm::Draw);
+        }
+        if signature.is_flipped() {
+            let table = self.get(&signature.flip())?;
+            return table.probe_board(&flip_colors(board));
+        }
+        self.get(&signature)?.probe_board(board)
+    }
+
+    /// Result of every legal root move for the side making it
+    pub fn probe_root(&self, move_api: &mut MoveAPI) -> Option<Vec<(Move, Dtm)>> {
+        self.probe(move_api.get_board_ref())?;
+
+        let mut out = Vec::new();
+        for m in move_api.get_legal_moves() {
+            move_api.exec_move(m);
+            let result = self.probe(move_api.get_board_ref());
+            move_api.undo_move();
+            out.push((m, result?.before()));
+        }
+        Some(out)
+    }
+}
@@ -1,3 +1,7 @@
+use rand::Rng;
+
+use super::{color::Color, variant::Variant};
+
 /// White Pieces
 pub const W_PAWN: char = 'P';
 pub const W_KNIGHT: char = 'N';
@@ -27,8 +31,190 @@ pub const W_CASTLE_SHORT: char = 'K';
 pub const B_CASTLE_LONG: char = 'q';
 pub const B_CASTLE_SHORT: char = 'k';
 
+/// Crazyhouse pocket delimiters and promoted piece marker
+pub const POCKET_START: char = '[';
+pub const POCKET_END: char = ']';
+pub const PROMOTED: char = '~';
+
+/// Every piece letter, White's then Black's
+const PIECES: &str = "PNBRQKpnbrqk";
+
 /// Indicator for Missing Fields
 pub const NONE: char = '-';
 
 /// Fen String for the Starting Board
 pub const STARTING_BOARD: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// Starting board with the Three-check counters
+pub const THREE_CHECK_STARTING_BOARD: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 +0+0";
+
+/// Crazyhouse starting board, both pockets empty
+pub const CRAZYHOUSE_STARTING_BOARD: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1";
+
+/// Antichess starting board, there is no castling
+pub const ANTICHESS_STARTING_BOARD: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1";
+
+/// Horde starting board, White's 36 pawns have no king
+pub const HORDE_STARTING_BOARD: &str = "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1";
+
+/// Racing Kings starting board
+pub const RACING_KINGS_STARTING_BOARD: &str = "8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1";
+
+/// Knight placements among the five squares left after
+/// the bishops and queen, indexed as in Scharnagl's numbering
+const CHESS960_KNIGHTS: [(usize, usize); 10] = [
+    (0, 1), (0, 2), (0, 3), (0, 4), (1, 2),
+    (1, 3), (1, 4), (2, 3), (2, 4), (3, 4),
+];
+
+/// Why `fen` cannot be read as a position of `variant`, if it cannot.
+/// Checks every field `Board::with_variant` relies on, and that every
+/// side with a royal king has exactly one
+pub fn validate(fen: &str, variant: Variant) -> Result<(), String> {
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    if fields.len() < 6 {
+        return Err(format!("{} fields instead of at least 6", fields.len()));
+    }
+
+    let (placement, pocket) = match fields[0].find(POCKET_START) {
+        Some(i) => match fields[0][i + 1..].strip_suffix(POCKET_END) {
+            Some(pocket) => (&fields[0][..i], pocket),
+            None => return Err(String::from("pocket is not closed")),
+        },
+        None => (fields[0], ""),
+    };
+
+    let rows: Vec<&str> = placement.split(ROW_END).collect();
+    if rows.len() != 8 {
+        return Err(format!("{} rows instead of 8", rows.len()));
+    }
+    let mut king_counts = [0; 2];
+    for (row, text) in rows.iter().enumerate() {
+        let mut col = 0;
+        let mut is_after_piece = false;
+        for ch in text.chars() {
+            if ch == PROMOTED && is_after_piece {
+                is_after_piece = false;
+                continue;
+            }
+            match ch.to_digit(10) {
+                Some(empty @ 1..=8) => {
+                    col += empty;
+                    is_after_piece = false;
+                }
+                _ if PIECES.contains(ch) => {
+                    // White's Horde pawns may stand on the first rank
+                    let is_back_rank_pawn = match ch {
+                        W_PAWN => row == 0 || (row == 7 && variant != Variant::Horde),
+                        B_PAWN => row == 0 || row == 7,
+                        _ => false,
+                    };
+                    if is_back_rank_pawn {
+                        return Err(String::from("pawn on the first or last rank"));
+                    }
+                    match ch {
+                        W_KING => king_counts[0] += 1,
+                        B_KING => king_counts[1] += 1,
+                        _ => {}
+                    }
+                    col += 1;
+                    is_after_piece = true;
+                }
+                _ => return Err(format!("unexpected {} in row {}", ch, row + 1)),
+            }
+        }
+        if col != 8 {
+            return Err(format!("row {} has {} squares instead of 8", row + 1, col));
+        }
+    }
+    if let Some(ch) = pocket.chars().find(|ch| !PIECES.contains(*ch) || *ch == W_KING || *ch == B_KING) {
+        return Err(format!("unexpected {} in pocket", ch));
+    }
+
+    for (color, count) in [(Color::White, king_counts[0]), (Color::Black, king_counts[1])] {
+        if variant.has_royal_king(color) && count != 1 {
+            return Err(format!("{} kings for {:?} instead of 1", count, color));
+        }
+    }
+
+    if fields[1] != W_TURN.to_string() && fields[1] != B_TURN.to_string() {
+        return Err(format!("side to move {} is not w or b", fields[1]));
+    }
+    let is_castle_right = |ch: char| matches!(ch, W_CASTLE_SHORT | W_CASTLE_LONG | B_CASTLE_SHORT | B_CASTLE_LONG | 'A'..='H' | 'a'..='h');
+    if fields[2] != NONE.to_string() && !fields[2].chars().all(is_castle_right) {
+        return Err(format!("bad castling rights {}", fields[2]));
+    }
+    let ep: Vec<char> = fields[3].chars().collect();
+    let is_ep_square = matches!(ep[..], ['a'..='h', '3' | '6']);
+    if fields[3] != NONE.to_string() && !is_ep_square {
+        return Err(format!("bad en passant square {}", fields[3]));
+    }
+    if fields[4].parse::<u16>().is_err() || fields[5].parse::<u16>().is_err() {
+        return Err(format!("bad move clocks {} {}", fields[4], fields[5]));
+    }
+    Ok(())
+}
+
+/// Number of Chess960 starting positions
+pub const CHESS960_POSITIONS: usize = 960;
+
+/// X-FEN of a Chess960 starting position picked at random
+pub fn random_chess960_start_position() -> String {
+    chess960_start_position(rand::thread_rng().gen_range(0..CHESS960_POSITIONS))
+}
+
+/// X-FEN of Chess960 starting position `index` (0..960),
+/// numbered as in Scharnagl's scheme, 518 is the standard array
+pub fn chess960_start_position(index: usize) -> String {
+    let mut rank: [Option<char>; 8] = [None; 8];
+    let mut n = index % CHESS960_POSITIONS;
+
+    // light squared bishop on b, d, f or h, dark squared one on a, c, e or g
+    rank[2 * (n % 4) + 1] = Some(B_BISHOP);
+    n /= 4;
+    rank[2 * (n % 4)] = Some(B_BISHOP);
+    n /= 4;
+
+    let empty = |rank: &[Option<char>; 8]| -> Vec<usize> {
+        (0..8).filter(|i| rank[*i].is_none()).collect()
+    };
+
+    let queen = empty(&rank)[n % 6];
+    rank[queen] = Some(B_QUEEN);
+    n /= 6;
+
+    let (first, second) = CHESS960_KNIGHTS[n];
+    let squares = empty(&rank);
+    rank[squares[first]] = Some(B_KNIGHT);
+    rank[squares[second]] = Some(B_KNIGHT);
+
+    // the king always stands between the rooks
+    let squares = empty(&rank);
+    rank[squares[0]] = Some(B_ROOK);
+    rank[squares[1]] = Some(B_KING);
+    rank[squares[2]] = Some(B_ROOK);
+
+    let black: String = rank.iter().map(|p| p.unwrap()).collect();
+    let white = black.to_ascii_uppercase();
+    format!("{}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1", black, white)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::move_api::MoveAPI;
+
+    /// Every Chess960 start position index must give a distinct array
+    /// that survives a FEN round trip, and position 518 must be standard chess
+    #[test]
+    fn chess960_start_positions() {
+        assert_eq!(chess960_start_position(518), STARTING_BOARD);
+
+        let mut seen = std::collections::HashSet::new();
+        for index in 0..960 {
+            let fen = chess960_start_position(index);
+            assert_eq!(MoveAPI::new(&fen).get_board_ref().get_fen(), fen, "position {}", index);
+            assert!(seen.insert(fen), "position {} repeats an earlier one", index);
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use super::{
     board::Board, cmove::Move, color::Color, move_executor::MoveExecutor,
-    move_generator::MoveGenerator, piece::Piece,
+    move_generator::MoveGenerator, nnue::{AccumulatorStack, Network}, piece::Piece,
+    variant::{Outcome, Variant, EIGHTH_RANK},
+    bitboard_util::mask,
 };
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 pub struct MoveAPI {
@@ -11,14 +14,30 @@ pub struct MoveAPI {
 
 impl MoveAPI {
     pub fn new(fen: &str) -> Self {
-        let board = Board::new(fen);
+        MoveAPI::from_board(Board::new(fen))
+    }
+
+    pub fn with_variant(fen: &str, variant: Variant) -> Self {
+        MoveAPI::from_board(Board::with_variant(fen, variant))
+    }
 
+    pub fn from_board(board: Board) -> Self {
         MoveAPI {
             move_generator: MoveGenerator::new(),
             move_executor: MoveExecutor::new(board),
         }
     }
 
+    /// Continue from `board` with the same move tables,
+    /// the move history and network accumulators are dropped
+    pub fn set_board(&mut self, board: Board) {
+        self.move_executor = MoveExecutor::new(board);
+    }
+
+    pub fn get_variant(&self) -> Variant {
+        self.get_board_ref().variant
+    }
+
     pub fn get_pseudo_moves_color(&self, color: Color) -> Vec<Move> {
         self.move_generator.get_moves_color(self.get_board_ref(), color)
     }
@@ -27,6 +46,15 @@ impl MoveAPI {
         self.move_executor.get_board_ref()
     }
 
+    /// Keep network accumulators updated through exec_move/undo_move
+    pub fn enable_nnue(&mut self, network: Arc<Network>) {
+        self.move_executor.enable_nnue(network);
+    }
+
+    pub fn get_accumulators(&self) -> Option<&AccumulatorStack> {
+        self.move_executor.get_accumulators()
+    }
+
     pub fn get_state(&self) -> [[(Option<Piece>, Option<Color>); 8]; 8] {
         return self.get_board_ref().get_state();
     }
@@ -38,66 +66,135 @@ impl MoveAPI {
     pub fn get_legal_moves(&mut self) -> Vec<Move> {
         // TODO: make this run at most once per turn
         let mut out = Vec::new();
+        if self.is_goal_reached() {
+            return out;
+        }
+
         let pseudolegal: Vec<Move> = self.move_generator.get_moves(self.get_board_ref());
-        let attack_color = self.get_board_ref().turn.get_opposite();
+        let mover = self.get_board_ref().turn;
 
         for m in pseudolegal {
             self.move_executor.exec_move(m);
-            let is_check = self.is_check(attack_color);
+            let is_legal = self.is_legal(mover);
             self.move_executor.undo_move();
 
-            // TODO: better check check system
-            if !is_check {
+            if is_legal {
                 out.push(m);
             }
         }
-        out
+        self.get_variant().filter_moves(self.get_board_ref(), out)
     }
 
     pub fn exec_move(&mut self, m: Move) {
+        let mover = self.get_board_ref().turn;
         self.move_executor.exec_move(m);
+
+        if self.get_variant().counts_checks() && self.is_check(mover) {
+            self.move_executor.add_check(mover);
+        }
+    }
+
+    /// Whether the position reached by a pseudo-legal move of `mover` is allowed
+    fn is_legal(&self, mover: Color) -> bool {
+        self.get_variant()
+            .is_legal(&self.move_generator, self.get_board_ref(), mover)
+    }
+
+    /// Whether the game already ended through the variant's own goal
+    fn is_goal_reached(&mut self) -> bool {
+        self.get_goal_outcome().is_some()
+    }
+
+    /// The variant's goal outcome, except that in Racing Kings the game
+    /// goes on for one move if Black can answer White's arrival in kind
+    fn get_goal_outcome(&mut self) -> Option<Outcome> {
+        let variant = self.get_variant();
+        let outcome = variant.get_goal_outcome(self.get_board_ref())?;
+
+        let is_black_turn = self.get_turn_color() == Color::Black;
+        if variant.has_equalizing_move()
+            && outcome == Outcome::Win(Color::White)
+            && is_black_turn
+            && self.can_king_reach(EIGHTH_RANK)
+        {
+            return None;
+        }
+        Some(outcome)
+    }
+
+    /// Whether the side to move has a legal king move onto `targets`
+    fn can_king_reach(&mut self, targets: u64) -> bool {
+        let mover = self.get_turn_color();
+        let king_moves = self
+            .move_generator
+            .get_piece_moves(self.get_board_ref(), Piece::King, mover);
+
+        for m in king_moves {
+            if mask(m.end.to_index()) & targets == 0 {
+                continue;
+            }
+            self.move_executor.exec_move(m);
+            let is_legal = self.is_legal(mover);
+            self.move_executor.undo_move();
+
+            if is_legal {
+                return true;
+            }
+        }
+        false
     }
 
     pub fn undo_move(&mut self) {
         self.move_executor.undo_move();
     }
 
+    /// The legal move written in coordinate notation, like e2e4 or e7e8q
+    pub fn find_coordinate_move(&mut self, text: &str) -> Option<Move> {
+        let turn = self.get_turn_color();
+        self.get_legal_moves()
+            .into_iter()
+            .find(|m| m.get_coordinates(turn) == text)
+    }
+
+    /// Whether the side to move has a royal king and it is attacked
+    pub fn is_in_check(&self) -> bool {
+        let turn = self.get_turn_color();
+        let board = self.get_board_ref();
+        self.get_variant().has_royal_king(turn)
+            && board.get_color_piece_board(Piece::King, turn) != 0
+            && self.is_check(turn.get_opposite())
+    }
+
     fn is_check(&self, attack_color: Color) -> bool {
         self.move_generator
             .is_attacked_king(self.get_board_ref(), attack_color)
     }
 
-    pub fn is_checkmate(&mut self) -> bool {
-        let attack_color = self.get_board_ref().turn.get_opposite();
-        self.get_legal_moves().is_empty() && self.is_check(attack_color)
-    }
+    /// Result of the game in the current position under the board's variant
+    pub fn get_outcome(&mut self) -> Outcome {
+        let variant = self.get_variant();
+        if let Some(outcome) = self.get_goal_outcome() {
+            return outcome;
+        }
 
-    pub fn is_stalemate(&mut self) -> bool {
+        if !self.get_legal_moves().is_empty() {
+            return Outcome::Ongoing;
+        }
         let attack_color = self.get_board_ref().turn.get_opposite();
-        self.get_legal_moves().is_empty() && !self.is_check(attack_color)
+        let is_check = variant.has_royal_king(self.get_turn_color()) && self.is_check(attack_color);
+        variant.get_no_move_outcome(self.get_board_ref(), is_check)
     }
 
     pub fn get_turn_color(&self) -> Color {
         return self.get_board_ref().turn;
     }
 
-    pub fn get_evaluation(&self) -> f64 {
-
-        let w_score = self.get_board_ref().get_material_score(Color::White);
-        let b_score = self.get_board_ref().get_material_score(Color::Black);
-        let w_dev = self.get_pseudo_moves_color(Color::White).len() as f64;
-        let b_dev = self.get_pseudo_moves_color(Color::Black).len() as f64;
-
-        let w_queen = self.move_generator.get_piece_moves(self.get_board_ref(), Piece::Queen, Color::White).len() as f64;
-        let b_queen = self.move_generator.get_piece_moves(self.get_board_ref(), Piece::Queen, Color::Black).len() as f64;
-
-        let overextend_w = w_queen;
-        let overextend_b = b_queen;
-
-        w_score - b_score + (w_dev - b_dev) * 0.001 + 0.001 * b_queen / w_queen
-    }
-
     pub fn perft(&mut self, depth: u64) -> (u64, Duration) {
+        // a game won through the variant's goal has no moves left
+        if self.is_goal_reached() {
+            return (0, Duration::new(0, 0));
+        }
+
         if depth == 1 {
 
             let mut dur = Duration::new(0, 0);
@@ -111,18 +208,18 @@ impl MoveAPI {
                 let mut out = Vec::new();
                 let pseudolegal: Vec<Move> = self.move_generator.get_moves(self.get_board_ref());
                 dur += now.elapsed();
-                let attack_color = self.get_board_ref().turn.get_opposite();
+                let mover = self.get_board_ref().turn;
 
                 for m in pseudolegal {
                     self.move_executor.exec_move(m);
-                    let is_check = self.is_check(attack_color);
+                    let is_legal = self.is_legal(mover);
                     self.move_executor.undo_move();
 
-                    if !is_check {
+                    if is_legal {
                         out.push(m);
                     }
                 }
-                out
+                self.get_variant().filter_moves(self.get_board_ref(), out)
             }
             .len() as u64;
 
@@ -133,18 +230,19 @@ impl MoveAPI {
         let mut dur = Duration::new(0, 0);
 
         let pseudolegal: Vec<Move> = self.move_generator.get_moves(self.get_board_ref());
+        // captures are only forced where every pseudo-legal move is legal,
+        // so filtering before the legality check gives the same moves
+        let pseudolegal = self.get_variant().filter_moves(self.get_board_ref(), pseudolegal);
         dur += now.elapsed();
 
-        let attack_color = self.get_board_ref().turn.get_opposite();
+        let mover = self.get_board_ref().turn;
 
         let mut out = 0;
 
         for m in pseudolegal {
-            self.move_executor.exec_move(m);
-
-            let is_check = self.is_check(attack_color);
+            self.exec_move(m);
 
-            if !is_check {
+            if self.is_legal(mover) {
                 let rval = self.perft(depth - 1);
                 out += rval.0;
                 dur += rval.1;
@@ -170,3 +268,138 @@ impl MoveAPI {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::fen;
+
+    /// Deepest count of each suite the tests check, deeper ones take minutes
+    const MAX_PERFT_DEPTH: usize = 4;
+
+    /// Positions with known move path enumeration counts,
+    /// the n-th count is for depth n + 1
+    /// https://www.chessprogramming.org/Perft_Results
+    const PERFT_SUITE: [(&str, &[u64]); 7] = [
+        (fen::STARTING_BOARD, &[20, 400, 8902, 197281, 4865609]),
+        ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 0", &[48, 2039, 97862, 4085603]),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 0", &[14, 191, 2812, 43238, 674624]),
+        ("r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1", &[6, 264, 9467, 422333]),
+        ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1486, 62379, 2103487]),
+        ("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", &[46, 2079, 89890, 3894594]),
+        ("n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 6", &[24, 496, 9483, 182838]),
+    ];
+
+    /// Chess960 positions from the standard Chess960 perft suite
+    const CHESS960_PERFT_SUITE: [(&str, &[u64]); 10] = [
+        ("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", &[21, 528, 12189, 326672]),
+        ("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", &[21, 807, 18002, 667366]),
+        ("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", &[20, 479, 10471, 273318]),
+        ("qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9", &[22, 593, 13440, 382958]),
+        ("1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9", &[28, 1120, 31058, 1171749]),
+        ("qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9", &[29, 899, 26578, 824055]),
+        ("q1bnrkr1/ppppp2p/2n2p2/4b1p1/2NP4/8/PPP1PPPP/QNB1RRKB w ge - 1 9", &[30, 860, 24566, 732757]),
+        ("qbn1brkr/ppp1p1p1/2n4p/3p1p2/P7/6PP/QPPPPP2/1BNNBRKR w HFhf - 0 9", &[25, 635, 17054, 465806]),
+        ("qnnbbrkr/1p2ppp1/2pp3p/p7/1P5P/2NP4/P1P1PPP1/Q1NBBRKR w HFhf - 0 9", &[24, 572, 15243, 384260]),
+        ("qn1rbbkr/ppp2p1p/1n1pp1p1/8/3P4/P6P/1PP1PPPK/QNNRBB1R w hd - 2 9", &[28, 811, 23175, 679699]),
+    ];
+
+    /// Variant positions with known move path enumeration counts
+    const VARIANT_PERFT_SUITE: [(Variant, &str, &[u64]); 12] = [
+        // neither goal can be reached this early
+        (Variant::KingOfTheHill, fen::STARTING_BOARD, &[20, 400, 8902, 197281]),
+        (Variant::ThreeCheck, fen::THREE_CHECK_STARTING_BOARD, &[20, 400, 8902, 197281]),
+        (Variant::Crazyhouse, fen::CRAZYHOUSE_STARTING_BOARD, &[20, 400, 8902, 197281, 4888832]),
+        (Variant::Crazyhouse, "2k5/8/8/8/8/8/8/4K3[QRBNPqrbnp] w - - 0 1", &[301, 75353]),
+        (Variant::Antichess, fen::ANTICHESS_STARTING_BOARD, &[20, 400, 8067, 153299, 2732672]),
+        // the capture is forced, and may promote to a king
+        (Variant::Antichess, "8/8/8/8/8/8/p7/1R6 b - - 0 1", &[5]),
+        (Variant::Atomic, fen::STARTING_BOARD, &[20, 400, 8902, 197326, 4864979]),
+        // kings may touch, but never capture
+        (Variant::Atomic, "8/8/8/8/3kK3/8/8/8 w - - 0 1", &[7]),
+        (Variant::Atomic, "4k3/8/8/8/8/8/3q4/4K3 w - - 0 1", &[1]),
+        (Variant::Horde, fen::HORDE_STARTING_BOARD, &[8, 128, 1274, 23310, 265223]),
+        (Variant::RacingKings, fen::RACING_KINGS_STARTING_BOARD, &[21, 421, 11264, 296242]),
+        // Black may still equalize, but not next to White's king
+        (Variant::RacingKings, "4K3/6k1/8/8/8/8/8/8 b - - 0 1", &[6]),
+    ];
+
+    /// Positions whose result follows from the variant's rules alone
+    const VARIANT_OUTCOMES: [(Variant, &str, Outcome); 15] = [
+        (Variant::KingOfTheHill, "4k3/8/8/8/4K3/8/8/8 b - - 0 1", Outcome::Win(Color::White)),
+        (Variant::KingOfTheHill, "8/8/8/3k4/8/8/8/4K3 w - - 0 1", Outcome::Win(Color::Black)),
+        (Variant::KingOfTheHill, "4k3/8/8/8/8/8/8/4K3 w - - 0 1", Outcome::Ongoing),
+        (Variant::ThreeCheck, "4k3/8/8/8/8/8/8/4K3 b - - 0 1 +3+1", Outcome::Win(Color::White)),
+        (Variant::ThreeCheck, "4k3/8/8/8/8/8/8/4K3 w - - 0 1 +2+2", Outcome::Ongoing),
+        (Variant::Standard, "4k3/4Q3/4K3/8/8/8/8/8 b - - 0 1", Outcome::Win(Color::White)),
+        (Variant::Antichess, "8/8/8/8/8/8/p7/8 b - - 0 1", Outcome::Win(Color::White)),
+        (Variant::Antichess, "8/8/8/8/8/p7/P7/8 w - - 0 1", Outcome::Win(Color::White)),
+        (Variant::Antichess, "8/8/8/8/8/p7/P7/8 b - - 0 1", Outcome::Win(Color::Black)),
+        (Variant::Atomic, "8/8/8/8/8/8/8/4K3 b - - 0 1", Outcome::Win(Color::White)),
+        (Variant::Horde, "4k3/8/8/8/8/8/8/8 w - - 0 1", Outcome::Win(Color::Black)),
+        (Variant::RacingKings, "4K3/8/8/8/8/8/k7/8 b - - 0 1", Outcome::Win(Color::White)),
+        (Variant::RacingKings, "4K3/6k1/8/8/8/8/8/8 b - - 0 1", Outcome::Ongoing),
+        (Variant::RacingKings, "4K1k1/8/8/8/8/8/8/8 w - - 0 1", Outcome::Draw),
+        (Variant::RacingKings, "6k1/8/8/8/8/8/8/4K3 w - - 0 1", Outcome::Win(Color::Black)),
+    ];
+
+    /// The legal move whose description is `text`, like "Queen:h5->f7"
+    fn find_move(move_api: &mut MoveAPI, text: &str) -> Move {
+        let found = move_api.get_legal_moves().into_iter().find(|m| m.to_string() == text);
+        found.unwrap_or_else(|| panic!("{} is not legal in {}", text, move_api.get_board_ref().get_fen()))
+    }
+
+    fn assert_perft(variant: Variant, fen: &str, counts: &[u64]) {
+        let mut move_api = MoveAPI::with_variant(fen, variant);
+        for (i, expected) in counts.iter().enumerate().take(MAX_PERFT_DEPTH) {
+            assert_eq!(move_api.perft(i as u64 + 1).0, *expected, "{} depth {}", fen, i + 1);
+        }
+    }
+
+    #[test]
+    fn perft_suite() {
+        for (fen, counts) in PERFT_SUITE {
+            assert_perft(Variant::Standard, fen, counts);
+        }
+    }
+
+    #[test]
+    fn chess960_perft_suite() {
+        for (fen, counts) in CHESS960_PERFT_SUITE {
+            assert_perft(Variant::Standard, fen, counts);
+        }
+    }
+
+    #[test]
+    fn variant_perft_suite() {
+        for (variant, fen, counts) in VARIANT_PERFT_SUITE {
+            assert_perft(variant, fen, counts);
+        }
+    }
+
+    #[test]
+    fn variant_outcomes() {
+        for (variant, fen, expected) in VARIANT_OUTCOMES {
+            assert_eq!(MoveAPI::with_variant(fen, variant).get_outcome(), expected, "{} ({})", fen, variant.get_name());
+        }
+    }
+
+    /// A third check ends Three-check, and the counters survive a FEN round trip
+    #[test]
+    fn third_check_wins() {
+        let mut move_api = MoveAPI::with_variant("rnbqkbnr/ppp2ppp/8/3pp2Q/4P3/8/PPPP1PPP/RNB1KBNR w KQkq - 0 3 +2+0", Variant::ThreeCheck);
+        let m = find_move(&mut move_api, "Queen:h5->f7");
+        move_api.exec_move(m);
+        assert!(move_api.get_board_ref().get_fen().ends_with("+3+0"));
+        assert_eq!(move_api.get_outcome(), Outcome::Win(Color::White));
+    }
+
+    /// Captured pieces go to the capturer's pocket, promoted ones as pawns
+    #[test]
+    fn crazyhouse_pockets() {
+        let mut move_api = MoveAPI::with_variant("4k3/8/8/8/8/8/7q~/4K2R[n] w - - 0 1", Variant::Crazyhouse);
+        let m = find_move(&mut move_api, "Rook:h1->h2");
+        move_api.exec_move(m);
+        assert_eq!(move_api.get_board_ref().get_fen(), "4k3/8/8/8/8/8/7R/4K3[Pn] b - - 0 1");
+    }
+}
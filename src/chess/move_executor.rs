@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use crate::chess::direction::shift;
 
 use super::{
@@ -7,23 +9,23 @@ use super::{
     cmove::Move,
     color::Color,
     direction::Direction,
-    piece::Piece,
+    move_table::get_king_masks,
+    nnue::{AccumulatorStack, Network},
+    piece::{Piece, PIECE_TYPES},
 };
 
 pub struct MoveExecutor {
     /// reference to the board
     board: Box<Board>,
     previous_boards: Vec<Board>,
-    castle_utility: CastleUtility,
+    /// network accumulators, updated alongside the board when enabled
+    accumulators: Option<AccumulatorStack>,
+    /// squares around each square that an atomic capture clears
+    blast_masks: [u64; 64],
 }
 
-const CAPTURE_TYPES: [Piece; 5] = [
-    Piece::Pawn,
-    Piece::Knight,
-    Piece::Bishop,
-    Piece::Rook,
-    Piece::Queen,
-];
+/// Kings are only ever captured in Antichess
+const CAPTURE_TYPES: [Piece; 6] = PIECE_TYPES;
 
 impl MoveExecutor {
     pub fn new(board: Board) -> MoveExecutor {
@@ -31,13 +33,29 @@ impl MoveExecutor {
             board: Box::new(board),
             previous_boards: Vec::new(),
 
-            castle_utility: CastleUtility::new(),
+            accumulators: None,
+            blast_masks: get_king_masks(),
         }
     }
 
+    /// Start keeping network accumulators for every executed move
+    pub fn enable_nnue(&mut self, network: Arc<Network>) {
+        self.accumulators = Some(AccumulatorStack::new(network, &self.board));
+    }
+
+    pub fn get_accumulators(&self) -> Option<&AccumulatorStack> {
+        self.accumulators.as_ref()
+    }
+
     pub fn exec_move(&mut self, mut cmove: Move) {
         self.previous_boards.push(*self.board.clone());
 
+        if cmove.is_drop {
+            self.exec_drop(&cmove);
+            self.finish_move();
+            return;
+        }
+
         let color = self.board.turn;
         let e_color = color.get_opposite();
         let mut friendly_pieces = self.board.get_pieces(color);
@@ -45,54 +63,10 @@ impl MoveExecutor {
         let mut piece_board = self.board.get_piece_board(cmove.piece);
 
         if cmove.piece == Piece::King {
-            let is_legal_short = self.board.get_castle(Castle::Short, color);
-            let is_legal_long = self.board.get_castle(Castle::Long, color);
-
-            let s = CastleUtility::get_king_landing(Castle::Short, color);
-            let l = CastleUtility::get_king_landing(Castle::Long, color);
-
-            // TODO: halve the code here :)
-
-            if is_legal_short && cmove.end.to_index() == s {
-                let clear_mask = self.castle_utility.get_clear_castle(Castle::Short, color);
-                let mut rook_board = self.board.get_piece_board(Piece::Rook);
-
-                // clear pieces on castling squares in relevant bitboards
-                friendly_pieces &= clear_mask;
-                piece_board &= clear_mask;
-                rook_board &= clear_mask;
-
-                // put castle result on friendly bitboard
-                let castle_mask = self.castle_utility.get_castle_result(Castle::Short, color);
-
-                friendly_pieces |= castle_mask;
-
-                // place rook on new location
-                let rook_mask = castle_mask & !mask(cmove.end.to_index());
-                rook_board |= rook_mask;
-
-                self.board.set_piece_board(Piece::Rook, rook_board);
-            }
-
-            // TODO: halve the code here :)
-            if is_legal_long && cmove.end.to_index() == l {
-                let clear_mask = self.castle_utility.get_clear_castle(Castle::Long, color);
-                let mut rook_board = self.board.get_piece_board(Piece::Rook);
-
-                // clear pieces on castling squares in relevant bitboards
-                friendly_pieces &= clear_mask;
-                piece_board &= clear_mask;
-                rook_board &= clear_mask;
-
-                // put castle result on friendly bitboard
-                let castle_mask = self.castle_utility.get_castle_result(Castle::Long, color);
-                friendly_pieces |= castle_mask;
-
-                // place rook on new location
-                let rook_mask = castle_mask & !mask(cmove.end.to_index());
-                rook_board |= rook_mask;
-
-                self.board.set_piece_board(Piece::Rook, rook_board);
+            if let Some(castle) = self.get_castle_type(&cmove, color) {
+                self.exec_castle(&cmove, castle, color);
+                self.finish_move();
+                return;
             }
             self.board.set_castle(Castle::Short, color, false);
             self.board.set_castle(Castle::Long, color, false);
@@ -100,11 +74,10 @@ impl MoveExecutor {
 
         if cmove.piece == Piece::Rook {
             // Check for rook loss of castle privileges
-            if cmove.start.to_index() == CastleUtility::get_rook_origin(Castle::Short, color) {
-                self.board.set_castle(Castle::Short, color, false);
-            } else if cmove.start.to_index() == CastleUtility::get_rook_origin(Castle::Long, color)
-            {
-                self.board.set_castle(Castle::Long, color, false);
+            for castle in [Castle::Short, Castle::Long] {
+                if cmove.start.to_index() == self.board.get_castle_rook(castle, color) {
+                    self.board.set_castle(castle, color, false);
+                }
             }
         }
 
@@ -124,15 +97,18 @@ impl MoveExecutor {
                         piece_board &= !mask(cmove.end.to_index());
                     }
 
+                    // Crazyhouse: the capturer pockets the piece, promoted pieces revert to pawns
+                    if self.board.variant.has_drops() {
+                        let is_promoted = self.board.promoted & mask(cmove.end.to_index()) != 0;
+                        let pocket_piece = if is_promoted { Piece::Pawn } else { capture_type };
+                        self.board.add_to_pocket(pocket_piece, color);
+                    }
+
                     // if rook is captured, other side obviously cant castle with it :)
-                    if cmove.end.to_index()
-                        == CastleUtility::get_rook_origin(Castle::Short, e_color)
-                    {
-                        self.board.set_castle(Castle::Short, e_color, false);
-                    } else if cmove.end.to_index()
-                        == CastleUtility::get_rook_origin(Castle::Long, e_color)
-                    {
-                        self.board.set_castle(Castle::Long, e_color, false);
+                    for castle in [Castle::Short, Castle::Long] {
+                        if cmove.end.to_index() == self.board.get_castle_rook(castle, e_color) {
+                            self.board.set_castle(castle, e_color, false);
+                        }
                     }
 
                     self.board
@@ -192,15 +168,136 @@ impl MoveExecutor {
         friendly_pieces &= !mask(cmove.start.to_index());
         friendly_pieces |= mask(cmove.end.to_index());
 
+        // promoted pieces keep their mark as they move
+        let is_promoted = self.board.promoted & mask(cmove.start.to_index()) != 0
+            || (cmove.piece == Piece::Pawn && cmove.promotion.is_some());
+        self.board.promoted &= !mask(cmove.start.to_index()) & !mask(cmove.end.to_index());
+        if is_promoted {
+            self.board.promoted |= mask(cmove.end.to_index());
+        }
+
         self.board.set_color_board(color, friendly_pieces);
         self.board.set_color_board(e_color, enemy_pieces);
         self.board.set_piece_board(cmove.piece, piece_board);
 
+        if capture_mask != 0 && self.board.variant.has_explosions() {
+            self.explode(cmove.end.to_index());
+        }
+
+        self.finish_move();
+    }
+
+    /// Pass the turn, advance the clocks and bring the accumulators up to date
+    fn finish_move(&mut self) {
+        let previous = self.previous_boards.last().unwrap();
+
+        // pawn moves and captures reset the fifty-move clock
+        let enemy = self.board.turn.get_opposite();
+        let is_capture = previous.get_pieces(enemy) & !self.board.get_pieces(enemy) != 0;
+        if previous.pawns != self.board.pawns || is_capture {
+            self.board.halfmove_clock = 0;
+        } else {
+            self.board.halfmove_clock += 1;
+        }
+
+        if self.board.turn == Color::Black {
+            self.board.fullmove_clock += 1;
+        }
         self.board.turn = self.board.turn.get_opposite();
+
+        if let Some(accumulators) = self.accumulators.as_mut() {
+            accumulators.push(self.previous_boards.last().unwrap(), &self.board);
+        }
+    }
+
+    /// Whether a king move castles. Standard boards encode castling
+    /// as the king moving to its landing square, Chess960 boards as
+    /// the king moving onto the castling rook
+    fn get_castle_type(&self, cmove: &Move, color: Color) -> Option<Castle> {
+        for castle in [Castle::Short, Castle::Long] {
+            if !self.board.get_castle(castle, color) {
+                continue;
+            }
+
+            let target = if self.board.is_chess960 {
+                self.board.get_castle_rook(castle, color)
+            } else {
+                CastleUtility::get_king_landing(castle, color)
+            };
+            if cmove.end.to_index() == target {
+                return Some(castle);
+            }
+        }
+        None
+    }
+
+    /// Atomic capture on `index`: the capturer and every piece
+    /// but pawns on the adjacent squares are removed
+    fn explode(&mut self, index: usize) {
+        let blast = self.blast_masks[index] & !(self.board.pawns & !mask(index)) | mask(index);
+
+        for piece in CAPTURE_TYPES {
+            let piece_board = self.board.get_piece_board(piece);
+            self.board.set_piece_board(piece, piece_board & !blast);
+        }
+        for color in [Color::White, Color::Black] {
+            let pieces = self.board.get_pieces(color);
+            self.board.set_color_board(color, pieces & !blast);
+
+            for castle in [Castle::Short, Castle::Long] {
+                if blast & mask(self.board.get_castle_rook(castle, color)) != 0 {
+                    self.board.set_castle(castle, color, false);
+                }
+            }
+        }
+    }
+
+    /// Place a piece from the pocket on an empty square
+    fn exec_drop(&mut self, cmove: &Move) {
+        let color = self.board.turn;
+        let target = mask(cmove.end.to_index());
+
+        self.board.set_color_board(color, self.board.get_pieces(color) | target);
+        self.board.set_piece_board(cmove.piece, self.board.get_piece_board(cmove.piece) | target);
+        self.board.remove_from_pocket(cmove.piece, color);
+        self.board.ep_target = 0;
+    }
+
+    fn exec_castle(&mut self, cmove: &Move, castle: Castle, color: Color) {
+        let king_origin = mask(cmove.start.to_index());
+        let rook_origin = mask(self.board.get_castle_rook(castle, color));
+        let king_landing = mask(CastleUtility::get_king_landing(castle, color));
+        let rook_landing = mask(CastleUtility::get_rook_landing(castle, color));
+
+        // clear both pieces before placing them, the king
+        // and rook may land on each other's starting squares
+        let friendly_pieces = self.board.get_pieces(color) & !king_origin & !rook_origin;
+        let king_board = self.board.get_piece_board(Piece::King) & !king_origin;
+        let rook_board = self.board.get_piece_board(Piece::Rook) & !rook_origin;
+
+        self.board.set_color_board(color, friendly_pieces | king_landing | rook_landing);
+        self.board.set_piece_board(Piece::King, king_board | king_landing);
+        self.board.set_piece_board(Piece::Rook, rook_board | rook_landing);
+
+        self.board.set_castle(Castle::Short, color, false);
+        self.board.set_castle(Castle::Long, color, false);
+        self.board.ep_target = 0;
     }
 
     pub fn undo_move(&mut self) {
         self.board = Box::new(self.previous_boards.pop().unwrap());
+
+        if let Some(accumulators) = self.accumulators.as_mut() {
+            accumulators.pop();
+            if accumulators.is_empty() {
+                self.accumulators = None;
+            }
+        }
+    }
+
+    /// Count a check given by `color` in the current position
+    pub fn add_check(&mut self, color: Color) {
+        self.board.add_check(color);
     }
 
     pub fn get_board_ref(&self) -> &Board {
@@ -2,10 +2,11 @@ use super::bitboard_util::{clear_bit, mask};
 use super::direction::Direction;
 use super::move_table::MoveTable;
 
-use super::board::{Board, Castle, Square};
+use super::board::{Board, Castle, POCKET_PIECES};
+use super::castle_utils::CastleUtility;
 use super::cmove::Move;
 use super::color::Color;
-use super::piece::Piece;
+use super::piece::{Piece, PIECE_TYPES};
 use super::position::Position;
 use core::time;
 use std::num::Wrapping;
@@ -15,16 +16,6 @@ pub struct MoveGenerator {
     move_table: MoveTable,
 }
 
-const PIECE_TYPES: [Piece; 6] = [
-    Piece::Pawn,
-    Piece::Knight,
-    Piece::Bishop,
-    Piece::Rook,
-    Piece::Queen,
-    Piece::King,
-];
-
-const UNIVERSE: u64 = u64::MAX;
 const BOARD_SIZE: u32 = 8;
 const W_PAWN_LAST_ROW: usize = 0;
 const B_PAWN_LAST_ROW: usize = 7;
@@ -63,6 +54,20 @@ impl MoveGenerator {
         // return value initialized here
         let mut out = move_board;
 
+        // Horde pawns on the first rank may move two squares as well
+        let first_row = match color {
+            Color::White => 7,
+            Color::Black => 0,
+        };
+        let is_first_rank = index as u32 / BOARD_SIZE == first_row;
+        if board.variant.has_first_rank_double_step() && is_first_rank && move_board != 0 {
+            let double_index = match color {
+                Color::White => index - 2 * BOARD_SIZE as usize,
+                Color::Black => index + 2 * BOARD_SIZE as usize,
+            };
+            out |= mask(double_index) & !all_pieces;
+        }
+
         // Add pawn captures
         out |= enemy_pieces & attack_board;
         let mut ep_target = board.ep_target;
@@ -93,75 +98,17 @@ impl MoveGenerator {
 
     fn get_king_move_bitboard(&self, board: &Board, index: usize, color: Color) -> u64 {
         let friendly_pieces = board.get_pieces(color);
-        let enemy_pieces = board.get_pieces(color.get_opposite());
-
-        // all pieces but the king TODO: better name
-        let all_pieces =
-            (friendly_pieces | enemy_pieces) & !board.get_color_piece_board(Piece::King, color);
         let mut attack_board = self.move_table[Piece::King][index];
 
-        let mut castle_short = self.move_table.get_castle(Castle::Short, color);
-        let mut castle_long = self.move_table.get_castle(Castle::Long, color);
-
-        // zeroed out if castling is impossible
-        castle_short &= (Wrapping(0) - Wrapping(board.get_castle(Castle::Short, color) as u64)).0;
-        castle_long &= (Wrapping(0) - Wrapping(board.get_castle(Castle::Long, color) as u64)).0;
-
-        // get intermediate castling squares
-        // they must not be under check
-        // after extensive debugging, we find these square must also have no pieces
-        let mut short_safety = self
-            .move_table
-            .get_castle_safe_squares(Castle::Short, color);
-        let mut long_safety = self.move_table.get_castle_safe_squares(Castle::Long, color);
-
-        // If ANY of the intermediate squares are under attack, castle move mask is zeroed out
-        let mut i = short_safety.leading_zeros();
-        while i < 64 {
-            // get move bitboard and map the destinations to Positions
-            let x = self.is_attacked(board, color.get_opposite(), i as usize);
-            let safety_mask = (Wrapping(UNIVERSE) + Wrapping(x as u64)).0;
-
-            castle_short &= safety_mask;
-
-            let piece_in_way = all_pieces & mask(i as usize) != 0;
-            let p_mask = (Wrapping(UNIVERSE) + Wrapping(piece_in_way as u64)).0;
-            // remove castle if there's a piece in the way
-            castle_short &= p_mask;
-
-            // Remove bit, so we can get the next one
-            short_safety = clear_bit(short_safety, i as usize);
-            i = short_safety.leading_zeros();
+        // a king that is an ordinary piece may walk into attacks, and does not castle
+        if !board.variant.has_royal_king(color) {
+            return (!friendly_pieces) & attack_board;
         }
 
-        // TODO: clean this up, small edge case for long castle
-
-        let in_way = if color == Color::White {
-            Square::B1
-        } else {
-            Square::B8
-        };
-
-        let piece_in_way = all_pieces & mask(in_way as usize) != 0;
-        let p_mask = (Wrapping(UNIVERSE) + Wrapping(piece_in_way as u64)).0;
-        // remove castle if there's a piece in the way
-        castle_long &= p_mask;
-
-        let mut i = long_safety.leading_zeros();
-        while i < 64 {
-            // get move bitboard and map the destinations to Positions
-            let x = self.is_attacked(board, color.get_opposite(), i as usize);
-            let safety_mask = (Wrapping(UNIVERSE) + Wrapping(x as u64)).0;
-            castle_long &= safety_mask;
-
-            let piece_in_way = all_pieces & mask(i as usize) != 0;
-            let p_mask = (Wrapping(UNIVERSE) + Wrapping(piece_in_way as u64)).0;
-            // remove castle if there's a piece in the way
-            castle_long &= p_mask;
-
-            // Remove bit, so we can get the next one
-            long_safety = clear_bit(long_safety, i as usize);
-            i = long_safety.leading_zeros();
+        // attacked squares next to the enemy king are safe in Atomic,
+        // leave all of it to the legality check
+        if board.variant.has_explosions() {
+            return ((!friendly_pieces) & attack_board) | self.get_castle_bitboard(board, index, color);
         }
 
         // ensure King is not attacked
@@ -174,9 +121,59 @@ impl MoveGenerator {
             adjacent = clear_bit(adjacent, index as usize);
         }
 
-        attack_board |= (castle_short) | (castle_long);
+        // castle targets are added after removing friendly squares,
+        // in Chess960 they are the castling rook's square
+        ((!friendly_pieces) & attack_board) | self.get_castle_bitboard(board, index, color)
+    }
 
-        (!friendly_pieces) & attack_board
+    /// Targets of the available castle moves for the king on `index`.
+    /// Works for any starting array: the squares the king and rook cross
+    /// must be empty (apart from those two), and the king may not start on,
+    /// cross or land on an attacked square
+    fn get_castle_bitboard(&self, board: &Board, index: usize, color: Color) -> u64 {
+        let all_pieces = board.get_pieces(color) | board.get_pieces(color.get_opposite());
+        let rooks = board.get_color_piece_board(Piece::Rook, color);
+
+        let mut out = 0;
+        for castle in [Castle::Short, Castle::Long] {
+            if !board.get_castle(castle, color) {
+                continue;
+            }
+
+            let rook_index = board.get_castle_rook(castle, color);
+            if rooks & mask(rook_index) == 0 {
+                continue;
+            }
+
+            let king_landing = CastleUtility::get_king_landing(castle, color);
+            let rook_landing = CastleUtility::get_rook_landing(castle, color);
+
+            let others = all_pieces & !mask(index) & !mask(rook_index);
+            let path = CastleUtility::get_span(index, king_landing)
+                | CastleUtility::get_span(rook_index, rook_landing);
+            if others & path != 0 {
+                continue;
+            }
+
+            let mut king_path = CastleUtility::get_span(index, king_landing);
+            let mut is_safe = true;
+            let mut i = king_path.leading_zeros();
+            while i < 64 {
+                is_safe &= !self.is_attacked(board, color.get_opposite(), i as usize);
+                king_path = clear_bit(king_path, i as usize);
+                i = king_path.leading_zeros();
+            }
+            if !is_safe {
+                continue;
+            }
+
+            out |= if board.is_chess960 {
+                mask(rook_index)
+            } else {
+                mask(king_landing)
+            };
+        }
+        out
     }
 
     /// Get moves for sliding pieces
@@ -252,7 +249,8 @@ impl MoveGenerator {
                 // todo: clean this up
                 if piece == Piece::Pawn {
                     if d.get_row() == last_row {
-                        out.extend(Move::make_promotions(Position::index(i as usize), d));
+                        let pieces = board.variant.get_promotion_pieces();
+                        out.extend(Move::make_promotions(Position::index(i as usize), d, pieces));
                     } else {
                         out.push(Move::new(
                             Position::index(i as usize),
@@ -277,6 +275,28 @@ impl MoveGenerator {
         out
     }
 
+    /// Crazyhouse drops of every pocket piece onto every empty square,
+    /// pawns may not be dropped on the first or last rank
+    pub fn get_drop_moves(&self, board: &Board, color: Color) -> Vec<Move> {
+        let mut out = Vec::new();
+        let empty = !(board.get_pieces(color) | board.get_pieces(color.get_opposite()));
+
+        for piece in POCKET_PIECES {
+            if board.get_pocket(piece, color) == 0 {
+                continue;
+            }
+
+            let targets = match piece {
+                Piece::Pawn => empty & !self.move_table.get_back_ranks(),
+                _ => empty,
+            };
+            for end in MoveGenerator::bitboard_to_positions(targets) {
+                out.push(Move::drop(piece, end));
+            }
+        }
+        out
+    }
+
     fn is_attacked(&self, board: &Board, attacking_color: Color, index: usize) -> bool {
         let friendly: Color = attacking_color.get_opposite();
 
@@ -294,7 +314,12 @@ impl MoveGenerator {
         let e_bishops = board.get_color_piece_board(Piece::Bishop, attacking_color) & (bishop_mask);
         let e_knights = board.get_color_piece_board(Piece::Knight, attacking_color) & (knight_mask);
         let e_pawns = board.get_color_piece_board(Piece::Pawn, attacking_color) & (pawn_mask);
-        let e_kings = board.get_color_piece_board(Piece::King, attacking_color) & (king_mask);
+        // a king captures nothing when captures explode
+        let e_kings = if board.variant.has_explosions() {
+            0
+        } else {
+            board.get_color_piece_board(Piece::King, attacking_color) & (king_mask)
+        };
 
         e_kings != 0
             || e_queens != 0
@@ -323,6 +348,12 @@ impl MoveGenerator {
         } 
         let king_position = king_positions[0];
 
+        // touching kings can not be checked when captures explode
+        let attacking_king = board.get_color_piece_board(Piece::King, attacking_color);
+        if board.variant.has_explosions() && self.move_table[Piece::King][king_position.to_index()] & attacking_king != 0 {
+            return false;
+        }
+
         self.is_attacked(board, attacking_color, king_position.to_index())
     }
 
@@ -340,6 +371,9 @@ impl MoveGenerator {
             out.extend(self.get_piece_moves(board, piece, board.turn));
         }
 
+        if board.variant.has_drops() {
+            out.extend(self.get_drop_moves(board, board.turn));
+        }
         out
     }
 
@@ -352,6 +386,9 @@ impl MoveGenerator {
             out.extend(self.get_piece_moves(board, piece, color));
         }
 
+        if board.variant.has_drops() {
+            out.extend(self.get_drop_moves(board, color));
+        }
         out
     }
 
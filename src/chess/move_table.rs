@@ -1,5 +1,5 @@
-use super::bitboard_util::{mask, put_bit};
-use super::board::{Castle, Square};
+use super::bitboard_util::put_bit;
+use super::board::Square;
 use super::color::Color;
 use super::direction::{get_direction, shift, Direction};
 use super::direction::{
@@ -10,11 +10,6 @@ use super::piece::Piece;
 use super::position::Position;
 use std::ops::Index;
 
-const W_CASTLE_SHORT: usize = Square::G1 as usize;
-const W_CASTLE_LONG: usize = Square::C1 as usize;
-const B_CASTLE_SHORT: usize = Square::G8 as usize;
-const B_CASTLE_LONG: usize = Square::C8 as usize;
-
 fn sliding_helper(index: usize, directions: &Vec<Direction>, max_magnitude: i8) -> u64 {
     let mut bitboard: u64 = 0;
     let origin = Position::index(index);
@@ -105,7 +100,8 @@ fn get_queen_masks() -> [u64; 64] {
     get_sliding_all(&directions, 8)
 }
 
-fn get_king_masks() -> [u64; 64] {
+/// Squares adjacent to each square, also the blast area of an atomic capture
+pub fn get_king_masks() -> [u64; 64] {
     let directions = vec![
         East, NorthEast, North, NorthWest, West, SouthWest, South, SouthEast,
     ];
@@ -135,14 +131,6 @@ fn get_ray_masks() -> [[u64; 64]; 64] {
     rays
 }
 
-fn manual_mask(squares: Vec<usize>) -> u64 {
-    let mut out: u64 = 0;
-    for sq in squares {
-        out = put_bit(out, sq);
-    }
-    out
-}
-
 pub struct MoveTable {
     pawn: [u64; 64],
     pawn_attack: [u64; 64],
@@ -157,17 +145,8 @@ pub struct MoveTable {
     king: [u64; 64],
 
     fourth_ranks: u64,
+    back_ranks: u64,
     rays: [[u64; 64]; 64],
-
-    w_castle_short: u64,
-    w_castle_long: u64,
-    b_castle_short: u64,
-    b_castle_long: u64,
-
-    w_castle_short_safety: u64,
-    w_castle_long_safety: u64,
-    b_castle_short_safety: u64,
-    b_castle_long_safety: u64,
 }
 
 impl Index<Piece> for MoveTable {
@@ -205,10 +184,13 @@ impl MoveTable {
             fourth_ranks |= put_bit(fourth_ranks, i);
         }
 
-        let ws = manual_mask(vec![W_CASTLE_SHORT, W_CASTLE_SHORT - 1, W_CASTLE_SHORT - 2]);
-        let wl = manual_mask(vec![W_CASTLE_LONG, W_CASTLE_LONG + 1, W_CASTLE_LONG + 2]);
-        let bs = manual_mask(vec![B_CASTLE_SHORT, B_CASTLE_SHORT - 1, B_CASTLE_SHORT - 2]);
-        let bl = manual_mask(vec![B_CASTLE_LONG, B_CASTLE_LONG + 1, B_CASTLE_LONG + 2]);
+        let mut back_ranks = 0;
+        for i in Square::A8 as usize..Square::H8 as usize + 1 {
+            back_ranks |= put_bit(back_ranks, i);
+        }
+        for i in Square::A1 as usize..Square::H1 as usize + 1 {
+            back_ranks |= put_bit(back_ranks, i);
+        }
 
         MoveTable {
             pawn,
@@ -224,17 +206,8 @@ impl MoveTable {
             king: get_king_masks(),
 
             fourth_ranks,
+            back_ranks,
             rays: get_ray_masks(),
-
-            w_castle_short: mask(W_CASTLE_SHORT),
-            w_castle_long: mask(W_CASTLE_LONG),
-            b_castle_short: mask(B_CASTLE_SHORT),
-            b_castle_long: mask(B_CASTLE_LONG),
-
-            w_castle_short_safety: ws,
-            w_castle_long_safety: wl,
-            b_castle_short_safety: bs,
-            b_castle_long_safety: bl,
         }
     }
 
@@ -256,25 +229,12 @@ impl MoveTable {
         self.fourth_ranks
     }
 
-    pub fn get_rays(&self) -> [[u64; 64]; 64] {
-        self.rays
+    /// First and eighth ranks
+    pub fn get_back_ranks(&self) -> u64 {
+        self.back_ranks
     }
 
-    pub fn get_castle(&self, castle_length: Castle, color: Color) -> u64 {
-        match (castle_length, color) {
-            (Castle::Short, Color::White) => self.w_castle_short,
-            (Castle::Long, Color::White) => self.w_castle_long,
-            (Castle::Short, Color::Black) => self.b_castle_short,
-            (Castle::Long, Color::Black) => self.b_castle_long,
-        }
-    }
-
-    pub fn get_castle_safe_squares(&self, castle_length: Castle, color: Color) -> u64 {
-        match (castle_length, color) {
-            (Castle::Short, Color::White) => self.w_castle_short_safety,
-            (Castle::Long, Color::White) => self.w_castle_long_safety,
-            (Castle::Short, Color::Black) => self.b_castle_short_safety,
-            (Castle::Long, Color::Black) => self.b_castle_long_safety,
-        }
+    pub fn get_rays(&self) -> [[u64; 64]; 64] {
+        self.rays
     }
 }
@@ -0,0 +1,293 @@
+use std::fs;
+use std::sync::Arc;
+
+use super::bitboard_util::clear_bit;
+use super::board::Board;
+use super::color::Color;
+use super::piece::{Piece, PIECE_TYPES};
+use super::position::Position;
+
+/// Simple 768 -> Hx2 -> 1 perspective network.
+///
+/// Each side has an accumulator of H neurons fed by 768 piece-square
+/// features (2 colors x 6 pieces x 64 squares) seen from that side,
+/// the side to move's accumulator goes first into the output layer.
+///
+/// File layout (little endian):
+/// magic `TNN1`, u32 hidden size H,
+/// i16 feature weights (768 x H, feature major), i16 feature biases (H),
+/// i16 output weights (2H), i32 output bias
+pub const FEATURE_COUNT: usize = 768;
+
+const MAGIC: &[u8; 4] = b"TNN1";
+
+/// Accumulator values are clipped to [0, QA]
+pub const QA: i32 = 255;
+/// Quantization of the output weights
+pub const QB: i32 = 64;
+/// Output in centipawns is `raw * SCALE / (QA * QB)`
+pub const SCALE: i32 = 400;
+
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_bias: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+/// Hidden layer values from both perspectives
+#[derive(Clone)]
+pub struct Accumulator {
+    pub white: Vec<i16>,
+    pub black: Vec<i16>,
+}
+
+/// Feature index of a piece from each perspective.
+/// Squares are counted from a1 for White and mirrored for Black,
+/// so both perspectives see their own pieces in the first half
+fn feature_indices(piece: Piece, color: Color, index: usize) -> (usize, usize) {
+    let position = Position::index(index);
+    let square = (7 - position.get_row() as usize) * 8 + position.get_col() as usize;
+    let piece_offset = piece as usize * 64;
+
+    let (white_side, black_side) = match color {
+        Color::White => (0, 384),
+        Color::Black => (384, 0),
+    };
+
+    (
+        white_side + piece_offset + square,
+        black_side + piece_offset + (square ^ 56),
+    )
+}
+
+fn read_i16s(bytes: &[u8], offset: &mut usize, count: usize) -> Result<Vec<i16>, String> {
+    let end = *offset + count * 2;
+    if end > bytes.len() {
+        return Err(String::from("Network file is truncated"));
+    }
+    let out = bytes[*offset..end]
+        .chunks_exact(2)
+        .map(|c| i16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    *offset = end;
+    Ok(out)
+}
+
+impl Network {
+    pub fn load(path: &str) -> Result<Network, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        Network::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Network, String> {
+        if bytes.len() < 8 || &bytes[0..4] != MAGIC {
+            return Err(String::from("Not a network file"));
+        }
+        let hidden = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+
+        let mut offset = 8;
+        let feature_weights = read_i16s(bytes, &mut offset, FEATURE_COUNT * hidden)?;
+        let feature_bias = read_i16s(bytes, &mut offset, hidden)?;
+        let output_weights = read_i16s(bytes, &mut offset, 2 * hidden)?;
+
+        if offset + 4 != bytes.len() {
+            return Err(String::from("Network file has the wrong size"));
+        }
+        let b = &bytes[offset..];
+        let output_bias = i32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+
+        Ok(Network {
+            hidden,
+            feature_weights,
+            feature_bias,
+            output_weights,
+            output_bias,
+        })
+    }
+
+    pub fn get_feature_weights(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+
+    /// Build both accumulators from scratch
+    pub fn refresh(&self, board: &Board) -> Accumulator {
+        let mut out = Accumulator {
+            white: self.feature_bias.clone(),
+            black: self.feature_bias.clone(),
+        };
+
+        for color in [Color::White, Color::Black] {
+            for piece in PIECE_TYPES {
+                let mut bitboard = board.get_color_piece_board(piece, color);
+                let mut i = bitboard.leading_zeros();
+                while i < 64 {
+                    self.add_piece(&mut out, piece, color, i as usize);
+                    bitboard = clear_bit(bitboard, i as usize);
+                    i = bitboard.leading_zeros();
+                }
+            }
+        }
+        out
+    }
+
+    pub fn add_piece(&self, accumulator: &mut Accumulator, piece: Piece, color: Color, index: usize) {
+        let (w, b) = feature_indices(piece, color, index);
+        for (a, x) in accumulator.white.iter_mut().zip(self.get_feature_weights(w)) {
+            *a = a.wrapping_add(*x);
+        }
+        for (a, x) in accumulator.black.iter_mut().zip(self.get_feature_weights(b)) {
+            *a = a.wrapping_add(*x);
+        }
+    }
+
+    pub fn remove_piece(&self, accumulator: &mut Accumulator, piece: Piece, color: Color, index: usize) {
+        let (w, b) = feature_indices(piece, color, index);
+        for (a, x) in accumulator.white.iter_mut().zip(self.get_feature_weights(w)) {
+            *a = a.wrapping_sub(*x);
+        }
+        for (a, x) in accumulator.black.iter_mut().zip(self.get_feature_weights(b)) {
+            *a = a.wrapping_sub(*x);
+        }
+    }
+
+    /// Output layer, in centipawns for the side to move
+    pub fn evaluate(&self, accumulator: &Accumulator, turn: Color) -> i32 {
+        let (us, them) = match turn {
+            Color::White => (&accumulator.white, &accumulator.black),
+            Color::Black => (&accumulator.black, &accumulator.white),
+        };
+
+        // each product is up to 255 * 32767, a wide layer overflows i32
+        let mut out: i64 = 0;
+        for (i, a) in us.iter().enumerate() {
+            out += (*a as i64).clamp(0, QA as i64) * self.output_weights[i] as i64;
+        }
+        for (i, a) in them.iter().enumerate() {
+            out += (*a as i64).clamp(0, QA as i64) * self.output_weights[self.hidden + i] as i64;
+        }
+
+        let centipawns = (out + self.output_bias as i64) * SCALE as i64 / (QA * QB) as i64;
+        centipawns.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+    }
+}
+
+/// Accumulators for every position on the executor's move stack
+pub struct AccumulatorStack {
+    network: Arc<Network>,
+    stack: Vec<Accumulator>,
+}
+
+impl AccumulatorStack {
+    pub fn new(network: Arc<Network>, board: &Board) -> Self {
+        let root = network.refresh(board);
+        AccumulatorStack {
+            network,
+            stack: vec![root],
+        }
+    }
+
+    pub fn get_network(&self) -> &Arc<Network> {
+        &self.network
+    }
+
+    pub fn get_current(&self) -> &Accumulator {
+        self.stack.last().unwrap()
+    }
+
+    /// Push the accumulator for `new` by applying only the
+    /// piece-square differences from `old`, which covers castling,
+    /// en passant and promotions without special cases
+    pub fn push(&mut self, old: &Board, new: &Board) {
+        let mut next = self.get_current().clone();
+
+        for color in [Color::White, Color::Black] {
+            for piece in PIECE_TYPES {
+                let before = old.get_color_piece_board(piece, color);
+                let after = new.get_color_piece_board(piece, color);
+
+                let mut removed = before & !after;
+                let mut i = removed.leading_zeros();
+                while i < 64 {
+                    self.network.remove_piece(&mut next, piece, color, i as usize);
+                    removed = clear_bit(removed, i as usize);
+                    i = removed.leading_zeros();
+                }
+
+                let mut added = after & !before;
+                let mut i = added.leading_zeros();
+                while i < 64 {
+                    self.network.add_piece(&mut next, piece, color, i as usize);
+                    added = clear_bit(added, i as usize);
+                    i = added.leading_zeros();
+                }
+            }
+        }
+        self.stack.push(next);
+    }
+
+    pub fn pop(&mut self) {
+        self.stack.pop();
+    }
+
+    /// True once moves made before the stack was created are undone
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::move_api::MoveAPI;
+
+    /// Reference evaluations of nets/tiny.nnue, computed by nets/make_tiny.py
+    const NNUE_REFERENCE: [(&str, i32); 3] = [
+        ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", -44),
+        ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", -282),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1", 146),
+    ];
+
+    /// Count positions where the incrementally updated
+    /// accumulator differs from one built from scratch
+    fn tree_mismatches(network: &Network, move_api: &mut MoveAPI, depth: u64) -> u64 {
+        let accumulators = move_api.get_accumulators().unwrap();
+        let turn = move_api.get_turn_color();
+        let incremental = network.evaluate(accumulators.get_current(), turn);
+        let refreshed = network.evaluate(&network.refresh(move_api.get_board_ref()), turn);
+
+        let mut out = (incremental != refreshed) as u64;
+        if depth == 0 {
+            return out;
+        }
+
+        for m in move_api.get_legal_moves() {
+            move_api.exec_move(m);
+            out += tree_mismatches(network, move_api, depth - 1);
+            move_api.undo_move();
+        }
+        out
+    }
+
+    #[test]
+    fn network_matches_reference_evaluations() {
+        let network = Arc::new(Network::load("nets/tiny.nnue").unwrap());
+        for (fen, expected) in NNUE_REFERENCE {
+            let mut move_api = MoveAPI::new(fen);
+            move_api.enable_nnue(network.clone());
+            let actual = network.evaluate(move_api.get_accumulators().unwrap().get_current(), move_api.get_turn_color());
+            assert_eq!(actual, expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_refreshes() {
+        let network = Arc::new(Network::load("nets/tiny.nnue").unwrap());
+        for (fen, _) in NNUE_REFERENCE {
+            let mut move_api = MoveAPI::new(fen);
+            move_api.enable_nnue(network.clone());
+            assert_eq!(tree_mismatches(&network, &mut move_api, 3), 0, "{}", fen);
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use super::bitboard_util::mask;
+use super::board::Board;
+use super::cmove::Move;
+use super::move_api::MoveAPI;
+use super::color::Color;
+use super::piece::{fen_to_piece, piece_to_fen, Piece};
+use super::position::Position;
+
+/// Game result tokens that end the movetext
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// Longest movetext line written, as the PGN standard asks
+const MAX_LINE_LENGTH: usize = 79;
+
+/// A game read from a PGN file: its tag pairs
+/// and its main line as SAN strings, variations and comments dropped
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<String>,
+}
+
+impl PgnGame {
+    pub fn get_tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// White's score: 1 for a win, 0.5 for a draw, 0 for a loss,
+    /// None for unfinished games
+    pub fn get_white_score(&self) -> Option<f64> {
+        match self.get_tag("Result") {
+            Some("1-0") => Some(1.0),
+            Some("0-1") => Some(0.0),
+            Some("1/2-1/2") => Some(0.5),
+            _ => None,
+        }
+    }
+
+    /// The game as PGN text: its tags, then its moves numbered from the
+    /// FEN tag's position if there is one, then the Result tag
+    pub fn to_pgn(&self) -> String {
+        let mut out = String::new();
+        for (name, value) in &self.tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            out.push_str(&format!("[{} \"{}\"]\n", name, value));
+        }
+        out.push('\n');
+
+        let (mut number, mut turn) = match self.get_tag("FEN") {
+            Some(fen) => {
+                let board = Board::new(fen);
+                (board.fullmove_clock.max(1) as u32, board.turn)
+            }
+            None => (1, Color::White),
+        };
+
+        let mut tokens = Vec::new();
+        for (i, san) in self.moves.iter().enumerate() {
+            match turn {
+                Color::White => tokens.push(format!("{}.", number)),
+                Color::Black if i == 0 => tokens.push(format!("{}...", number)),
+                Color::Black => {}
+            }
+            tokens.push(san.clone());
+            if turn == Color::Black {
+                number += 1;
+            }
+            turn = turn.get_opposite();
+        }
+        tokens.push(self.get_tag("Result").unwrap_or("*").to_string());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+                out.push_str(&line);
+                out.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        out.push_str(&line);
+        out.push_str("\n\n");
+        out
+    }
+}
+
+/// Split the text of a PGN file into games
+pub fn parse_games(text: &str) -> Vec<PgnGame> {
+    let mut out = Vec::new();
+    let mut tags = Vec::new();
+    let mut movetext = String::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+
+        // a tag after movetext starts the next game
+        if line.starts_with('[') {
+            if !movetext.trim().is_empty() {
+                out.push(PgnGame {
+                    tags: std::mem::take(&mut tags),
+                    moves: parse_movetext(&movetext),
+                });
+                movetext.clear();
+            }
+            if let Some(tag) = parse_tag(line) {
+                tags.push(tag);
+            }
+        } else if !line.starts_with('%') {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+
+    if !movetext.trim().is_empty() || !tags.is_empty() {
+        out.push(PgnGame {
+            tags,
+            moves: parse_movetext(&movetext),
+        });
+    }
+    out
+}
+
+/// `[Name "Value"]`, with `\"` and `\\` escapes in the value
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, rest) = inner.split_once(char::is_whitespace)?;
+    let quoted = rest.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let value = quoted.replace("\\\"", "\"").replace("\\\\", "\\");
+    Some((name.to_string(), value))
+}
+
+/// Main line moves of a movetext section
+fn parse_movetext(movetext: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut variation_depth = 0;
+    let mut chars = movetext.chars().peekable();
+    let mut token = String::new();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' => {
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' => variation_depth += 1,
+            // a stray ')' must not hide the rest of the main line
+            ')' => variation_depth = (variation_depth - 1).max(0),
+            c if c.is_whitespace() => {}
+            c => {
+                token.push(c);
+                let is_end = chars
+                    .peek()
+                    .is_none_or(|next| next.is_whitespace() || "{;()".contains(*next));
+                if !is_end {
+                    continue;
+                }
+
+                if variation_depth == 0 {
+                    if let Some(san) = clean_token(&token) {
+                        out.push(san);
+                    }
+                }
+                token.clear();
+            }
+        }
+    }
+    out
+}
+
+/// SAN of a movetext token, None for move numbers, NAGs and results
+fn clean_token(token: &str) -> Option<String> {
+    if token.starts_with('$') || RESULTS.contains(&token) || token == "e.p." {
+        return None;
+    }
+
+    // "12." and "12..." number moves, "12.e4" is also seen in the wild.
+    // Digits without a dot start castling written with zeros, like "0-0"
+    let after_number = token.trim_start_matches(|c: char| c.is_ascii_digit());
+    let san = match after_number.strip_prefix('.') {
+        Some(rest) => rest.trim_start_matches('.'),
+        None if after_number.is_empty() => after_number,
+        None => token,
+    };
+    if san.is_empty() {
+        return None;
+    }
+    Some(san.to_string())
+}
+
+/// Whether a legal king move castles. Standard boards encode castling
+/// as the king moving two files, Chess960 boards as the king moving onto its rook
+fn is_castle(board: &Board, m: &Move) -> bool {
+    if m.piece != Piece::King {
+        return false;
+    }
+    let own_rooks = board.get_color_piece_board(Piece::Rook, board.turn);
+    (m.end.get_col() - m.start.get_col()).abs() == 2 || own_rooks & mask(m.end.to_index()) != 0
+}
+
+/// The legal move written as `san` in the current position
+pub fn find_san_move(move_api: &mut MoveAPI, san: &str) -> Option<Move> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let board = *move_api.get_board_ref();
+    let legal_moves = move_api.get_legal_moves();
+
+    let castle_side = match san {
+        "O-O" | "0-0" => Some(true),
+        "O-O-O" | "0-0-0" => Some(false),
+        _ => None,
+    };
+    if let Some(is_short) = castle_side {
+        return legal_moves
+            .into_iter()
+            .find(|m| is_castle(&board, m) && (m.end.get_col() > m.start.get_col()) == is_short);
+    }
+
+    // split off the promotion, written "e8=Q" or "e8Q"
+    let (body, promotion) = match san.char_indices().last() {
+        Some((i, c)) if "QRBNK".contains(c) && i > 0 => (san[..i].trim_end_matches('='), Some(fen_to_piece(c).0)),
+        _ => (san, None),
+    };
+
+    let (piece, rest) = match body.chars().next() {
+        Some(c) if "KQRBN".contains(c) => (fen_to_piece(c).0, &body[1..]),
+        Some(_) => (Piece::Pawn, body),
+        None => return None,
+    };
+
+    let rest: String = rest.chars().filter(|c| *c != 'x' && *c != '-' && *c != ':').collect();
+    if rest.len() < 2 || !rest.is_char_boundary(rest.len() - 2) {
+        return None;
+    }
+    let (hint, target) = rest.split_at(rest.len() - 2);
+    let target = parse_square(target)?;
+
+    let candidates: Vec<Move> = legal_moves
+        .into_iter()
+        .filter(|m| {
+            m.piece == piece
+                && !m.is_drop
+                && !is_castle(&board, m)
+                && m.get_landing(board.turn) == target
+                && m.promotion == promotion
+                && matches_hint(m.start, hint)
+        })
+        .collect();
+
+    // an ambiguous move is as bad as a missing one
+    match candidates[..] {
+        [m] => Some(m),
+        _ => None,
+    }
+}
+
+/// SAN of a legal move in the current position, like Nbd7, exd6, e8=Q+,
+/// O-O or N@f3 for drops
+pub fn get_san(move_api: &mut MoveAPI, m: &Move) -> String {
+    let board = *move_api.get_board_ref();
+    let legal_moves = move_api.get_legal_moves();
+
+    let mut san = if m.is_drop {
+        format!("{}@{}", piece_to_fen(m.piece, Color::White), m.end.to_string())
+    } else if is_castle(&board, m) {
+        let is_short = m.end.get_col() > m.start.get_col();
+        (if is_short { "O-O" } else { "O-O-O" }).to_string()
+    } else {
+        let landing = m.get_landing(board.turn);
+        let is_capture = board.get_pieces(board.turn.get_opposite()) & mask(landing.to_index()) != 0 || landing != m.end;
+        let mut out = String::new();
+
+        if m.piece == Piece::Pawn {
+            if is_capture {
+                out.push_str(&m.start.to_string()[..1]);
+            }
+        } else {
+            out.push(piece_to_fen(m.piece, Color::White));
+            out.push_str(&get_disambiguation(&board, m, &legal_moves));
+        }
+        if is_capture {
+            out.push('x');
+        }
+        out.push_str(&landing.to_string());
+        if let Some(promotion) = m.promotion {
+            out.push('=');
+            out.push(piece_to_fen(promotion, Color::White));
+        }
+        out
+    };
+
+    move_api.exec_move(*m);
+    if move_api.is_in_check() {
+        san.push(if move_api.get_legal_moves().is_empty() { '#' } else { '+' });
+    }
+    move_api.undo_move();
+    san
+}
+
+/// File, rank or square of the start square, as much as it takes to tell
+/// the move apart from other moves of the same piece to the same square
+fn get_disambiguation(board: &Board, m: &Move, legal_moves: &[Move]) -> String {
+    let landing = m.get_landing(board.turn);
+    let others: Vec<Position> = legal_moves
+        .iter()
+        .filter(|other| {
+            other.piece == m.piece
+                && !other.is_drop
+                && other.start != m.start
+                && !is_castle(board, other)
+                && other.get_landing(board.turn) == landing
+        })
+        .map(|other| other.start)
+        .collect();
+
+    let square = m.start.to_string();
+    if others.is_empty() {
+        String::new()
+    } else if others.iter().all(|other| other.get_col() != m.start.get_col()) {
+        square[..1].to_string()
+    } else if others.iter().all(|other| other.get_row() != m.start.get_row()) {
+        square[1..].to_string()
+    } else {
+        square
+    }
+}
+
+/// Whether `start` fits a disambiguation hint like "b", "1" or "b1"
+fn matches_hint(start: Position, hint: &str) -> bool {
+    let square = start.to_string();
+    hint.chars().all(|c| square.contains(c))
+}
+
+/// "e4" to a Position, without going through the logging in position.rs
+fn parse_square(square: &str) -> Option<Position> {
+    let mut chars = square.chars();
+    let file = chars.next()?;
+    let rank = chars.next()?;
+    if !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    let col = file as i8 - 'a' as i8;
+    let row = 8 - (rank as i8 - '0' as i8);
+    Some(Position::new(row, col))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn movetext_keeps_castling_with_zeros() {
+        let moves = parse_movetext("1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. 0-0 Nf6 5.0-0-0+ 1-0");
+        assert_eq!(moves, ["e4", "e5", "Nf3", "Nc6", "Bc4", "Bc5", "0-0", "Nf6", "0-0-0+"]);
+    }
+
+    #[test]
+    fn movetext_survives_stray_closing_parenthesis() {
+        let moves = parse_movetext("1. e4 e5 ) 2. Nf3 (2. d4 exd4) Nc6 *");
+        assert_eq!(moves, ["e4", "e5", "Nf3", "Nc6"]);
+    }
+}
@@ -11,6 +11,16 @@ pub enum Piece {
     King,
 }
 
+/// Every piece type, in the order of the enum
+pub const PIECE_TYPES: [Piece; 6] = [
+    Piece::Pawn,
+    Piece::Knight,
+    Piece::Bishop,
+    Piece::Rook,
+    Piece::Queen,
+    Piece::King,
+];
+
 pub fn get_piece_value(piece: Piece) -> f64 {
     match piece {
         Piece::Pawn => 1f64,
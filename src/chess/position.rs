@@ -92,8 +92,7 @@ pub fn position_to_algebraic(position: &Position) -> String {
     out
 }
 
-pub fn algebraic_to_position(algebraic: &String) -> Position {
-    println!("{}", algebraic);
+pub fn algebraic_to_position(algebraic: &str) -> Position {
     let convert = get_notation_map();
     let col_char = algebraic.chars().next().unwrap();
     let col_digit = convert.get(&col_char).unwrap().to_digit(10).unwrap();
@@ -0,0 +1,1640 @@
+//! Probing of Syzygy endgame tablebases.
+//!
+//! A table covers one material signature like KQvKR: the `.rtbw` file holds
+//! win/draw/loss for every position, the `.rtbz` file the distance to the
+//! next capture or pawn move (DTZ) that keeps the result. Files are only
+//! listed when the directory is opened and read the first time they are probed.
+//!
+//! Inside the files squares count from a1 = 0 to h8 = 63 and pieces are
+//! numbered 1 to 6 (pawn to king) for White, 9 to 14 for Black.
+//! The layout and the indexing scheme follow the reference prober
+//! that ships with the tables.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use super::bitboard_util::{clear_bit, mask};
+use super::board::{Board, Castle};
+use super::cmove::Move;
+use super::color::Color;
+use super::move_api::MoveAPI;
+use super::piece::{Piece, PIECE_TYPES};
+use super::variant::{Outcome, Variant};
+
+/// Directory the server, xboard and syzygy-probe read tables from when none is named
+pub const DEFAULT_SYZYGY_DIR: &str = "syzygy";
+
+/// Largest tables the format has, 7 pieces
+const TB_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+/// Flags of a PairsData record
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/// Pieces in the order they are written in table names
+const NAME_ORDER: [(Piece, char); 6] = [
+    (Piece::King, 'K'),
+    (Piece::Queen, 'Q'),
+    (Piece::Rook, 'R'),
+    (Piece::Bishop, 'B'),
+    (Piece::Knight, 'N'),
+    (Piece::Pawn, 'P'),
+];
+
+/// Win/draw/loss for the side to move. Cursed wins and blessed losses
+/// are wins and losses that the fifty move rule turns into draws
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    pub fn get_name(self) -> &'static str {
+        match self {
+            Wdl::Loss => "loss",
+            Wdl::BlessedLoss => "blessed loss",
+            Wdl::Draw => "draw",
+            Wdl::CursedWin => "cursed win",
+            Wdl::Win => "win",
+        }
+    }
+}
+
+/// Extra information a table probe gives besides its value
+#[derive(Copy, Clone, PartialEq)]
+enum ProbeState {
+    Ok,
+    /// A DTZ table is stored for the other side to move
+    ChangeStm,
+    /// The best move is a capture or pawn move, for which DTZ tables store nothing
+    ZeroingBestMove,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum TableKind {
+    Wdl,
+    Dtz,
+}
+
+impl TableKind {
+    fn get_extension(self) -> &'static str {
+        match self {
+            TableKind::Wdl => "rtbw",
+            TableKind::Dtz => "rtbz",
+        }
+    }
+}
+
+/// Lookup tables of the indexing scheme, the same for every table
+struct Indexing {
+    map_pawns: [usize; 64],
+    map_b1h1h7: [usize; 64],
+    map_a1d1d4: [usize; 64],
+    map_kk: [[usize; 64]; 10],
+    binomial: [[u64; 64]; TB_PIECES],
+    lead_pawn_idx: [[u64; 64]; TB_PIECES],
+    lead_pawns_size: [[u64; 4]; TB_PIECES],
+}
+
+fn file_of(square: usize) -> usize {
+    square & 7
+}
+
+fn rank_of(square: usize) -> usize {
+    square >> 3
+}
+
+fn flip_file(square: usize) -> usize {
+    square ^ 7
+}
+
+fn flip_rank(square: usize) -> usize {
+    square ^ 56
+}
+
+/// Negative below the a1-h8 diagonal, zero on it
+fn off_a1h8(square: usize) -> i32 {
+    rank_of(square) as i32 - file_of(square) as i32
+}
+
+fn is_king_distance_one(a: usize, b: usize) -> bool {
+    let files = (file_of(a) as i32 - file_of(b) as i32).abs();
+    let ranks = (rank_of(a) as i32 - rank_of(b) as i32).abs();
+    files.max(ranks) <= 1
+}
+
+impl Indexing {
+    fn new() -> Indexing {
+        let mut out = Indexing {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; TB_PIECES],
+            lead_pawn_idx: [[0; 64]; TB_PIECES],
+            lead_pawns_size: [[0; 4]; TB_PIECES],
+        };
+
+        // squares below the a1-h8 diagonal to 0..27
+        let mut code = 0;
+        for s in 0..64 {
+            if off_a1h8(s) < 0 {
+                out.map_b1h1h7[s] = code;
+                code += 1;
+            }
+        }
+
+        // the a1-d1-d4 triangle to 0..9, diagonal squares last
+        let mut diagonal = Vec::new();
+        code = 0;
+        for s in 0..28 {
+            if off_a1h8(s) < 0 && file_of(s) <= 3 {
+                out.map_a1d1d4[s] = code;
+                code += 1;
+            } else if off_a1h8(s) == 0 && file_of(s) <= 3 {
+                diagonal.push(s);
+            }
+        }
+        for s in diagonal {
+            out.map_a1d1d4[s] = code;
+            code += 1;
+        }
+
+        // the 462 king pairs with the first king in the triangle. With the
+        // first king on the diagonal the second one is not above it,
+        // pairs with both kings on the diagonal go last
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                if out.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    if is_king_distance_one(s1, s2) || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+                        continue;
+                    } else if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        out.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            out.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        // binomial[k][n]: ways to choose k squares out of n
+        out.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..TB_PIECES.min(n + 1) {
+                out.binomial[k][n] = if k > 0 { out.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { out.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // map_pawns counts the squares left to the other pawns when the
+        // leading pawn stands on a square, the leading pawn is the
+        // one with the highest value: nearest the edge, then lowest rank
+        let mut available = 47;
+        for lead_pawns in 1..TB_PIECES {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let square = 8 * rank + file;
+                    if lead_pawns == 1 {
+                        out.map_pawns[square] = available;
+                        available -= 1;
+                        out.map_pawns[flip_file(square)] = available;
+                        available = available.saturating_sub(1);
+                    }
+                    out.lead_pawn_idx[lead_pawns][square] = idx;
+                    idx += out.binomial[lead_pawns - 1][out.map_pawns[square]];
+                }
+                out.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        out
+    }
+}
+
+fn indexing() -> &'static Indexing {
+    static INDEXING: OnceLock<Indexing> = OnceLock::new();
+    INDEXING.get_or_init(Indexing::new)
+}
+
+/// Decoding information for one side to move and one leading pawn file
+/// of a table. Offsets point into the bytes of the table file
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    min_sym_len: u8,
+    num_blocks: usize,
+    block_size: usize,
+    span: usize,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    block_length_size: usize,
+    data: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    pieces: [u8; TB_PIECES],
+    group_idx: [u64; TB_PIECES + 1],
+    group_len: [usize; TB_PIECES + 1],
+    /// Offsets of the DTZ value maps for win, loss, cursed win and blessed loss
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    /// Positions the record holds
+    fn get_size(&self) -> usize {
+        let groups = self.group_len.iter().position(|len| *len == 0).unwrap_or(TB_PIECES);
+        self.group_idx[groups] as usize
+    }
+}
+
+/// A table file read into memory
+struct TableFile {
+    bytes: Vec<u8>,
+    /// `[side to move][leading pawn file]`
+    items: Vec<Vec<PairsData>>,
+}
+
+/// Material of one table, with its files loaded on first use
+struct TableInfo {
+    name: String,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    /// Pawns of the leading color then of the other one
+    pawn_count: [usize; 2],
+    /// Both sides have the same material, only White to move is stored
+    is_symmetric: bool,
+    wdl: OnceLock<Option<TableFile>>,
+    dtz: OnceLock<Option<TableFile>>,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u32_be(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u64_be(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+/// Left (first 12 bits) and right (last 12 bits) child of a btree symbol
+fn get_children(bytes: &[u8], btree: usize, sym: usize) -> Option<(usize, usize)> {
+    let lr = bytes.get(btree + 3 * sym..btree + 3 * sym + 3)?;
+    let left = (((lr[1] & 0xF) as usize) << 8) | lr[0] as usize;
+    let right = ((lr[2] as usize) << 4) | (lr[1] >> 4) as usize;
+    Some((left, right))
+}
+
+impl TableInfo {
+    /// Table of a name like "KRPvKR", the first side is White in the file
+    fn new(name: &str) -> Option<TableInfo> {
+        let (white, black) = name.split_once('v')?;
+        let count = |side: &str, letter: char| side.chars().filter(|c| *c == letter).count();
+        if count(white, 'K') != 1 || count(black, 'K') != 1 {
+            return None;
+        }
+        if !name.chars().all(|c| c == 'v' || NAME_ORDER.iter().any(|(_, l)| *l == c)) {
+            return None;
+        }
+
+        let has_unique_pieces = [white, black]
+            .iter()
+            .any(|side| "QRBNP".chars().any(|letter| count(side, letter) == 1));
+
+        // the leading color has fewer pawns, it compresses better
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let is_white_leading = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        let pawn_count = if is_white_leading {
+            [white_pawns, black_pawns]
+        } else {
+            [black_pawns, white_pawns]
+        };
+
+        Some(TableInfo {
+            name: name.to_string(),
+            piece_count: name.len() - 1,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count,
+            is_symmetric: white == black,
+            wdl: OnceLock::new(),
+            dtz: OnceLock::new(),
+        })
+    }
+
+    fn get_sides(&self, kind: TableKind) -> usize {
+        if kind == TableKind::Wdl && !self.is_symmetric {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn get_file(&self, dir: &Path, kind: TableKind) -> Option<&TableFile> {
+        let cell = match kind {
+            TableKind::Wdl => &self.wdl,
+            TableKind::Dtz => &self.dtz,
+        };
+        cell.get_or_init(|| {
+            let path = dir.join(format!("{}.{}", self.name, kind.get_extension()));
+            let bytes = fs::read(&path).ok()?;
+            let file = self.parse(bytes, kind);
+            if file.is_none() {
+                eprintln!("Could not read tablebase {}", path.display());
+            }
+            file
+        })
+        .as_ref()
+    }
+
+    /// Read the headers of a table file
+    fn parse(&self, bytes: Vec<u8>, kind: TableKind) -> Option<TableFile> {
+        let magic = match kind {
+            TableKind::Wdl => WDL_MAGIC,
+            TableKind::Dtz => DTZ_MAGIC,
+        };
+        if bytes.len() % 64 != 16 || bytes[0..4] != magic {
+            return None;
+        }
+
+        let flags = bytes[4];
+        if (flags & 2 != 0) != self.has_pawns || (flags & 1 != 0) == self.is_symmetric {
+            return None;
+        }
+
+        let sides = self.get_sides(kind);
+        let files = if self.has_pawns { 4 } else { 1 };
+        let both_have_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut items = vec![vec![PairsData::default(); files]; sides];
+        let mut offset = 5;
+
+        for file in 0..files {
+            let order_byte = *bytes.get(offset)?;
+            let second_byte = if both_have_pawns { *bytes.get(offset + 1)? } else { 0xFF };
+            let order = [
+                [(order_byte & 0xF) as usize, (second_byte & 0xF) as usize],
+                [(order_byte >> 4) as usize, (second_byte >> 4) as usize],
+            ];
+            offset += 1 + both_have_pawns as usize;
+
+            for k in 0..self.piece_count {
+                let byte = *bytes.get(offset)?;
+                for (side, item) in items.iter_mut().enumerate() {
+                    item[file].pieces[k] = if side == 0 { byte & 0xF } else { byte >> 4 };
+                }
+                offset += 1;
+            }
+            for (side, item) in items.iter_mut().enumerate() {
+                self.set_groups(&mut item[file], order[side], file);
+            }
+        }
+        offset += offset & 1;
+
+        for file in 0..files {
+            for item in items.iter_mut() {
+                offset = set_sizes(&bytes, &mut item[file], offset)?;
+            }
+        }
+
+        if kind == TableKind::Dtz {
+            let map = &mut items[0];
+            for data in map.iter_mut() {
+                if data.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                if data.flags & FLAG_WIDE != 0 {
+                    offset += offset & 1;
+                    for i in 0..4 {
+                        data.map_idx[i] = offset + 2;
+                        offset += 2 * read_u16(&bytes, offset)? as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        data.map_idx[i] = offset + 1;
+                        offset += *bytes.get(offset)? as usize + 1;
+                    }
+                }
+            }
+            offset += offset & 1;
+        }
+
+        for file in 0..files {
+            for item in items.iter_mut() {
+                item[file].sparse_index = offset;
+                offset += 6 * item[file].sparse_index_size;
+            }
+        }
+        for file in 0..files {
+            for item in items.iter_mut() {
+                item[file].block_length = offset;
+                offset += 2 * item[file].block_length_size;
+            }
+        }
+        for file in 0..files {
+            for item in items.iter_mut() {
+                offset = (offset + 0x3F) & !0x3F;
+                item[file].data = offset;
+                offset += item[file].num_blocks * item[file].block_size;
+            }
+        }
+
+        if offset > bytes.len() {
+            return None;
+        }
+        Some(TableFile { bytes, items })
+    }
+
+    /// Split the pieces into groups encoded together: the leading group,
+    /// the other side's pawns, then each run of equal pieces.
+    /// KRvKN groups as KRK + N, KNNvK as KK + NN, KPPvKP as P + PP + K + K
+    fn set_groups(&self, data: &mut PairsData, order: [usize; 2], file: usize) {
+        let tables = indexing();
+        let mut n = 0;
+        let mut first_len: i32 = if self.has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        data.group_len[0] = 1;
+
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || data.pieces[i] == data.pieces[i - 1] {
+                data.group_len[n] += 1;
+            } else {
+                n += 1;
+                data.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        data.group_len[n] = 0;
+
+        // the groups are combined in a per-table order: the leading
+        // group sits at order[0] and the other pawns at order[1]
+        let both_have_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if both_have_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - data.group_len[0] - if both_have_pawns { data.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                data.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    tables.lead_pawns_size[data.group_len[0]][file]
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                data.group_idx[1] = idx;
+                idx *= tables.binomial[data.group_len[1]][48 - data.group_len[0]];
+            } else {
+                data.group_idx[next] = idx;
+                idx *= tables.binomial[data.group_len[next]][free_squares];
+                free_squares -= data.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        data.group_idx[n] = idx;
+    }
+}
+
+/// Read the Huffman and block layout of one PairsData record,
+/// returns the offset right after it
+fn set_sizes(bytes: &[u8], data: &mut PairsData, mut offset: usize) -> Option<usize> {
+    data.flags = *bytes.get(offset)?;
+    offset += 1;
+
+    if data.flags & FLAG_SINGLE_VALUE != 0 {
+        // every position has the same value, kept in min_sym_len
+        data.min_sym_len = *bytes.get(offset)?;
+        return Some(offset + 1);
+    }
+
+    let table_size = data.get_size();
+
+    data.block_size = 1 << *bytes.get(offset)?;
+    data.span = 1 << *bytes.get(offset + 1)?;
+    data.sparse_index_size = table_size.div_ceil(data.span);
+    let padding = *bytes.get(offset + 2)? as usize;
+    data.num_blocks = read_u32(bytes, offset + 3)? as usize;
+    data.block_length_size = data.num_blocks + padding;
+    let max_sym_len = *bytes.get(offset + 7)?;
+    data.min_sym_len = *bytes.get(offset + 8)?;
+    offset += 9;
+    if max_sym_len < data.min_sym_len || max_sym_len > 32 {
+        return None;
+    }
+
+    // canonical Huffman code: longer symbols have lower values, base64[i]
+    // is the lowest symbol of length min_sym_len + i padded to 64 bits
+    data.lowest_sym = offset;
+    let lengths = (max_sym_len - data.min_sym_len + 1) as usize;
+    data.base64 = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        let lowest = read_u16(bytes, offset + 2 * i)? as u64;
+        let next_lowest = read_u16(bytes, offset + 2 * i + 2)? as u64;
+        data.base64[i] = (data.base64[i + 1] + lowest).checked_sub(next_lowest)? / 2;
+    }
+    for (i, base) in data.base64.iter_mut().enumerate() {
+        *base <<= 64 - i - data.min_sym_len as usize;
+    }
+    offset += 2 * lengths;
+
+    // recursive pairing: every symbol stands for a pair of symbols,
+    // symlen is the number of values a symbol expands to, minus one
+    let symbols = read_u16(bytes, offset)? as usize;
+    offset += 2;
+    data.btree = offset;
+    data.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            data.symlen[sym] = set_symlen(bytes, data, sym, &mut visited)?;
+        }
+    }
+
+    Some(offset + 3 * symbols + (symbols & 1))
+}
+
+fn set_symlen(bytes: &[u8], data: &mut PairsData, sym: usize, visited: &mut Vec<bool>) -> Option<u8> {
+    visited[sym] = true;
+    let (left, right) = get_children(bytes, data.btree, sym)?;
+    if right == 0xFFF {
+        return Some(0);
+    }
+    if left >= data.symlen.len() || right >= data.symlen.len() {
+        return None;
+    }
+
+    if !visited[left] {
+        data.symlen[left] = set_symlen(bytes, data, left, visited)?;
+    }
+    if !visited[right] {
+        data.symlen[right] = set_symlen(bytes, data, right, visited)?;
+    }
+    Some(data.symlen[left].wrapping_add(data.symlen[right]).wrapping_add(1))
+}
+
+/// Value stored at position `idx` of a record
+fn decompress_pairs(bytes: &[u8], data: &PairsData, idx: u64) -> Option<i32> {
+    if data.flags & FLAG_SINGLE_VALUE != 0 {
+        return Some(data.min_sym_len as i32);
+    }
+
+    // sparse index entry k holds the block and the offset
+    // in that block of value k * span + span / 2
+    let span = data.span as u64;
+    let k = (idx / span) as usize;
+    let entry = data.sparse_index + 6 * k;
+    let mut block = read_u32(bytes, entry)? as usize;
+    let mut offset = read_u16(bytes, entry + 4)? as i64;
+    offset += (idx % span) as i64 - (span / 2) as i64;
+
+    let block_length = |block: usize| read_u16(bytes, data.block_length + 2 * block).map(|len| len as i64);
+    while offset < 0 {
+        block = block.checked_sub(1)?;
+        offset += block_length(block)? + 1;
+    }
+    while offset > block_length(block)? {
+        offset -= block_length(block)? + 1;
+        block += 1;
+    }
+
+    // walk the symbols of the block until the one holding our value
+    let mut ptr = data.data + block * data.block_size;
+    let mut buf64 = read_u64_be(bytes, ptr)?;
+    ptr += 8;
+    let mut buf64_size = 64;
+    let mut sym;
+
+    loop {
+        let mut len = 0;
+        while buf64 < data.base64[len] {
+            len += 1;
+            if len >= data.base64.len() {
+                return None;
+            }
+        }
+        sym = ((buf64 - data.base64[len]) >> (64 - len - data.min_sym_len as usize)) as usize;
+        sym += read_u16(bytes, data.lowest_sym + 2 * len)? as usize;
+        if sym >= data.symlen.len() {
+            return None;
+        }
+
+        if offset < data.symlen[sym] as i64 + 1 {
+            break;
+        }
+        offset -= data.symlen[sym] as i64 + 1;
+        let bits = len + data.min_sym_len as usize;
+        buf64 <<= bits;
+        buf64_size -= bits as i32;
+
+        if buf64_size <= 32 {
+            buf64_size += 32;
+            buf64 |= (read_u32_be(bytes, ptr)? as u64) << (64 - buf64_size);
+            ptr += 4;
+        }
+    }
+
+    // expand the symbol down to the leaf holding the value,
+    // the values of a pair are the left ones then the right ones
+    while data.symlen[sym] != 0 {
+        let (left, right) = get_children(bytes, data.btree, sym)?;
+        if offset < data.symlen[left] as i64 + 1 {
+            sym = left;
+        } else {
+            offset -= data.symlen[left] as i64 + 1;
+            sym = right;
+        }
+    }
+    get_children(bytes, data.btree, sym).map(|(left, _)| left as i32)
+}
+
+/// A Syzygy table directory
+pub struct Tablebases {
+    dir: PathBuf,
+    tables: HashMap<String, TableInfo>,
+    max_pieces: usize,
+}
+
+/// Piece number used in table files
+fn piece_code(piece: Piece, color: Color) -> u8 {
+    let code = match piece {
+        Piece::Pawn => 1,
+        Piece::Knight => 2,
+        Piece::Bishop => 3,
+        Piece::Rook => 4,
+        Piece::Queen => 5,
+        Piece::King => 6,
+    };
+    match color {
+        Color::White => code,
+        Color::Black => code + 8,
+    }
+}
+
+/// Table file square of every piece of `color` and type `piece`, lowest first
+fn get_squares(board: &Board, piece: Piece, color: Color) -> Vec<usize> {
+    let mut out = Vec::new();
+    let mut bitboard = board.get_color_piece_board(piece, color);
+    let mut i = bitboard.leading_zeros();
+    while i < 64 {
+        out.push(i as usize ^ 56);
+        bitboard = clear_bit(bitboard, i as usize);
+        i = bitboard.leading_zeros();
+    }
+    out.sort();
+    out
+}
+
+/// Material of one side as written in table names, like "KRP"
+fn get_side_name(board: &Board, color: Color) -> String {
+    NAME_ORDER
+        .iter()
+        .map(|(piece, letter)| {
+            let count = board.get_color_piece_board(*piece, color).count_ones() as usize;
+            letter.to_string().repeat(count)
+        })
+        .collect()
+}
+
+fn is_capture(board: &Board, m: &Move) -> bool {
+    board.get_pieces(board.turn.get_opposite()) & mask(m.end.to_index()) != 0
+}
+
+fn is_zeroing(board: &Board, m: &Move) -> bool {
+    m.piece == Piece::Pawn || is_capture(board, m)
+}
+
+fn sign_of(value: i32) -> i32 {
+    value.signum()
+}
+
+/// DTZ of the position before a capture or pawn move that gives `wdl`
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0,
+    }
+}
+
+impl Tablebases {
+    /// List the tables of `dir`. A missing directory gives no tables
+    pub fn open(dir: &str) -> Tablebases {
+        let mut tables = HashMap::new();
+        let mut max_pieces = 0;
+
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                let name = match file_name.strip_suffix(".rtbw") {
+                    Some(name) => name,
+                    None => continue,
+                };
+                if let Some(table) = TableInfo::new(name) {
+                    max_pieces = max_pieces.max(table.piece_count);
+                    tables.insert(name.to_string(), table);
+                }
+            }
+        }
+
+        Tablebases {
+            dir: PathBuf::from(dir),
+            tables,
+            max_pieces,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// Piece count of the largest table found, 0 without tables
+    pub fn get_max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Whether the tables can answer for this board: standard rules,
+    /// no castling rights left and few enough pieces
+    pub fn can_probe(&self, board: &Board) -> bool {
+        let castles = [
+            (Castle::Short, Color::White),
+            (Castle::Long, Color::White),
+            (Castle::Short, Color::Black),
+            (Castle::Long, Color::Black),
+        ];
+        board.variant == Variant::Standard
+            && castles.iter().all(|(castle, color)| !board.get_castle(*castle, *color))
+            && ((board.white_pieces | board.black_pieces).count_ones() as usize) <= self.max_pieces
+    }
+
+    /// Win, draw or loss for the side to move,
+    /// None when no table covers the position
+    pub fn probe_wdl(&self, move_api: &mut MoveAPI) -> Option<Wdl> {
+        if !self.can_probe(move_api.get_board_ref()) {
+            return None;
+        }
+        self.search(move_api, false).map(|(wdl, _)| Wdl::from_value(wdl))
+    }
+
+    /// Plies to the next capture or pawn move that keeps the result,
+    /// positive when the side to move wins, negative when it loses
+    /// and 0 for draws. Cursed wins and blessed losses count 100 more
+    pub fn probe_dtz(&self, move_api: &mut MoveAPI) -> Option<i32> {
+        if !self.can_probe(move_api.get_board_ref()) {
+            return None;
+        }
+        self.get_dtz(move_api)
+    }
+
+    /// DTZ of every legal move counted from the current position,
+    /// None when a move could not be probed
+    pub fn probe_root(&self, move_api: &mut MoveAPI) -> Option<Vec<(Move, i32)>> {
+        if !self.can_probe(move_api.get_board_ref()) {
+            return None;
+        }
+
+        let mut out = Vec::new();
+        for chess_move in move_api.get_legal_moves() {
+            let mover = move_api.get_turn_color();
+            move_api.exec_move(chess_move);
+            let dtz = if move_api.get_board_ref().halfmove_clock == 0 {
+                self.search(move_api, false).map(|(wdl, _)| dtz_before_zeroing(-wdl))
+            } else {
+                self.get_dtz(move_api).map(|dtz| -dtz + sign_of(-dtz))
+            };
+            // a mating move is one ply from the end
+            let is_mate = dtz == Some(2) && move_api.get_outcome() == Outcome::Win(mover);
+            move_api.undo_move();
+
+            out.push((chess_move, if is_mate { 1 } else { dtz? }));
+        }
+        Some(out)
+    }
+
+    /// Best value of the captures, or of all zeroing moves when
+    /// `check_zeroing`, and the table value. Tables may store a wrong
+    /// value in positions where a capture is the best move
+    fn search(&self, move_api: &mut MoveAPI, check_zeroing: bool) -> Option<(i32, ProbeState)> {
+        let board = *move_api.get_board_ref();
+        let moves = move_api.get_legal_moves();
+        let mut best_value = -2;
+        let mut move_count = 0;
+
+        for chess_move in &moves {
+            let wanted = if check_zeroing {
+                is_zeroing(&board, chess_move)
+            } else {
+                is_capture(&board, chess_move)
+            };
+            if !wanted {
+                continue;
+            }
+            move_count += 1;
+
+            move_api.exec_move(*chess_move);
+            let value = self.search(move_api, false).map(|(value, _)| -value);
+            move_api.undo_move();
+            let value = value?;
+
+            if value > best_value {
+                best_value = value;
+                if value >= 2 {
+                    return Some((value, ProbeState::ZeroingBestMove));
+                }
+            }
+        }
+
+        // with only zeroing moves the table is not needed, it
+        // does not store positions with en passant rights anyway
+        let no_more_moves = move_count > 0 && move_count == moves.len();
+        let value = if no_more_moves {
+            best_value
+        } else {
+            self.probe_table(move_api, TableKind::Wdl, 0)?.0
+        };
+
+        if best_value >= value {
+            let state = if best_value > 0 || no_more_moves {
+                ProbeState::ZeroingBestMove
+            } else {
+                ProbeState::Ok
+            };
+            return Some((best_value, state));
+        }
+        Some((value, ProbeState::Ok))
+    }
+
+    fn get_dtz(&self, move_api: &mut MoveAPI) -> Option<i32> {
+        let (wdl, state) = self.search(move_api, true)?;
+        if wdl == 0 {
+            return Some(0);
+        }
+        if state == ProbeState::ZeroingBestMove {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        let (dtz, state) = self.probe_table(move_api, TableKind::Dtz, wdl)?;
+        if state != ProbeState::ChangeStm {
+            let cursed = (wdl == 1 || wdl == -1) as i32;
+            return Some((dtz + 100 * cursed) * sign_of(wdl));
+        }
+
+        // the table is stored for the other side to move,
+        // look one ply ahead for the move with the best DTZ
+        let board = *move_api.get_board_ref();
+        let mover = board.turn;
+        let mut min_dtz = 0xFFFF;
+
+        for chess_move in move_api.get_legal_moves() {
+            let zeroing = is_zeroing(&board, &chess_move);
+            move_api.exec_move(chess_move);
+
+            let dtz = if zeroing {
+                self.search(move_api, false).map(|(value, _)| -dtz_before_zeroing(value))
+            } else {
+                self.get_dtz(move_api).map(|dtz| -dtz)
+            };
+            let is_mate = dtz == Some(1) && move_api.get_outcome() == Outcome::Win(mover);
+            move_api.undo_move();
+
+            let mut dtz = dtz?;
+            if is_mate {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += sign_of(dtz);
+            }
+            if dtz < min_dtz && sign_of(dtz) == sign_of(wdl) {
+                min_dtz = dtz;
+            }
+        }
+
+        // no legal moves means mate
+        Some(if min_dtz == 0xFFFF { -1 } else { min_dtz })
+    }
+
+    /// Look the position up in its table. Returns the stored value,
+    /// WDL values as -2..=2 and DTZ values in plies
+    fn probe_table(&self, move_api: &MoveAPI, kind: TableKind, wdl: i32) -> Option<(i32, ProbeState)> {
+        let board = move_api.get_board_ref();
+        if (board.white_pieces | board.black_pieces).count_ones() == 2 {
+            return Some((0, ProbeState::Ok));
+        }
+
+        // tables are named with their first side playing White,
+        // with Black holding that material colors and squares are flipped
+        let white = get_side_name(board, Color::White);
+        let black = get_side_name(board, Color::Black);
+        let (table, black_stronger) = match self.tables.get(&format!("{}v{}", white, black)) {
+            Some(table) => (table, false),
+            None => (self.tables.get(&format!("{}v{}", black, white))?, true),
+        };
+        let file = table.get_file(&self.dir, kind)?;
+
+        let (stm, tb_file, idx) = table.get_index(file, kind, board, black_stronger)?;
+
+        // DTZ tables only store one side to move
+        if kind == TableKind::Dtz {
+            let flags = file.items[0][tb_file].flags;
+            let is_stored = (flags & FLAG_STM) as usize == stm || (table.is_symmetric && !table.has_pawns);
+            if !is_stored {
+                return Some((0, ProbeState::ChangeStm));
+            }
+        }
+
+        let data = &file.items[stm % table.get_sides(kind)][tb_file];
+        let value = decompress_pairs(&file.bytes, data, idx)?;
+        Some((map_score(file, kind, tb_file, value, wdl)?, ProbeState::Ok))
+    }
+}
+
+impl TableInfo {
+    /// Side to move and leading pawn file of the record holding `board`,
+    /// and the position's index in it. `black_stronger` when Black holds
+    /// the material of the table's first side
+    fn get_index(&self, file: &TableFile, kind: TableKind, board: &Board, black_stronger: bool) -> Option<(usize, usize, u64)> {
+        let is_black = board.turn == Color::Black;
+        let symmetric_black_to_move = self.is_symmetric && is_black;
+        let flip = symmetric_black_to_move || black_stronger;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip ^ is_black) as usize;
+
+        let tables = indexing();
+        let mut squares: Vec<usize> = Vec::new();
+        let mut pieces: Vec<u8> = Vec::new();
+        let mut lead_pawns_count = 0;
+        let mut lead_color = None;
+        let mut tb_file = 0;
+
+        // pawn tables are split by the file of the leading pawn,
+        // the pawn with the highest map_pawns value
+        if self.has_pawns {
+            let pc = file.items[0][0].pieces[0] ^ flip_color;
+            let color = if pc < 8 { Color::White } else { Color::Black };
+            lead_color = Some(color);
+            for square in get_squares(board, Piece::Pawn, color) {
+                squares.push(square ^ flip_squares);
+                pieces.push(piece_code(Piece::Pawn, color) ^ flip_color);
+            }
+            lead_pawns_count = squares.len();
+
+            let lead = (0..lead_pawns_count)
+                .max_by_key(|i| (tables.map_pawns[squares[*i]], Reverse(*i)))?;
+            squares.swap(0, lead);
+            tb_file = file_of(squares[0]).min(7 - file_of(squares[0]));
+        }
+
+        let mut rest: Vec<(usize, u8)> = Vec::new();
+        for color in [Color::White, Color::Black] {
+            for (piece, _) in NAME_ORDER {
+                if piece == Piece::Pawn && Some(color) == lead_color {
+                    continue;
+                }
+                for square in get_squares(board, piece, color) {
+                    rest.push((square, piece_code(piece, color)));
+                }
+            }
+        }
+        rest.sort();
+        for (square, code) in rest {
+            squares.push(square ^ flip_squares);
+            pieces.push(code ^ flip_color);
+        }
+        let size = squares.len();
+        if size < 2 || size != self.piece_count {
+            return None;
+        }
+
+        let data = &file.items[stm % self.get_sides(kind)][tb_file];
+
+        // put the pieces in the order the table stores them
+        for i in lead_pawns_count..size - 1 {
+            for j in i + 1..size {
+                if data.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // mirror so the leading piece is on files a to d
+        if file_of(squares[0]) > 3 {
+            for square in squares.iter_mut() {
+                *square = flip_file(*square);
+            }
+        }
+
+        let mut idx: u64;
+        if self.has_pawns {
+            idx = tables.lead_pawn_idx[lead_pawns_count][squares[0]];
+            squares[1..lead_pawns_count].sort_by_key(|s| tables.map_pawns[*s]);
+            for (i, square) in squares.iter().enumerate().take(lead_pawns_count).skip(1) {
+                idx += tables.binomial[i][tables.map_pawns[*square]];
+            }
+        } else {
+            // without pawns also mirror the leading piece below rank 5
+            // and below the a1-h8 diagonal
+            if rank_of(squares[0]) > 3 {
+                for square in squares.iter_mut() {
+                    *square = flip_rank(*square);
+                }
+            }
+            for i in 0..data.group_len[0] {
+                if off_a1h8(squares[i]) == 0 {
+                    continue;
+                }
+                if off_a1h8(squares[i]) > 0 {
+                    for square in squares[i..].iter_mut() {
+                        *square = ((*square >> 3) | (*square << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            idx = if self.has_unique_pieces {
+                let adjust1 = (squares[1] > squares[0]) as usize;
+                let adjust2 = (squares[2] > squares[0]) as usize + (squares[2] > squares[1]) as usize;
+
+                if off_a1h8(squares[0]) != 0 {
+                    ((tables.map_a1d1d4[squares[0]] * 63 + (squares[1] - adjust1)) * 62 + squares[2] - adjust2) as u64
+                } else if off_a1h8(squares[1]) != 0 {
+                    ((6 * 63 + rank_of(squares[0]) * 28 + tables.map_b1h1h7[squares[1]]) * 62 + squares[2] - adjust2)
+                        as u64
+                } else if off_a1h8(squares[2]) != 0 {
+                    (6 * 63 * 62
+                        + 4 * 28 * 62
+                        + rank_of(squares[0]) * 7 * 28
+                        + (rank_of(squares[1]) - adjust1) * 28
+                        + tables.map_b1h1h7[squares[2]]) as u64
+                } else {
+                    (6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + rank_of(squares[0]) * 7 * 6
+                        + (rank_of(squares[1]) - adjust1) * 6
+                        + (rank_of(squares[2]) - adjust2)) as u64
+                }
+            } else {
+                tables.map_kk[tables.map_a1d1d4[squares[0]]][squares[1]] as u64
+            };
+        }
+
+        // the other groups, each square counted among
+        // the squares the earlier groups left free
+        idx *= data.group_idx[0];
+        let mut start = data.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while data.group_len[next] != 0 {
+            let len = data.group_len[next];
+            squares[start..start + len].sort();
+            let mut n = 0;
+            for i in 0..len {
+                let square = squares[start + i];
+                let adjust = squares[..start].iter().filter(|s| square > **s).count();
+                let free = (square - adjust).checked_sub(8 * remaining_pawns as usize)?;
+                n += tables.binomial[i + 1][free];
+            }
+            remaining_pawns = false;
+            idx += n * data.group_idx[next];
+            start += len;
+            next += 1;
+        }
+        Some((stm, tb_file, idx))
+    }
+}
+
+/// Turn a stored value into a WDL value or a DTZ in plies.
+/// DTZ tables number their values by frequency, and store
+/// moves rather than plies where that loses nothing
+fn map_score(file: &TableFile, kind: TableKind, tb_file: usize, value: i32, wdl: i32) -> Option<i32> {
+    if kind == TableKind::Wdl {
+        return Some(value - 2);
+    }
+
+    let data = &file.items[0][tb_file];
+    let flags = data.flags;
+    let map_index = [1, 3, 0, 2, 0][(wdl + 2) as usize];
+    let mut value = value;
+    if flags & FLAG_MAPPED != 0 {
+        let start = data.map_idx[map_index];
+        value = if flags & FLAG_WIDE != 0 {
+            read_u16(&file.bytes, start + 2 * value as usize)? as i32
+        } else {
+            *file.bytes.get(start + value as usize)? as i32
+        };
+    }
+
+    let is_plies = match wdl {
+        2 => flags & FLAG_WIN_PLIES != 0,
+        -2 => flags & FLAG_LOSS_PLIES != 0,
+        _ => false,
+    };
+    if !is_plies {
+        value *= 2;
+    }
+    Some(value + 1)
+}
+
+/// Bytes per block of a written table
+const WRITTEN_BLOCK_BITS: u8 = 6;
+
+/// Values between two sparse index entries of a written table
+const WRITTEN_SPAN_BITS: u8 = 6;
+
+/// Longest Huffman code the decoder reads
+const MAX_SYM_LEN: usize = 32;
+
+/// The parts of one PairsData record of a table being written,
+/// in the order of the sections of the file
+#[derive(Clone)]
+struct WrittenPairs {
+    header: Vec<u8>,
+    sparse_index: Vec<u8>,
+    block_lengths: Vec<u8>,
+    blocks: Vec<u8>,
+}
+
+/// Code lengths of a Huffman code for symbols with these counts
+fn get_code_lengths(counts: &[u64]) -> Vec<usize> {
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = BinaryHeap::new();
+    let mut parents = vec![usize::MAX; counts.len()];
+    for (symbol, count) in counts.iter().enumerate() {
+        heap.push(Reverse((*count, symbol)));
+    }
+    while heap.len() > 1 {
+        let Reverse((a, left)) = heap.pop().unwrap();
+        let Reverse((b, right)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[left] = node;
+        parents[right] = node;
+        heap.push(Reverse((a + b, node)));
+    }
+
+    (0..counts.len())
+        .map(|symbol| {
+            let mut len = 0;
+            let mut node = symbol;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                len += 1;
+            }
+            len
+        })
+        .collect()
+}
+
+impl WrittenPairs {
+    fn single_value(flags: u8, value: u8) -> WrittenPairs {
+        WrittenPairs {
+            header: vec![flags | FLAG_SINGLE_VALUE, value],
+            sparse_index: Vec::new(),
+            block_lengths: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Huffman code every value on its own. The reference generator
+    /// also pairs up frequent runs of values, the decoder does not need it
+    fn encode(values: &[u16], flags: u8) -> Result<WrittenPairs, String> {
+        let mut distinct: Vec<u16> = values.to_vec();
+        distinct.sort_unstable();
+        distinct.dedup();
+        if distinct.len() == 1 {
+            return Ok(WrittenPairs::single_value(flags, distinct[0] as u8));
+        }
+        if distinct.len() >= 0xFFF {
+            return Err(format!("{} values are more than a table holds", distinct.len()));
+        }
+
+        let mut counts = vec![0u64; distinct.len()];
+        for value in values {
+            counts[distinct.binary_search(value).unwrap()] += 1;
+        }
+        let lengths = get_code_lengths(&counts);
+        let min_len = *lengths.iter().min().unwrap();
+        let max_len = *lengths.iter().max().unwrap();
+        if max_len > MAX_SYM_LEN {
+            return Err(format!("a code of {} bits is longer than a table holds", max_len));
+        }
+
+        // canonical code: symbols are numbered from the longest codes
+        // to the shortest, and longer codes have lower values
+        let mut symbols: Vec<usize> = (0..distinct.len()).collect();
+        symbols.sort_by_key(|symbol| (Reverse(lengths[*symbol]), distinct[*symbol]));
+        let len_count = max_len - min_len + 1;
+        let mut count_of_len = vec![0u64; len_count];
+        for len in &lengths {
+            count_of_len[len - min_len] += 1;
+        }
+        let mut lowest_sym = vec![0u64; len_count];
+        let mut base = vec![0u64; len_count];
+        for i in (0..len_count - 1).rev() {
+            lowest_sym[i] = lowest_sym[i + 1] + count_of_len[i + 1];
+            base[i] = (base[i + 1] + count_of_len[i + 1]) / 2;
+        }
+
+        // (code, length) of every distinct value
+        let mut codes = vec![(0u64, 0usize); distinct.len()];
+        for (id, symbol) in symbols.iter().enumerate() {
+            let i = lengths[*symbol] - min_len;
+            codes[*symbol] = (base[i] + id as u64 - lowest_sym[i], lengths[*symbol]);
+        }
+
+        // fill blocks with whole codes, each block starts a new bit stream
+        let block_bits = 8usize << WRITTEN_BLOCK_BITS;
+        let mut blocks: Vec<u8> = Vec::new();
+        let mut block_lengths: Vec<u8> = Vec::new();
+        let mut block_starts: Vec<usize> = Vec::new();
+        let mut bits = block_bits;
+        for (i, value) in values.iter().enumerate() {
+            let (code, len) = codes[distinct.binary_search(value).unwrap()];
+            if bits + len > block_bits {
+                if let Some(start) = block_starts.last() {
+                    block_lengths.extend_from_slice(&((i - start - 1) as u16).to_le_bytes());
+                }
+                block_starts.push(i);
+                blocks.resize(block_starts.len() << WRITTEN_BLOCK_BITS, 0);
+                bits = 0;
+            }
+            let block = (block_starts.len() - 1) << WRITTEN_BLOCK_BITS;
+            for bit in (0..len).rev() {
+                if code >> bit & 1 != 0 {
+                    blocks[block + bits / 8] |= 0x80 >> (bits % 8);
+                }
+                bits += 1;
+            }
+        }
+        let last_start = *block_starts.last().unwrap();
+        block_lengths.extend_from_slice(&((values.len() - last_start - 1) as u16).to_le_bytes());
+
+        // entry k holds the block and offset of value k * span + span / 2,
+        // past the end it counts on from the last block
+        let span = 1usize << WRITTEN_SPAN_BITS;
+        let mut sparse_index: Vec<u8> = Vec::new();
+        for k in 0..values.len().div_ceil(span) {
+            let idx = k * span + span / 2;
+            let block = block_starts.partition_point(|start| *start <= idx) - 1;
+            sparse_index.extend_from_slice(&(block as u32).to_le_bytes());
+            sparse_index.extend_from_slice(&((idx - block_starts[block]) as u16).to_le_bytes());
+        }
+
+        let mut header = vec![flags, WRITTEN_BLOCK_BITS, WRITTEN_SPAN_BITS, 0];
+        header.extend_from_slice(&(block_starts.len() as u32).to_le_bytes());
+        header.push(max_len as u8);
+        header.push(min_len as u8);
+        for lowest in &lowest_sym {
+            header.extend_from_slice(&(*lowest as u16).to_le_bytes());
+        }
+        header.extend_from_slice(&(symbols.len() as u16).to_le_bytes());
+        // every symbol is a leaf: the value, then 0xFFF for no right child
+        for symbol in &symbols {
+            let value = distinct[*symbol];
+            header.push(value as u8);
+            header.push(((value >> 8) & 0xF) as u8 | 0xF0);
+            header.push(0xFF);
+        }
+        if symbols.len() & 1 != 0 {
+            header.push(0);
+        }
+
+        Ok(WrittenPairs {
+            header,
+            sparse_index,
+            block_lengths,
+            blocks,
+        })
+    }
+}
+
+/// Put the sections of a table file together, `items` by side to move then pawn file
+fn assemble(prefix: &[u8], items: &[Vec<WrittenPairs>], kind: TableKind) -> Vec<u8> {
+    let files = items[0].len();
+    let mut bytes = prefix.to_vec();
+    bytes.resize(bytes.len() + (bytes.len() & 1), 0);
+    for file in 0..files {
+        for item in items {
+            bytes.extend_from_slice(&item[file].header);
+        }
+    }
+    if kind == TableKind::Dtz {
+        bytes.resize(bytes.len() + (bytes.len() & 1), 0);
+    }
+    for file in 0..files {
+        for item in items {
+            bytes.extend_from_slice(&item[file].sparse_index);
+        }
+    }
+    for file in 0..files {
+        for item in items {
+            bytes.extend_from_slice(&item[file].block_lengths);
+        }
+    }
+    for file in 0..files {
+        for item in items {
+            bytes.resize((bytes.len() + 0x3F) & !0x3F, 0);
+            bytes.extend_from_slice(&item[file].blocks);
+        }
+    }
+
+    // the decoder reads a few bytes past the last block
+    bytes.resize(bytes.len() + 8, 0);
+    bytes.resize(bytes.len() + (80 - bytes.len() % 64) % 64, 0);
+    bytes
+}
+
+/// The board and its mirror images: mirrored files, and
+/// without pawns also mirrored ranks and the a1-h8 diagonal
+fn get_images(board: &Board, has_pawns: bool) -> Vec<Board> {
+    let transform = |bitboard: u64, flips: usize| {
+        let mut out = 0;
+        let mut rest = bitboard;
+        while rest != 0 {
+            let i = rest.leading_zeros() as usize;
+            rest = clear_bit(rest, i);
+            let mut square = i ^ 56;
+            if flips & 1 != 0 {
+                square = flip_file(square);
+            }
+            if flips & 2 != 0 {
+                square = flip_rank(square);
+            }
+            if flips & 4 != 0 {
+                square = ((square >> 3) | (square << 3)) & 63;
+            }
+            out |= mask(square ^ 56);
+        }
+        out
+    };
+
+    let image_count = if has_pawns { 2 } else { 8 };
+    (0..image_count)
+        .map(|flips| {
+            let mut image = *board;
+            for piece in PIECE_TYPES {
+                image.set_piece_board(piece, transform(board.get_piece_board(piece), flips));
+            }
+            image.white_pieces = transform(board.white_pieces, flips);
+            image.black_pieces = transform(board.black_pieces, flips);
+            image
+        })
+        .collect()
+}
+
+impl TableInfo {
+    /// Piece codes in the order a written table stores them: the pawns
+    /// first in pawn tables, then the kings, the pieces a side has one of
+    /// and the runs of equal pieces. None with pawns on both sides
+    fn get_written_order(&self) -> Option<Vec<u8>> {
+        let (white, black) = self.name.split_once('v')?;
+        let mut codes = Vec::new();
+        for (side, color) in [(white, Color::White), (black, Color::Black)] {
+            for letter in side.chars() {
+                let (piece, _) = NAME_ORDER.iter().find(|(_, l)| *l == letter)?;
+                codes.push(piece_code(*piece, color));
+            }
+        }
+        if self.pawn_count[1] > 0 {
+            return None;
+        }
+
+        let count = |code: u8| codes.iter().filter(|c| **c == code).count();
+        let is_pawn = |code: u8| code & 7 == 1;
+        let is_king = |code: u8| code & 7 == 6;
+        let mut order: Vec<u8> = codes.iter().copied().filter(|c| is_pawn(*c)).collect();
+        order.extend(codes.iter().copied().filter(|c| is_king(*c)));
+        order.extend(codes.iter().copied().filter(|c| !is_pawn(*c) && !is_king(*c) && count(*c) == 1));
+        order.extend(codes.iter().copied().filter(|c| !is_pawn(*c) && !is_king(*c) && count(*c) > 1));
+        Some(order)
+    }
+
+    /// A file of `kind` for this table: the values of `positions`, their
+    /// images, and any value where no position is given. DTZ is stored
+    /// for White to move, in plies
+    fn write(&self, kind: TableKind, order: &[u8], positions: &[(Board, Wdl, i32)]) -> Result<Vec<u8>, String> {
+        let files = if self.has_pawns { 4 } else { 1 };
+        let mut prefix = match kind {
+            TableKind::Wdl => WDL_MAGIC.to_vec(),
+            TableKind::Dtz => DTZ_MAGIC.to_vec(),
+        };
+        prefix.push(!self.is_symmetric as u8 | (self.has_pawns as u8) << 1);
+        for _ in 0..files {
+            // the leading group is indexed first, both sides use the same order
+            prefix.push(0);
+            prefix.extend(order.iter().map(|code| code | code << 4));
+        }
+
+        let flags = match kind {
+            TableKind::Wdl => 0,
+            TableKind::Dtz => FLAG_WIN_PLIES | FLAG_LOSS_PLIES,
+        };
+        let sides = self.get_sides(kind);
+
+        // a file of one value has the layout of the real one
+        let blank = vec![vec![WrittenPairs::single_value(flags, 0); files]; sides];
+        let layout = self
+            .parse(assemble(&prefix, &blank, kind), kind)
+            .ok_or(format!("{} could not be laid out", self.name))?;
+
+        let mut values: Vec<Vec<Vec<Option<u16>>>> = layout
+            .items
+            .iter()
+            .map(|item| item.iter().map(|data| vec![None; data.get_size()]).collect())
+            .collect();
+        for (board, wdl, dtz) in positions {
+            let value = match kind {
+                TableKind::Wdl => (*wdl as i32 + 2) as u16,
+                TableKind::Dtz if *wdl == Wdl::Draw => continue,
+                TableKind::Dtz => {
+                    let is_cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+                    (dtz.unsigned_abs() - 1 - 100 * is_cursed as u32) as u16
+                }
+            };
+            for image in get_images(board, self.has_pawns) {
+                let (stm, tb_file, idx) = self
+                    .get_index(&layout, kind, &image, false)
+                    .ok_or_else(|| format!("{} has no index in {}", image.get_fen(), self.name))?;
+                if kind == TableKind::Dtz && stm != 0 {
+                    continue;
+                }
+                let slot = values[stm % sides][tb_file]
+                    .get_mut(idx as usize)
+                    .ok_or_else(|| format!("{} has index {} past the end of {}", image.get_fen(), idx, self.name))?;
+                if slot.is_some_and(|stored| stored != value) {
+                    return Err(format!("{} shares index {} of {} with another position", image.get_fen(), idx, self.name));
+                }
+                *slot = Some(value);
+            }
+        }
+
+        // positions nobody asks for take the value before them
+        let mut items = Vec::new();
+        for item in values {
+            let mut written = Vec::new();
+            for file_values in item {
+                let mut last = file_values.iter().flatten().next().copied().unwrap_or(0);
+                let filled: Vec<u16> = file_values
+                    .iter()
+                    .map(|value| {
+                        last = value.unwrap_or(last);
+                        last
+                    })
+                    .collect();
+                written.push(WrittenPairs::encode(&filled, flags)?);
+            }
+            items.push(written);
+        }
+        Ok(assemble(&prefix, &items, kind))
+    }
+}
+
+/// Write the `.rtbw` and `.rtbz` files of table `name` to `dir`. `positions`
+/// holds every position of the table once, in any of its mirror images, with
+/// its result and DTZ for the side to move. Tables with pawns on both sides
+/// are left out
+pub fn write_table(dir: &Path, name: &str, positions: &[(Board, Wdl, i32)]) -> Result<(), String> {
+    let table = TableInfo::new(name).ok_or(format!("{} is not a table name", name))?;
+    let order = table
+        .get_written_order()
+        .ok_or(format!("{} has pawns on both sides, it cannot be written", name))?;
+    for kind in [TableKind::Wdl, TableKind::Dtz] {
+        let bytes = table.write(kind, &order, positions)?;
+        let path = dir.join(format!("{}.{}", name, kind.get_extension()));
+        fs::write(&path, bytes).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::dtm::{Dtm, DtmTablebases, Signature};
+    use crate::{syzygy_builder, tablebase_builder};
+
+    /// The index tables against the sizes the format uses, needs no table files
+    #[test]
+    fn index_tables_match_format_sizes() {
+        let tables = indexing();
+
+        // 462 king pairs, numbered without gaps
+        let mut codes: Vec<usize> = Vec::new();
+        for (idx, row) in tables.map_kk.iter().enumerate() {
+            let s1 = (0..28).find(|s| tables.map_a1d1d4[*s] == idx && (idx > 0 || *s == 1)).unwrap();
+            for (s2, code) in row.iter().enumerate() {
+                let is_above = off_a1h8(s1) == 0 && off_a1h8(s2) > 0;
+                if !is_king_distance_one(s1, s2) && !is_above {
+                    codes.push(*code);
+                }
+            }
+        }
+        codes.sort();
+        assert_eq!(codes, (0..462).collect::<Vec<usize>>(), "king pair index");
+
+        // each leading pawn square has its own map_pawns value
+        let mut pawn_values: Vec<usize> = (8..56).map(|s| tables.map_pawns[s]).collect();
+        pawn_values.sort();
+        assert_eq!(pawn_values, (0..48).collect::<Vec<usize>>(), "pawn square index");
+
+        assert_eq!(tables.binomial[2][62], 1891);
+        assert_eq!(tables.binomial[5][63], 7028847);
+
+        // one leading pawn: 6 ranks of a file and its mirror
+        assert_eq!(tables.lead_pawns_size[1], [6, 6, 6, 6]);
+    }
+
+    /// Every position of the generated distance-to-mate tables must get
+    /// the same result from the Syzygy tables, and a DTZ that fits its
+    /// distance to mate. Pawn moves reset DTZ, so KPvK only bounds it
+    #[test]
+    fn decoder_agrees_with_generated_tables() {
+        let tablebases = Tablebases::open(syzygy_builder::build_test_tables().to_str().unwrap());
+        let mut dtm_tables = DtmTablebases::new();
+        for name in ["KQvK", "KRvK", "KPvK"] {
+            let signature = Signature::parse(name).unwrap();
+            tablebase_builder::generate_with_successors(&signature, &mut dtm_tables);
+            let table = dtm_tables.get(&signature).unwrap();
+
+            for (index, result) in table.get_results() {
+                let board = signature.get_board(index).unwrap();
+                let mut move_api = MoveAPI::from_board(board);
+                let fen = board.get_fen();
+                let wdl = tablebases.probe_wdl(&mut move_api).unwrap_or_else(|| panic!("no WDL for {}", fen));
+                let dtz = tablebases.probe_dtz(&mut move_api).unwrap_or_else(|| panic!("no DTZ for {}", fen));
+
+                match result {
+                    Dtm::Draw => assert!(wdl == Wdl::Draw && dtz == 0, "{}: {:?} {} for a draw", fen, wdl, dtz),
+                    Dtm::Loss(0) => assert_eq!(wdl, Wdl::Loss, "{} is mate", fen),
+                    Dtm::Win(plies) | Dtm::Loss(plies) => {
+                        let is_win = matches!(result, Dtm::Win(_));
+                        assert_eq!(wdl, if is_win { Wdl::Win } else { Wdl::Loss }, "{}", fen);
+                        assert_eq!(dtz > 0, is_win, "{}: DTZ {}", fen, dtz);
+                        // tables storing moves instead of plies may round DTZ up by one
+                        let distance = dtz.unsigned_abs();
+                        let is_fitting = if signature.has_pawns() {
+                            distance <= plies + 1
+                        } else {
+                            distance == plies || distance == plies + 1
+                        };
+                        assert!(is_fitting, "{}: DTZ {} for {:?}", fen, dtz, result);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Moves played backwards, for retrograde analysis.
+//!
+//! An un-move takes back the last move of the side that is not to move.
+//! Only quiet moves are taken back: no captured piece comes back and no
+//! promoted piece turns back into a pawn, tables reach those positions
+//! through their smaller tables instead. Castling rights and en passant
+//! targets are not restored either.
+
+use super::bitboard_util::{clear_bit, mask};
+use super::board::Board;
+use super::cmove::Move;
+use super::color::Color;
+use super::move_generator::MoveGenerator;
+use super::move_table::get_king_masks;
+use super::piece::Piece;
+use super::position::Position;
+
+/// Pieces that move the same way forwards and backwards
+const REVERSIBLE_PIECES: [Piece; 4] = [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen];
+
+pub struct UnmoveGenerator {
+    move_generator: MoveGenerator,
+    king_masks: [u64; 64],
+}
+
+impl UnmoveGenerator {
+    pub fn new() -> Self {
+        UnmoveGenerator {
+            move_generator: MoveGenerator::new(),
+            king_masks: get_king_masks(),
+        }
+    }
+
+    /// Un-moves of the side that just moved. `start` is the square the
+    /// piece stands on now and `end` the square it came from
+    pub fn get_unmoves(&self, board: &Board) -> Vec<Move> {
+        let color = board.turn.get_opposite();
+        let empty = !(board.white_pieces | board.black_pieces);
+        let mut out = Vec::new();
+
+        // the squares a piece reaches are the squares it can have come from,
+        // as long as nothing stood there
+        for piece in REVERSIBLE_PIECES {
+            let moves = self.move_generator.get_piece_moves(board, piece, color);
+            out.extend(moves.into_iter().filter(|m| mask(m.end.to_index()) & empty != 0));
+        }
+
+        // the king may have stepped out of check, so attacked squares count too
+        let king = board.get_color_piece_board(Piece::King, color);
+        if king != 0 {
+            let index = king.leading_zeros() as usize;
+            out.extend(get_unmoves_to(index, self.king_masks[index] & empty, Piece::King));
+        }
+
+        out.extend(self.get_pawn_unmoves(board, color, empty));
+        out
+    }
+
+    /// Pawns step back one square, or two onto their starting row
+    fn get_pawn_unmoves(&self, board: &Board, color: Color, empty: u64) -> Vec<Move> {
+        // rows count from rank 8 down
+        let (back, first_row, start_row) = match color {
+            Color::White => (1, 7, 6),
+            Color::Black => (-1, 0, 1),
+        };
+
+        let mut out = Vec::new();
+        let mut pawns = board.get_color_piece_board(Piece::Pawn, color);
+        while pawns != 0 {
+            let index = pawns.leading_zeros() as usize;
+            pawns = clear_bit(pawns, index);
+
+            let square = Position::index(index);
+            let single = Position::new(square.get_row() + back, square.get_col());
+            if single.get_row() == first_row || mask(single.to_index()) & empty == 0 {
+                continue;
+            }
+            out.push(Move::new(square, single, Piece::Pawn, None));
+
+            let double = Position::new(single.get_row() + back, single.get_col());
+            if double.get_row() == start_row && mask(double.to_index()) & empty != 0 {
+                out.push(Move::new(square, double, Piece::Pawn, None));
+            }
+        }
+        out
+    }
+
+    /// The board before `unmove` was played
+    pub fn unmake(board: &Board, unmove: &Move) -> Board {
+        let mut out = *board;
+        let color = board.turn.get_opposite();
+        let now = mask(unmove.start.to_index());
+        let before = mask(unmove.end.to_index());
+
+        out.set_piece_board(unmove.piece, (board.get_piece_board(unmove.piece) & !now) | before);
+        out.set_color_board(color, (board.get_pieces(color) & !now) | before);
+        out.turn = color;
+        out.ep_target = 0;
+        out
+    }
+
+    /// Legal boards one quiet move before `board`:
+    /// the side answering the move was not left in check
+    pub fn get_predecessors(&self, board: &Board) -> Vec<Board> {
+        self.get_unmoves(board)
+            .iter()
+            .map(|unmove| UnmoveGenerator::unmake(board, unmove))
+            .filter(|before| !self.move_generator.is_attacked_king(before, before.turn))
+            .collect()
+    }
+}
+
+fn get_unmoves_to(index: usize, origins: u64, piece: Piece) -> Vec<Move> {
+    let mut out = Vec::new();
+    let mut origins = origins;
+    while origins != 0 {
+        let origin = origins.leading_zeros() as usize;
+        origins = clear_bit(origins, origin);
+        out.push(Move::new(Position::index(index), Position::index(origin), piece, None));
+    }
+    out
+}
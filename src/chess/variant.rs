@@ -0,0 +1,243 @@
+use super::bitboard_util::mask;
+use super::board::{Board, Square};
+use super::cmove::Move;
+use super::color::Color;
+use super::fen;
+use super::move_generator::MoveGenerator;
+use super::piece::Piece;
+
+/// Rule sets a game can be played with.
+/// The variant is stored on the Board, so it travels with
+/// every copy of the position and the move generator and
+/// executor can consult it without extra plumbing
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Variant {
+    Standard,
+    KingOfTheHill,
+    ThreeCheck,
+    Crazyhouse,
+    Antichess,
+    Atomic,
+    Horde,
+    RacingKings,
+}
+
+/// Every variant, in the order they are listed to users
+pub const VARIANTS: [Variant; 8] = [
+    Variant::Standard,
+    Variant::KingOfTheHill,
+    Variant::ThreeCheck,
+    Variant::Crazyhouse,
+    Variant::Antichess,
+    Variant::Atomic,
+    Variant::Horde,
+    Variant::RacingKings,
+];
+
+/// Result of a game as seen from the rules
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Outcome {
+    Ongoing,
+    Win(Color),
+    Draw,
+}
+
+/// Checks a player has to give to win Three-check
+const CHECK_LIMIT: u8 = 3;
+
+/// a8 to h8, the goal of Racing Kings
+pub const EIGHTH_RANK: u64 = 0xFF00000000000000;
+
+impl Variant {
+    /// Look up a variant by the name used in requests
+    pub fn from_name(name: &str) -> Option<Variant> {
+        VARIANTS.into_iter().find(|v| v.get_name() == name)
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Variant::Standard => "standard",
+            Variant::KingOfTheHill => "kingOfTheHill",
+            Variant::ThreeCheck => "threeCheck",
+            Variant::Crazyhouse => "crazyhouse",
+            Variant::Antichess => "antichess",
+            Variant::Atomic => "atomic",
+            Variant::Horde => "horde",
+            Variant::RacingKings => "racingKings",
+        }
+    }
+
+    pub fn get_start_fen(&self) -> &'static str {
+        match self {
+            Variant::Standard | Variant::KingOfTheHill | Variant::Atomic => fen::STARTING_BOARD,
+            Variant::ThreeCheck => fen::THREE_CHECK_STARTING_BOARD,
+            Variant::Crazyhouse => fen::CRAZYHOUSE_STARTING_BOARD,
+            Variant::Antichess => fen::ANTICHESS_STARTING_BOARD,
+            Variant::Horde => fen::HORDE_STARTING_BOARD,
+            Variant::RacingKings => fen::RACING_KINGS_STARTING_BOARD,
+        }
+    }
+
+    /// Whether the board keeps count of the checks each side gave
+    pub fn counts_checks(&self) -> bool {
+        *self == Variant::ThreeCheck
+    }
+
+    /// Whether captured pieces go to the capturer's pocket and can be dropped
+    pub fn has_drops(&self) -> bool {
+        *self == Variant::Crazyhouse
+    }
+
+    /// Whether `color` has a king that must be kept out of check.
+    /// In Antichess the king is an ordinary piece: it can be captured,
+    /// pawns can promote to it, and it does not castle.
+    /// White's Horde has no king at all
+    pub fn has_royal_king(&self, color: Color) -> bool {
+        match self {
+            Variant::Antichess => false,
+            Variant::Horde => color == Color::Black,
+            _ => true,
+        }
+    }
+
+    /// Whether pawns on the first rank may move two squares (Horde)
+    pub fn has_first_rank_double_step(&self) -> bool {
+        *self == Variant::Horde
+    }
+
+    /// Whether giving check is illegal (Racing Kings)
+    pub fn forbids_checks(&self) -> bool {
+        *self == Variant::RacingKings
+    }
+
+    /// Whether Black may still draw with its next move after
+    /// White's king reached the goal (Racing Kings)
+    pub fn has_equalizing_move(&self) -> bool {
+        *self == Variant::RacingKings
+    }
+
+    /// Whether captures explode (Atomic): the capturer and every piece
+    /// but pawns next to the target square leave the board too.
+    /// Kings can never capture there, so they may stand next to each other
+    pub fn has_explosions(&self) -> bool {
+        *self == Variant::Atomic
+    }
+
+    /// Whether a side that can capture has to
+    pub fn has_forced_captures(&self) -> bool {
+        *self == Variant::Antichess
+    }
+
+    /// Pieces a pawn may promote to
+    pub fn get_promotion_pieces(&self) -> &'static [Piece] {
+        match self {
+            Variant::Antichess => &[Piece::Queen, Piece::Knight, Piece::Rook, Piece::Bishop, Piece::King],
+            _ => &[Piece::Queen, Piece::Knight, Piece::Rook, Piece::Bishop],
+        }
+    }
+
+    /// A pseudo-legal move by `mover` that led to `board` is legal
+    /// if it does not leave the mover's king attacked
+    pub fn is_legal(&self, move_generator: &MoveGenerator, board: &Board, mover: Color) -> bool {
+        if !self.has_royal_king(mover) {
+            return true;
+        }
+
+        if self.forbids_checks() && move_generator.is_attacked_king(board, mover) {
+            return false;
+        }
+
+        if self.has_explosions() {
+            // blowing up your own king is never allowed, blowing
+            // up the enemy king wins even when in check
+            if board.get_color_piece_board(Piece::King, mover) == 0 {
+                return false;
+            }
+            if board.get_color_piece_board(Piece::King, mover.get_opposite()) == 0 {
+                return true;
+            }
+        }
+        !move_generator.is_attacked_king(board, mover.get_opposite())
+    }
+
+    /// Narrow the legal moves down further, when captures are
+    /// forced only the captures remain if there are any
+    pub fn filter_moves(&self, board: &Board, moves: Vec<Move>) -> Vec<Move> {
+        if !self.has_forced_captures() {
+            return moves;
+        }
+
+        // en passant moves end on the captured pawn, so
+        // every capture ends on an enemy piece
+        let enemy_pieces = board.get_pieces(board.turn.get_opposite());
+        let is_capture = |m: &Move| !m.is_drop && enemy_pieces & mask(m.end.to_index()) != 0;
+        if moves.iter().any(is_capture) {
+            moves.into_iter().filter(is_capture).collect()
+        } else {
+            moves
+        }
+    }
+
+    /// Game end reached through the variant's own goal,
+    /// these are checked before looking for legal moves
+    pub fn get_goal_outcome(&self, board: &Board) -> Option<Outcome> {
+        match self {
+            Variant::Standard | Variant::Crazyhouse => None,
+            // capturing the whole horde wins
+            Variant::Horde => match board.get_pieces(Color::White) {
+                0 => Some(Outcome::Win(Color::Black)),
+                _ => None,
+            },
+            // first king to the eighth rank, arriving together draws,
+            // see MoveAPI for Black's last chance to equalize
+            Variant::RacingKings => {
+                let is_home = |color| board.get_color_piece_board(Piece::King, color) & EIGHTH_RANK != 0;
+                match (is_home(Color::White), is_home(Color::Black)) {
+                    (true, true) => Some(Outcome::Draw),
+                    (true, false) => Some(Outcome::Win(Color::White)),
+                    (false, true) => Some(Outcome::Win(Color::Black)),
+                    (false, false) => None,
+                }
+            }
+            // exploding the enemy king wins
+            Variant::Atomic => [Color::White, Color::Black]
+                .into_iter()
+                .find(|color| board.get_color_piece_board(Piece::King, *color) == 0)
+                .map(|color| Outcome::Win(color.get_opposite())),
+            // losing every piece wins
+            Variant::Antichess => [Color::White, Color::Black]
+                .into_iter()
+                .find(|color| board.get_pieces(*color) == 0)
+                .map(Outcome::Win),
+            Variant::KingOfTheHill => {
+                let hill = mask(Square::D4 as usize)
+                    | mask(Square::E4 as usize)
+                    | mask(Square::D5 as usize)
+                    | mask(Square::E5 as usize);
+                [Color::White, Color::Black]
+                    .into_iter()
+                    .find(|color| board.get_color_piece_board(Piece::King, *color) & hill != 0)
+                    .map(Outcome::Win)
+            }
+            Variant::ThreeCheck => [Color::White, Color::Black]
+                .into_iter()
+                .find(|color| board.get_checks(*color) >= CHECK_LIMIT)
+                .map(Outcome::Win),
+        }
+    }
+
+    /// Result when the side to move has no legal moves,
+    /// `is_check` is only meaningful for a royal king
+    pub fn get_no_move_outcome(&self, board: &Board, is_check: bool) -> Outcome {
+        if *self == Variant::Antichess {
+            // being stalemated wins
+            return Outcome::Win(board.turn);
+        }
+
+        if is_check {
+            Outcome::Win(board.turn.get_opposite())
+        } else {
+            Outcome::Draw
+        }
+    }
+}
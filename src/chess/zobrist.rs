@@ -0,0 +1,99 @@
+use super::bitboard_util::clear_bit;
+use super::board::{Board, Castle, POCKET_PIECES};
+use super::color::Color;
+use super::piece::{Piece, PIECE_TYPES};
+use super::position::Position;
+
+/// Layout of the key table:
+/// 12 * 64 piece-square keys, 4 castle keys, 8 en passant file keys, 1 turn key,
+/// then 2 * 4 keys for the number of checks each side gave (Three-check)
+/// and 2 * 5 * 17 keys for the number of each piece in a pocket (Crazyhouse)
+const PIECE_KEY_COUNT: usize = 12 * 64;
+const CASTLE_OFFSET: usize = PIECE_KEY_COUNT;
+const EP_OFFSET: usize = CASTLE_OFFSET + 4;
+const TURN_OFFSET: usize = EP_OFFSET + 8;
+const CHECK_OFFSET: usize = TURN_OFFSET + 1;
+const POCKET_OFFSET: usize = CHECK_OFFSET + 8;
+const POCKET_DEPTH: usize = 17;
+const KEY_COUNT: usize = POCKET_OFFSET + 2 * 5 * POCKET_DEPTH;
+
+/// Keys are generated at compile time with splitmix64
+/// so hashes are identical across runs and threads
+const KEYS: [u64; KEY_COUNT] = generate_keys();
+
+const fn generate_keys() -> [u64; KEY_COUNT] {
+    let mut keys = [0; KEY_COUNT];
+    let mut state: u64 = 0x2545F4914F6CDD1D;
+    let mut i = 0;
+    while i < KEY_COUNT {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+}
+
+fn piece_key(piece: Piece, color: Color, index: usize) -> u64 {
+    let color_offset = match color {
+        Color::White => 0,
+        Color::Black => 6,
+    };
+    KEYS[(color_offset + piece as usize) * 64 + index]
+}
+
+/// Compute the Zobrist hash of a board from scratch
+pub fn hash(board: &Board) -> u64 {
+    let mut out = 0;
+
+    for color in [Color::White, Color::Black] {
+        for piece in PIECE_TYPES {
+            let mut bitboard = board.get_color_piece_board(piece, color);
+            let mut i = bitboard.leading_zeros();
+            while i < 64 {
+                out ^= piece_key(piece, color, i as usize);
+                bitboard = clear_bit(bitboard, i as usize);
+                i = bitboard.leading_zeros();
+            }
+        }
+    }
+
+    let castles = [
+        (Castle::Short, Color::White),
+        (Castle::Long, Color::White),
+        (Castle::Short, Color::Black),
+        (Castle::Long, Color::Black),
+    ];
+    for (i, (castle, color)) in castles.into_iter().enumerate() {
+        if board.get_castle(castle, color) {
+            out ^= KEYS[CASTLE_OFFSET + i];
+        }
+    }
+
+    if board.ep_target != 0 {
+        let col = Position::index(board.ep_target.leading_zeros() as usize).get_col();
+        out ^= KEYS[EP_OFFSET + col as usize];
+    }
+
+    if board.turn == Color::White {
+        out ^= KEYS[TURN_OFFSET];
+    }
+
+    for (i, color) in [Color::White, Color::Black].into_iter().enumerate() {
+        let checks = board.get_checks(color).min(3) as usize;
+        if checks > 0 {
+            out ^= KEYS[CHECK_OFFSET + i * 4 + checks];
+        }
+
+        for piece in POCKET_PIECES {
+            let count = (board.get_pocket(piece, color) as usize).min(POCKET_DEPTH - 1);
+            if count > 0 {
+                out ^= KEYS[POCKET_OFFSET + (i * 5 + piece as usize) * POCKET_DEPTH + count];
+            }
+        }
+    }
+
+    out
+}
@@ -0,0 +1,252 @@
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Instant;
+
+use rand::Rng;
+
+use crate::chess::bitboard_util::mask;
+use crate::chess::color::Color;
+use crate::chess::fen;
+use crate::chess::move_api::MoveAPI;
+use crate::chess::pgn;
+use crate::chess::variant::Outcome;
+use crate::game::evaluator;
+use crate::match_runner::{self, GameRecord, GameSettings, PlayerSpec, SearchLimits, MATE_SCORE};
+
+/// Games between two progress reports
+const REPORT_INTERVAL: usize = 100;
+
+struct DatagenOptions {
+    games: usize,
+    limits: SearchLimits,
+    evaluator: String,
+    threads: usize,
+    random_plies: usize,
+    settings: GameSettings,
+}
+
+fn parse_options(args: &[String]) -> Result<DatagenOptions, String> {
+    let mut options = DatagenOptions {
+        games: 1000,
+        limits: SearchLimits::default(),
+        evaluator: "material-mobility".to_string(),
+        threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        random_plies: 8,
+        settings: GameSettings::default(),
+    };
+
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let value = rest.next().ok_or(format!("{} needs a value", arg))?;
+        if options.settings.set_option(arg, value)? {
+            continue;
+        }
+        let count = || value.parse::<usize>().map_err(|_| format!("{} needs a whole number", arg));
+        match arg.as_str() {
+            "--games" => options.games = count()?,
+            "--depth" => options.limits.depth = Some(count()?.max(1)),
+            "--nodes" => options.limits.nodes = Some(count()?),
+            "--evaluator" => options.evaluator = value.clone(),
+            "--threads" => options.threads = count()?.max(1),
+            "--random-plies" => options.random_plies = count()?,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    evaluator::from_name(&options.evaluator)?;
+    Ok(options)
+}
+
+/// A position `plies` random legal moves from the start, played again
+/// from scratch when the game ends on the way
+fn get_random_opening(plies: usize) -> String {
+    let mut rng = rand::thread_rng();
+    'opening: loop {
+        let mut move_api = MoveAPI::new(fen::STARTING_BOARD);
+        for _ in 0..plies {
+            let moves = move_api.get_legal_moves();
+            if moves.is_empty() {
+                continue 'opening;
+            }
+            move_api.exec_move(moves[rng.gen_range(0..moves.len())]);
+        }
+        if !move_api.get_legal_moves().is_empty() {
+            return move_api.get_board_ref().get_fen();
+        }
+    }
+}
+
+/// Whether the side to move can capture, en passant included
+fn has_capture(move_api: &mut MoveAPI) -> bool {
+    let board = *move_api.get_board_ref();
+    let enemies = board.get_pieces(board.turn.get_opposite());
+    move_api.get_legal_moves().iter().any(|m| {
+        let landing = m.get_landing(board.turn);
+        !m.is_drop && (landing != m.end || enemies & mask(landing.to_index()) != 0)
+    })
+}
+
+/// Dataset lines of the quiet positions of a game: `FEN | score | result`,
+/// the score in centipawns and the result as 1.0, 0.5 or 0.0, both for White.
+/// Positions the search saw a mate in are left out too
+fn get_samples(record: &GameRecord) -> Vec<String> {
+    let result = match record.outcome {
+        Outcome::Win(Color::White) => "1.0",
+        Outcome::Win(Color::Black) => "0.0",
+        _ => "0.5",
+    };
+
+    let mut out = Vec::new();
+    let mut move_api = MoveAPI::new(&record.start_fen);
+    for (san, score) in record.moves.iter().zip(&record.scores) {
+        let board = *move_api.get_board_ref();
+        if let Some(score) = score.filter(|score| score.abs() < MATE_SCORE) {
+            if !move_api.is_in_check() && !has_capture(&mut move_api) {
+                let white_score = if board.turn == Color::White { score } else { -score };
+                out.push(format!("{} | {} | {}", board.get_fen(), white_score.round() as i64, result));
+            }
+        }
+
+        match pgn::find_san_move(&mut move_api, san) {
+            Some(m) => move_api.exec_move(m),
+            None => break,
+        }
+    }
+    out
+}
+
+/// Usage: datagen <output file> [--games N] [--depth N] [--nodes N] [--evaluator name] [--threads N]
+///        [--random-plies N] [--max-plies N] [--resign-score CP] [--resign-moves N]
+///        [--draw-score CP] [--draw-moves N] [--draw-after N]
+///
+/// Plays `games` games (default 1000) of the agent against itself, searching
+/// to `depth` (default 4) or `nodes` nodes, whichever comes first, on
+/// `threads` threads (default one per core). Each
+/// game starts after `random-plies` random moves (default 8) and ends by the
+/// rules or by adjudication as in `match`. Every quiet position, not in check
+/// and without a capture, is appended to the output file with its search
+/// score and the game's result, in the format `tune` reads
+pub fn run(args: &[String]) {
+    if args.is_empty() {
+        println!("Usage: datagen <output file> [--games N] [--depth N] [--nodes N] [--evaluator name] [--threads N] [--random-plies N]");
+        println!("       [--max-plies N] [--resign-score CP] [--resign-moves N] [--draw-score CP] [--draw-moves N] [--draw-after N]");
+        return;
+    }
+    let options = match parse_options(&args[1..]) {
+        Ok(options) => options,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+    let file = match OpenOptions::new().create(true).append(true).open(&args[0]) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("Could not open {}: {}", args[0], e);
+            return;
+        }
+    };
+    let mut output = BufWriter::new(file);
+
+    let spec = PlayerSpec::Tomato {
+        name: None,
+        evaluator: options.evaluator.clone(),
+        threads: 1,
+        limits: options.limits,
+        skill: None,
+        personality: None,
+    };
+    let next_game = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
+    let mut workers = Vec::new();
+    for _ in 0..options.threads.min(options.games) {
+        let (spec, next_game, sender) = (spec.clone(), next_game.clone(), sender.clone());
+        let (games, random_plies, settings) = (options.games, options.random_plies, options.settings.clone());
+        workers.push(thread::spawn(move || {
+            let players = (spec.create(), spec.create());
+            let (Ok(mut white), Ok(mut black)) = players else { return };
+            while next_game.fetch_add(1, Ordering::Relaxed) < games {
+                let opening = get_random_opening(random_plies);
+                let record = match_runner::play_game(white.as_mut(), black.as_mut(), &opening, &settings);
+                if sender.send(get_samples(&record)).is_err() {
+                    return;
+                }
+            }
+        }));
+    }
+    drop(sender);
+
+    let now = Instant::now();
+    let mut positions = 0;
+    for (count, samples) in receiver.iter().enumerate() {
+        positions += samples.len();
+        for sample in samples {
+            if let Err(e) = writeln!(output, "{}", sample) {
+                println!("Could not write {}: {}", args[0], e);
+                return;
+            }
+        }
+        if (count + 1) % REPORT_INTERVAL == 0 || count + 1 == options.games {
+            let seconds = now.elapsed().as_secs_f64();
+            println!(
+                "{} games, {} positions, {:.0} positions/s",
+                count + 1,
+                positions,
+                positions as f64 / seconds
+            );
+        }
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+    if let Err(e) = output.flush() {
+        println!("Could not write {}: {}", args[0], e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tuner;
+
+    fn get_record(scores: Vec<Option<f64>>) -> GameRecord {
+        GameRecord {
+            white: String::new(),
+            black: String::new(),
+            start_fen: fen::STARTING_BOARD.to_string(),
+            moves: ["e4", "d5", "Bb5+", "c6", "Ba4"].iter().map(|san| san.to_string()).collect(),
+            scores,
+            outcome: Outcome::Win(Color::White),
+            termination: String::from("checkmate"),
+            failed: None,
+        }
+    }
+
+    /// Only the first two positions are quiet: after 1. e4 d5 White can
+    /// take on d5, after 2. Bb5+ Black is in check, after 2... c6 both
+    /// bishop and pawn can take
+    #[test]
+    fn samples_are_quiet_and_read_back() {
+        let record = get_record(vec![Some(20.0), Some(-15.0), Some(40.0), Some(-35.0), Some(30.0)]);
+        let mut move_api = MoveAPI::new(fen::STARTING_BOARD);
+        let mut fens = vec![move_api.get_board_ref().get_fen()];
+        let m = pgn::find_san_move(&mut move_api, "e4").unwrap();
+        move_api.exec_move(m);
+        fens.push(move_api.get_board_ref().get_fen());
+
+        let samples = get_samples(&record);
+        assert_eq!(samples, [format!("{} | 20 | 1.0", fens[0]), format!("{} | 15 | 1.0", fens[1])]);
+        for (sample, fen) in samples.iter().zip(fens) {
+            assert_eq!(tuner::parse_line(sample), Some((fen, 1.0)));
+        }
+    }
+
+    #[test]
+    fn mates_and_unscored_moves_are_skipped() {
+        let record = get_record(vec![Some(MATE_SCORE), None, Some(40.0), Some(-35.0), Some(30.0)]);
+        assert!(get_samples(&record).is_empty());
+    }
+}
@@ -1 +1,9 @@
 pub mod agent;
+pub mod book;
+pub mod endgame;
+pub mod evaluator;
+pub mod kpk;
+pub mod mcts;
+pub mod personality;
+pub mod skill;
+pub mod transposition;
@@ -1,9 +1,16 @@
 
-use std::{cmp::Ordering, iter::Map, collections::{HashMap, VecDeque}};
+use std::{cmp::Ordering, iter::Map, collections::{HashMap, VecDeque}, sync::{Arc, atomic::{AtomicBool, AtomicUsize, self}}, thread, time::{Duration, Instant}};
 
 use rand::{rngs::ThreadRng, SeedableRng, Rng};
 
-use crate::{chess::{cmove::Move, move_api::MoveAPI, board, color::Color, piece}, api::chess_api::{GameAPI, ChessGame}};
+use crate::{chess::{cmove::Move, fen, move_api::MoveAPI, board, color::Color, dtm::{Dtm, DtmTablebases}, piece, syzygy::{Tablebases, Wdl}, variant::Outcome}, api::chess_api::{GameAPI, ChessGame}};
+
+use super::book::{BookSelection, OpeningBook};
+use super::endgame;
+use super::personality::Personality;
+use super::skill::Skill;
+use super::evaluator::{Evaluator, MaterialMobilityEvaluator};
+use super::transposition::{Bound, TableEntry, TranspositionTable, self};
 
 pub struct RandomAgent {
     random_generator: ThreadRng
@@ -12,14 +19,67 @@ pub struct RandomAgent {
 
 
 const GOOD_MOVE_COUNT: usize = 2;
-pub struct TomatoAgent {
+
+/// Root moves an agent that takes risks chooses from
+const RISK_LINES: usize = 3;
+pub struct TomatoAgent<E: Evaluator = MaterialMobilityEvaluator> {
     random_generator: ThreadRng,
+    evaluator: Arc<E>,
     good_ideas:  Vec<VecDeque<Move>>,
     max_depth: usize,
+    threads: usize,
+    transposition_table: Arc<TranspositionTable>,
+    stop: Arc<AtomicBool>,
+    root_exclusions: Vec<Move>,
+    /// Root moves the tables know to lose, kept apart from
+    /// the multi-PV exclusions that `analyze` clears
+    table_exclusions: Vec<Move>,
+    book: Option<Arc<OpeningBook>>,
+    book_depth: usize,
+    book_selection: BookSelection,
+    tablebases: Option<Arc<Tablebases>>,
+    dtm_tables: Option<Arc<DtmTablebases>>,
+    move_time: Option<Duration>,
+    node_limit: Option<usize>,
+    deadline: Option<Instant>,
+    /// Node count at which the running iteration stops
+    max_count: Option<usize>,
+    /// The running iteration hit its deadline or node limit
+    is_aborted: bool,
+    reporter: Option<Reporter>,
+    skill: Option<Skill>,
+    /// Pawns a draw costs the side to move at the root
+    contempt: f64,
+    /// Root moves this many pawns below the best are played too
+    risk: f64,
+    /// Lines of coordinate moves played from the starting position
+    openings: Vec<String>,
+    last_score: Option<f64>,
     pub prunes: usize,
     pub count: usize,
 }
 
+/// One line of a multi-PV analysis,
+/// score is from the perspective of the side to move
+pub struct AnalysisLine {
+    pub chess_move: Move,
+    pub score: f64,
+    pub pv: Vec<Move>,
+}
+
+/// One finished iteration of an iterative deepening search,
+/// score is from the perspective of the side to move
+pub struct SearchReport {
+    pub depth: usize,
+    pub score: f64,
+    pub nodes: usize,
+    pub elapsed: Duration,
+    pub pv: Vec<Move>,
+}
+
+/// Receives the report of every finished iteration
+pub type Reporter = Box<dyn FnMut(&SearchReport)>;
+
 
 struct ScoredMove {
     chess_move: Move,
@@ -47,25 +107,128 @@ impl ScoredMove {
 const MIN: f64 = -100000f64;
 const MAX: f64 = 100000f64;
 
-impl TomatoAgent {
+/// Score of a position the tablebases call won, below any mate found by the search
+const TABLEBASE_WIN: f64 = MAX / 2f64;
+
+/// Deepest ply a mate or tablebase score counts down to, so the
+/// scores within it of MAX and TABLEBASE_WIN keep their distance
+const MAX_PLY: f64 = 1000f64;
+
+/// Whether `score` is a win or loss by mate
+fn is_mate(score: f64) -> bool {
+    score.abs() > MAX - MAX_PLY
+}
+
+/// Whether `score` counts down plies from the root, a mate or a tablebase result
+fn is_distance_score(score: f64) -> bool {
+    is_mate(score) || (score.abs() > TABLEBASE_WIN - MAX_PLY && score.abs() <= TABLEBASE_WIN)
+}
+
+/// A mate or tablebase score found `depth` plies below the root, counted
+/// from the node instead, so the table gives it back right at any ply
+fn score_to_table(score: f64, depth: usize) -> f64 {
+    if !is_distance_score(score) {
+        score
+    } else if score > 0f64 {
+        score + depth as f64
+    } else {
+        score - depth as f64
+    }
+}
+
+/// A table score read `depth` plies below the root, the inverse of `score_to_table`
+fn score_from_table(score: f64, depth: usize) -> f64 {
+    if !is_distance_score(score) {
+        score
+    } else if score > 0f64 {
+        score - depth as f64
+    } else {
+        score + depth as f64
+    }
+}
+
+impl<E: Evaluator> TomatoAgent<E> {
     fn evaluate_move(&mut self, move_api : &mut MoveAPI, alpha: f64, beta: f64, color: f64, depth: usize) -> (f64, Option<Move>) {
+        if let Some(deadline) = self.deadline {
+            if self.count.is_multiple_of(1024) && Instant::now() >= deadline {
+                self.is_aborted = true;
+                self.stop.store(true, atomic::Ordering::Relaxed);
+            }
+        }
+        // checked before the leaf, so a leaf past the limit is not counted either
+        if self.max_count.is_some_and(|max_count| self.count >= max_count) {
+            self.is_aborted = true;
+            self.stop.store(true, atomic::Ordering::Relaxed);
+        }
+
+        // helper threads are abandoned once the main thread has an answer
+        if self.stop.load(atomic::Ordering::Relaxed) {
+            return (0f64, None);
+        }
+
         if depth >= self.max_depth  {
-            // todo remove hardcode
-            return (-move_api.get_evaluation() * color, None);
+            // the evaluation is from white's side, negamax wants it from
+            // the side to move, which `color` gives
+            let score = endgame::evaluate(move_api.get_board_ref(), self.evaluator.evaluate_position(move_api));
+            return (score * color, None);
+        }
+
+        let remaining = self.max_depth - depth;
+        let key = move_api.get_board_ref().get_hash();
+        let entry = self.transposition_table.probe(key).map(|entry| TableEntry {
+            score: score_from_table(entry.score, depth),
+            ..entry
+        });
+
+        // never cut at the root, the caller needs a move
+        if let Some(entry) = entry.filter(|e| depth > 0 && e.depth >= remaining) {
+            let is_usable = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => entry.score >= beta,
+                Bound::Upper => entry.score <= alpha,
+            };
+            if is_usable {
+                return (entry.score, entry.best_move);
+            }
+        }
+
+        // right after a capture or pawn move, the tables know the result
+        if depth > 0 && move_api.get_board_ref().halfmove_clock == 0 {
+            if let Some(wdl) = self.probe_wdl(move_api) {
+                return (self.get_tablebase_score(wdl, depth), None);
+            }
         }
 
         let mut a = alpha;
 
         let mut moves = move_api.get_legal_moves();
 
+        if depth == 0 {
+            moves.retain(|m| !self.root_exclusions.contains(m) && !self.table_exclusions.contains(m));
+        }
+
         if moves.len() == 0 {
-            return (MIN, None); // todo
+            // scored from the rules of the board's variant, a finished
+            // game is a loss for the side to move unless it won or drew
+            let turn = move_api.get_turn_color();
+            return match move_api.get_outcome() {
+                Outcome::Win(color) if color == turn => (MAX - depth as f64, None),
+                Outcome::Draw => (self.get_draw_score(depth), None),
+                _ => (MIN + depth as f64, None),
+            };
         }
         
         // TODO: Ordering moves has a bug found in 2025/04/06 that breaks the engine
         // TODO: Fix it
         // moves = self.order_moves(move_api, moves, color, depth);
 
+        // try the move that was best last time first
+        if let Some(hash_move) = entry.and_then(|e| e.best_move) {
+            if let Some(i) = moves.iter().position(|m| *m == hash_move) {
+                moves[..i + 1].rotate_right(1);
+            }
+        }
+
         let mut best_value = MIN;
         let mut best_move = moves[0];
 
@@ -73,10 +236,17 @@ impl TomatoAgent {
             move_api.exec_move(chess_move);
             let value = -self.evaluate_move(move_api, -beta, -a, -color, depth + 1).0;
             move_api.undo_move();
+            // a stopped child is not counted, so a node limit is met exactly
+            if self.stop.load(atomic::Ordering::Relaxed) {
+                return (0f64, None);
+            }
             self.count += 1;
 
             if value > best_value {
                 best_value = value;
+                // set before the cutoff, so a failing high node reports the
+                // refutation, which the table then tries first
+                best_move = chess_move;
                 a = f64::max(a, value);
                 if a >= beta {
                     self.prunes += 1;
@@ -88,19 +258,241 @@ impl TomatoAgent {
                     }
                     break;
                 }
-                best_move = chess_move;
             }
         }
 
+        // a stopped search returns garbage, and a root searched with
+        // excluded moves has a partial result, keep both out of the table
+        let is_partial = depth == 0 && !(self.root_exclusions.is_empty() && self.table_exclusions.is_empty());
+        if !self.stop.load(atomic::Ordering::Relaxed) && !is_partial {
+            let bound = if best_value >= beta {
+                Bound::Lower
+            } else if best_value <= alpha {
+                Bound::Upper
+            } else {
+                Bound::Exact
+            };
+            self.transposition_table.store(key, TableEntry {
+                depth: remaining,
+                // a draw's contempt only depends on whether the root side is
+                // to move, the same wherever the key comes up in one search,
+                // so only wins and losses are converted
+                score: score_to_table(best_value, depth),
+                bound,
+                best_move: Some(best_move),
+            });
+        }
+
         return (best_value, Some(best_move));
     }
+
     pub fn choose_best_move(&mut self, move_api : &mut MoveAPI, color: f64) -> Move {
-        // todo remove hardcode
-        let res = self.evaluate_move(move_api, MIN, MAX , 1f64, 0);
-        println!("Prediction: {}", res.0);
+        let res = if self.move_time.is_some() || self.node_limit.is_some() || self.reporter.is_some() {
+            self.search_iteratively(move_api, color)
+        } else {
+            self.search_root(move_api, color)
+        };
+        eprintln!("Prediction: {}", res.0);
+        self.last_score = Some(res.0);
         res.1.unwrap()
     }
 
+    /// Search one ply deeper at a time up to `max_depth` until the move
+    /// time or the node limit runs out. When not even one ply finishes
+    /// the first legal move is played
+    fn search_iteratively(&mut self, move_api : &mut MoveAPI, color: f64) -> (f64, Option<Move>) {
+        let start = Instant::now();
+        let start_count = self.count;
+        let best = self.deepen(move_api, |agent, move_api| agent.search_root(move_api, color), |agent, move_api, depth, res| {
+            if let (Some(chess_move), true) = (res.1, agent.reporter.is_some()) {
+                let report = SearchReport {
+                    depth,
+                    score: res.0,
+                    nodes: agent.count - start_count,
+                    elapsed: start.elapsed(),
+                    pv: agent.get_pv(move_api, chess_move),
+                };
+                (agent.reporter.as_mut().unwrap())(&report);
+            }
+            // a mate found stays one
+            is_mate(res.0)
+        });
+
+        match best {
+            Some(res) if res.1.is_some() => res,
+            _ => self.get_first_move(move_api, color),
+        }
+    }
+
+    /// Run `search` one ply deeper at a time up to `max_depth` until the move
+    /// time or the node limit runs out, and return the last result that
+    /// finished. An iteration cut short is thrown away. The move time never
+    /// stops the first one, the node limit does so the count stays exact.
+    /// `is_done` sees each finished result and can end the deepening early
+    fn deepen<T>(
+        &mut self,
+        move_api : &mut MoveAPI,
+        mut search: impl FnMut(&mut Self, &mut MoveAPI) -> T,
+        mut is_done: impl FnMut(&mut Self, &mut MoveAPI, usize, &T) -> bool,
+    ) -> Option<T> {
+        let start = Instant::now();
+        let start_count = self.count;
+        let max_depth = self.max_depth;
+        let mut best = None;
+
+        for depth in 1..=max_depth {
+            self.max_depth = depth;
+            self.deadline = self.move_time.filter(|_| depth > 1).map(|time| start + time);
+            self.max_count = self.node_limit.map(|nodes| start_count + nodes);
+            self.is_aborted = false;
+
+            let res = search(self, move_api);
+            if self.is_aborted {
+                self.stop.store(false, atomic::Ordering::Relaxed);
+                break;
+            }
+
+            // the next iteration takes longer than all before it together
+            let is_done = is_done(self, move_api, depth, &res) || self.move_time.is_some_and(|time| start.elapsed() * 2 > time);
+            best = Some(res);
+            if is_done {
+                break;
+            }
+        }
+
+        self.max_depth = max_depth;
+        self.deadline = None;
+        self.max_count = None;
+        best
+    }
+
+    /// The first legal move with the static score of the position,
+    /// played when the limits leave no time to search
+    fn get_first_move(&mut self, move_api : &mut MoveAPI, color: f64) -> (f64, Option<Move>) {
+        let first = move_api.get_legal_moves().first().copied();
+        let score = endgame::evaluate(move_api.get_board_ref(), self.evaluator.evaluate_position(move_api));
+        (score * color, first)
+    }
+
+    /// Find the best `lines` root moves, best first. With a move time
+    /// or node limit the lines come from the deepest search that finished
+    /// within it, as in `search_iteratively`
+    pub fn analyze(&mut self, move_api : &mut MoveAPI, color: f64, lines: usize) -> Vec<AnalysisLine> {
+        if self.move_time.is_none() && self.node_limit.is_none() {
+            return self.get_lines(move_api, color, lines);
+        }
+
+        let best = self.deepen(move_api, |agent, move_api| agent.get_lines(move_api, color, lines), |_, _, _, _| false);
+        match best {
+            Some(out) if !out.is_empty() => out,
+            _ => {
+                let (score, first) = self.get_first_move(move_api, color);
+                first.map(|chess_move| AnalysisLine { chess_move, score, pv: vec![chess_move] }).into_iter().collect()
+            }
+        }
+    }
+
+    /// The best `lines` root moves at `max_depth`, best first.
+    /// Each pass searches the root again with the moves
+    /// found by the earlier passes excluded
+    fn get_lines(&mut self, move_api : &mut MoveAPI, color: f64, lines: usize) -> Vec<AnalysisLine> {
+        let mut out = Vec::new();
+
+        for _ in 0..lines {
+            let res = self.search_root(move_api, color);
+            let chess_move = match res.1 {
+                Some(m) if !self.is_aborted => m,
+                _ => break,
+            };
+
+            out.push(AnalysisLine {
+                chess_move,
+                score: res.0,
+                pv: self.get_pv(move_api, chess_move),
+            });
+            self.root_exclusions.push(chess_move);
+        }
+        self.root_exclusions.clear();
+
+        // a later pass can see deeper table entries than an earlier one
+        out.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+        out
+    }
+
+    /// Principal variation starting with `first`, read back from the table
+    fn get_pv(&self, move_api : &mut MoveAPI, first: Move) -> Vec<Move> {
+        let mut pv = vec![first];
+        move_api.exec_move(first);
+
+        while pv.len() < self.max_depth {
+            let key = move_api.get_board_ref().get_hash();
+            let next = match self.transposition_table.probe(key).and_then(|e| e.best_move) {
+                Some(m) => m,
+                None => break,
+            };
+
+            // guard against hash collisions
+            if !move_api.get_legal_moves().contains(&next) {
+                break;
+            }
+            move_api.exec_move(next);
+            pv.push(next);
+        }
+
+        for _ in 0..pv.len() {
+            move_api.undo_move();
+        }
+        pv
+    }
+
+    fn search_root(&mut self, move_api : &mut MoveAPI, color: f64) -> (f64, Option<Move>) {
+        self.evaluator.prepare(move_api);
+
+        if self.threads <= 1 {
+            return self.evaluate_move(move_api, MIN, MAX, color, 0);
+        }
+
+        // Lazy SMP: helpers search the same root at slightly varied depths
+        // (odd helpers go one ply deeper), and the main thread picks up
+        // their work through the shared table
+        let board = *move_api.get_board_ref();
+        let helper_count = AtomicUsize::new(0);
+        self.stop.store(false, atomic::Ordering::Relaxed);
+
+        let res = thread::scope(|scope| {
+            for id in 1..self.threads {
+                let max_depth = self.max_depth + id % 2;
+                let transposition_table = self.transposition_table.clone();
+                let stop = self.stop.clone();
+                let evaluator = self.evaluator.clone();
+                let root_exclusions = self.root_exclusions.clone();
+                let table_exclusions = self.table_exclusions.clone();
+                let tablebases = self.tablebases.clone();
+                let contempt = self.contempt;
+                let helper_count = &helper_count;
+                scope.spawn(move || {
+                    let mut helper = TomatoAgent::new_helper(max_depth, evaluator, transposition_table, stop);
+                    helper.root_exclusions = root_exclusions;
+                    helper.table_exclusions = table_exclusions;
+                    helper.tablebases = tablebases;
+                    helper.contempt = contempt;
+                    let mut helper_api = MoveAPI::from_board(board);
+                    helper.evaluator.prepare(&mut helper_api);
+                    helper.evaluate_move(&mut helper_api, MIN, MAX, color, 0);
+                    helper_count.fetch_add(helper.count, atomic::Ordering::Relaxed);
+                });
+            }
+
+            let res = self.evaluate_move(move_api, MIN, MAX, color, 0);
+            self.stop.store(true, atomic::Ordering::Relaxed);
+            res
+        });
+        self.stop.store(false, atomic::Ordering::Relaxed);
+        self.count += helper_count.load(atomic::Ordering::Relaxed);
+
+        res
+    }
+
     
 
 
@@ -123,7 +515,7 @@ impl TomatoAgent {
 
         for chess_move in &moves {
             move_api.exec_move(chess_move.clone());
-            let board_value = color * -move_api.get_evaluation(); // todo negative is hardcoded (not anymore?)
+            let board_value = color * -self.evaluator.evaluate_position(move_api); // todo negative is hardcoded (not anymore?)
             let freedom_value = move_api.get_piece_move_count(sColor) as f64;
             let aggression_value = freedom_value - move_api.get_piece_move_count(oColor) as f64;
             let piece_value = piece::get_piece_dev_value(chess_move.piece) as f64;
@@ -157,7 +549,21 @@ impl TomatoAgent {
 
 
 
-    pub fn new(max_depth: usize)-> Self {
+    /// Search with a custom evaluation function
+    pub fn with_evaluator(max_depth: usize, evaluator: E) -> Self {
+        TomatoAgent::with_table_entries(max_depth, evaluator, transposition::DEFAULT_ENTRIES)
+    }
+
+    /// Search with a custom evaluation function and a
+    /// transposition table of `entries` slots
+    pub fn with_table_entries(max_depth: usize, evaluator: E, entries: usize) -> Self {
+        let transposition_table = Arc::new(TranspositionTable::new(entries));
+        TomatoAgent::new_helper(max_depth, Arc::new(evaluator), transposition_table, Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Build an agent around an existing evaluator, table and stop flag,
+    /// used for the Lazy SMP helper threads
+    fn new_helper(max_depth: usize, evaluator: Arc<E>, transposition_table: Arc<TranspositionTable>, stop: Arc<AtomicBool>) -> Self {
         let mut random_generator = rand::thread_rng();  
 
         let mut good_ideas = Vec::new();
@@ -169,19 +575,239 @@ impl TomatoAgent {
 
         TomatoAgent {
             random_generator,
+            evaluator,
             good_ideas,
             max_depth,
+            threads: 1,
+            transposition_table,
+            stop,
+            root_exclusions: Vec::new(),
+            table_exclusions: Vec::new(),
+            book: None,
+            book_depth: 0,
+            book_selection: BookSelection::WeightedRandom,
+            tablebases: None,
+            dtm_tables: None,
+            move_time: None,
+            node_limit: None,
+            deadline: None,
+            max_count: None,
+            is_aborted: false,
+            reporter: None,
+            skill: None,
+            contempt: 0f64,
+            risk: 0f64,
+            openings: Vec::new(),
+            last_score: None,
             prunes: 0,
             count: 0,
         }
     }
 
+    /// Score of the last move the search chose, from the perspective of
+    /// the side that played it. None for book and tablebase moves
+    pub fn get_last_score(&self) -> Option<f64> {
+        self.last_score
+    }
+
+    /// Search with `threads` threads, 1 keeps the search deterministic
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Search deeper one ply at a time until `time` is spent,
+    /// the depth the agent was built with stays the limit
+    pub fn with_move_time(mut self, time: Duration) -> Self {
+        self.move_time = Some(time);
+        self
+    }
+
+    /// Search deeper one ply at a time until `nodes` nodes are searched,
+    /// the depth the agent was built with stays the limit. Nodes are counted
+    /// on the main thread, so with one thread the search is reproducible.
+    /// A limit too small for one ply plays the first legal move
+    pub fn with_node_limit(mut self, nodes: usize) -> Self {
+        self.node_limit = Some(nodes);
+        self
+    }
+
+    /// Play at `skill`, below full strength the level caps the depth
+    pub fn with_skill(mut self, skill: Skill) -> Self {
+        if let Some(depth) = skill.get_max_depth() {
+            self.max_depth = self.max_depth.min(depth);
+        }
+        self.skill = Some(skill);
+        self
+    }
+
+    /// Play in the style of `personality`: its contempt, risk and openings.
+    /// Its weights are in `personality.get_evaluator()`
+    pub fn with_personality(mut self, personality: &Personality) -> Self {
+        self.contempt = personality.contempt;
+        self.risk = personality.risk;
+        self.openings = personality.openings.clone();
+        self
+    }
+
+    /// Score of a draw for the side to move `depth` plies below the root
+    fn get_draw_score(&self, depth: usize) -> f64 {
+        if depth.is_multiple_of(2) {
+            -self.contempt
+        } else {
+            self.contempt
+        }
+    }
+
+    /// The next move of a preferred opening the game follows so far,
+    /// a random one when several do
+    fn get_opening_move(&mut self, move_api: &mut MoveAPI) -> Option<Move> {
+        let board = *move_api.get_board_ref();
+        let mut candidates = Vec::new();
+        for line in &self.openings {
+            let mut line_api = MoveAPI::new(fen::STARTING_BOARD);
+            for text in line.split_whitespace() {
+                let Some(m) = line_api.find_coordinate_move(text) else { break };
+                if *line_api.get_board_ref() == board {
+                    candidates.push(m);
+                    break;
+                }
+                line_api.exec_move(m);
+            }
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(candidates[self.random_generator.gen_range(0..candidates.len())])
+    }
+
+    /// Call `reporter` after every finished iteration of the search
+    pub fn with_reporter(mut self, reporter: Reporter) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    /// Play from `book` before searching, for the first `max_depth` plies of the game
+    pub fn with_book(mut self, book: Arc<OpeningBook>, max_depth: usize, selection: BookSelection) -> Self {
+        self.book = Some(book);
+        self.book_depth = max_depth;
+        self.book_selection = selection;
+        self
+    }
+
+    /// A move from the opening book, if the game is still early enough
+    fn get_book_move(&mut self, move_api: &mut MoveAPI) -> Option<Move> {
+        let book = self.book.as_ref()?;
+        if move_api.get_board_ref().get_ply() >= self.book_depth {
+            return None;
+        }
+        book.choose_move(move_api, self.book_selection, &mut self.random_generator)
+    }
+
+    /// Probe Syzygy tables, at the root to pick moves and in the search for results
+    pub fn with_tablebases(mut self, tablebases: Arc<Tablebases>) -> Self {
+        self.tablebases = Some(tablebases);
+        self
+    }
+
+    fn probe_wdl(&self, move_api: &mut MoveAPI) -> Option<Wdl> {
+        let tablebases = self.tablebases.as_ref()?;
+        tablebases.probe_wdl(move_api)
+    }
+
+    /// Search score of a tablebase result, wins found
+    /// nearer the root score higher. The fifty move rule
+    /// makes cursed wins and blessed losses draws
+    fn get_tablebase_score(&self, wdl: Wdl, depth: usize) -> f64 {
+        match wdl {
+            Wdl::Win => TABLEBASE_WIN - depth as f64,
+            Wdl::Loss => -TABLEBASE_WIN + depth as f64,
+            _ => self.get_draw_score(depth),
+        }
+    }
+
+    /// The root move the tables prefer. A win plays the move with the
+    /// lowest DTZ, a loss the one that holds out longest. When the best
+    /// result is a draw the losing moves are excluded and the search
+    /// picks among the rest
+    fn get_tablebase_move(&mut self, move_api: &mut MoveAPI) -> Option<Move> {
+        let tablebases = self.tablebases.as_ref()?;
+        let moves = tablebases.probe_root(move_api)?;
+        let halfmove_clock = move_api.get_board_ref().halfmove_clock as i32;
+
+        // same ranking as the reference prober: certain wins rank equally.
+        // Wins past the fifty move rule still rank above draws, the nearer
+        // the better, and losses past it below them, though the search
+        // scores both as draws
+        let get_rank = |dtz: i32| {
+            if dtz > 0 {
+                if dtz + halfmove_clock <= 99 { 1000 } else { 1000 - (dtz + halfmove_clock) }
+            } else if dtz < 0 {
+                if -dtz * 2 + halfmove_clock < 100 { -1000 } else { -1000 + (-dtz + halfmove_clock) }
+            } else {
+                0
+            }
+        };
+
+        let best_rank = moves.iter().map(|(_, dtz)| get_rank(*dtz)).max()?;
+        if best_rank == 0 {
+            self.table_exclusions = moves
+                .iter()
+                .filter(|(_, dtz)| get_rank(*dtz) < 0)
+                .map(|(m, _)| *m)
+                .collect();
+            return None;
+        }
+
+        moves
+            .iter()
+            .filter(|(_, dtz)| get_rank(*dtz) == best_rank)
+            .min_by_key(|(_, dtz)| *dtz)
+            .map(|(m, _)| *m)
+    }
+
+    /// Play from generated distance-to-mate tables at the root
+    pub fn with_dtm_tables(mut self, dtm_tables: Arc<DtmTablebases>) -> Self {
+        self.dtm_tables = Some(dtm_tables);
+        self
+    }
+
+    /// The root move the distance-to-mate tables prefer: the fastest mate,
+    /// or the longest defence when every move loses. When the best result
+    /// is a draw the losing moves are excluded and the search picks among the rest
+    fn get_dtm_move(&mut self, move_api: &mut MoveAPI) -> Option<Move> {
+        let dtm_tables = self.dtm_tables.as_ref()?;
+        let moves = dtm_tables.probe_root(move_api)?;
+
+        let get_rank = |dtm: Dtm| match dtm {
+            Dtm::Win(plies) => 1000 - plies as i32,
+            Dtm::Draw => 0,
+            Dtm::Loss(plies) => -1000 + plies as i32,
+        };
+
+        let (best_move, best_dtm) = *moves.iter().max_by_key(|(_, dtm)| get_rank(*dtm))?;
+        if best_dtm == Dtm::Draw {
+            self.table_exclusions = moves
+                .iter()
+                .filter(|(_, dtm)| matches!(dtm, Dtm::Loss(_)))
+                .map(|(m, _)| *m)
+                .collect();
+            return None;
+        }
+        Some(best_move)
+    }
 
     
 
 }
 
-impl Agent for TomatoAgent {
+impl TomatoAgent {
+    pub fn new(max_depth: usize)-> Self {
+        TomatoAgent::with_evaluator(max_depth, MaterialMobilityEvaluator::new())
+    }
+}
+
+impl<E: Evaluator> Agent for TomatoAgent<E> {
     fn getAgentMove(&mut self, board_logic: &mut ChessGame) -> Option<Move> {
         //Move::new(Square::E2, Square::E4, Piece::new("c"), None )
 
@@ -191,15 +817,59 @@ impl Agent for TomatoAgent {
             -1f64
         };
 
+        self.last_score = None;
         let possible_moves = board_logic.get_legal_moves();
         if possible_moves.len() == 0 {
             return None;
         }
 
-        let fen = board_logic.get_fen();
-        let mut move_api = MoveAPI::new(&fen);
-        
-        return Some(self.choose_best_move(&mut move_api, color));
+        let mut move_api = MoveAPI::from_board(board_logic.get_board());
+        self.table_exclusions.clear();
+
+        if let Some(opening_move) = self.get_opening_move(&mut move_api) {
+            eprintln!("Opening move: {}", opening_move.to_string());
+            return Some(opening_move);
+        }
+
+        if let Some(book_move) = self.get_book_move(&mut move_api) {
+            eprintln!("Book move: {}", book_move.to_string());
+            return Some(book_move);
+        }
+
+        if let Some(dtm_move) = self.get_dtm_move(&mut move_api) {
+            eprintln!("DTM table move: {}", dtm_move.to_string());
+            return Some(dtm_move);
+        }
+
+        if let Some(tablebase_move) = self.get_tablebase_move(&mut move_api) {
+            eprintln!("Tablebase move: {}", tablebase_move.to_string());
+            return Some(tablebase_move);
+        }
+
+        if let Some(skill) = self.skill.filter(|skill| !skill.is_full_strength()) {
+            let lines = self.analyze(&mut move_api, color, skill.get_lines());
+            if let Some(skill_move) = skill.choose_move(&lines, &possible_moves, &mut self.random_generator) {
+                eprintln!("Skill level {} move: {}", skill.get_level(), skill_move.to_string());
+                self.last_score = lines.iter().find(|line| line.chess_move == skill_move).map(|line| line.score);
+                return Some(skill_move);
+            }
+        }
+
+        if self.risk > 0f64 {
+            let lines = self.analyze(&mut move_api, color, RISK_LINES);
+            let best_score = lines.first().map_or(0f64, |line| line.score);
+            let candidates: Vec<&AnalysisLine> = lines.iter().filter(|line| line.score >= best_score - self.risk).collect();
+            if !candidates.is_empty() {
+                let line = candidates[self.random_generator.gen_range(0..candidates.len())];
+                eprintln!("Risky move: {}", line.chess_move.to_string());
+                self.last_score = Some(line.score);
+                return Some(line.chess_move);
+            }
+        }
+
+        let best_move = self.choose_best_move(&mut move_api, color);
+        self.root_exclusions.clear();
+        return Some(best_move);
         
 
     }
@@ -246,3 +916,198 @@ impl Agent for RandomAgent {
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::piece::Piece;
+    use crate::game::skill::Skill;
+    use crate::syzygy_builder;
+
+    fn open_test_tablebases() -> Arc<Tablebases> {
+        Arc::new(Tablebases::open(syzygy_builder::build_test_tables().to_str().unwrap()))
+    }
+
+    /// At the root the tables pick the winning move nearest a zeroing move or mate
+    #[test]
+    fn tablebase_move_has_lowest_dtz() {
+        let fen = "8/8/8/8/8/2k5/8/KQ6 w - - 0 1";
+        let tablebases = open_test_tablebases();
+        let mut agent = TomatoAgent::new(1).with_tablebases(tablebases.clone());
+        let chosen = agent.getAgentMove(&mut ChessGame::new(MoveAPI::new(fen))).unwrap();
+
+        let moves = tablebases.probe_root(&mut MoveAPI::new(fen)).unwrap();
+        let best_dtz = moves.iter().map(|(_, dtz)| *dtz).filter(|dtz| *dtz > 0).min().unwrap();
+        let chosen_dtz = moves.iter().find(|(m, _)| *m == chosen).map(|(_, dtz)| *dtz);
+        assert_eq!(chosen_dtz, Some(best_dtz));
+    }
+
+    /// Both sides take a hanging queen, the leaf is scored for the side to move
+    #[test]
+    fn search_takes_free_queen_for_either_side() {
+        for (fen, captured) in [("4k3/8/8/3q4/8/8/3Q4/4K3 w - - 0 1", 'q'), ("4k3/3q4/8/8/3Q4/8/8/4K3 b - - 0 1", 'Q')] {
+            let mut agent = TomatoAgent::new(2);
+            let chosen = agent.getAgentMove(&mut ChessGame::new(MoveAPI::new(fen))).unwrap();
+            let mut move_api = MoveAPI::new(fen);
+            move_api.exec_move(chosen);
+            assert!(!move_api.get_board_ref().get_fen().split(' ').next().unwrap().contains(captured), "{}", fen);
+        }
+    }
+
+    /// A win the fifty move rule has cursed is still played for,
+    /// by the move nearest a zeroing move
+    #[test]
+    fn tablebase_move_plays_cursed_win() {
+        let tablebases = open_test_tablebases();
+        let fen = |clock: i32| format!("8/8/8/8/8/2k5/8/KQ6 w - - {} 1", clock);
+        let moves = tablebases.probe_root(&mut MoveAPI::new(&fen(0))).unwrap();
+        let best_dtz = moves.iter().map(|(_, dtz)| *dtz).filter(|dtz| *dtz > 0).min().unwrap();
+
+        // with this clock even the fastest win runs past the fifty moves
+        let clock = 100 - best_dtz;
+        let mut move_api = MoveAPI::new(&fen(clock));
+
+        let mut agent = TomatoAgent::new(1).with_tablebases(tablebases);
+        let chosen = agent.get_tablebase_move(&mut move_api).unwrap();
+        assert!(agent.table_exclusions.is_empty());
+        assert_eq!(moves.iter().find(|(m, _)| *m == chosen).map(|(_, dtz)| *dtz), Some(best_dtz));
+    }
+
+    /// Right after a pawn move the search takes the result from the tables,
+    /// the pawn outruns the king long past the search horizon
+    #[test]
+    fn search_scores_tablebase_wins() {
+        let mut move_api = MoveAPI::new("8/8/8/8/8/k7/7P/7K w - - 0 1");
+        let mut agent = TomatoAgent::new(2).with_tablebases(open_test_tablebases());
+        let (score, best_move) = agent.search_root(&mut move_api, 1f64);
+        assert_eq!(score, TABLEBASE_WIN - 1f64);
+        assert_eq!(best_move.map(|m| m.piece), Some(Piece::Pawn));
+    }
+
+    /// A result stored at one ply reads back at another
+    /// as the same distance from the node
+    #[test]
+    fn table_scores_keep_distance_from_node() {
+        // won two plies below a node three plies deep, read one ply deep
+        assert_eq!(score_from_table(score_to_table(TABLEBASE_WIN - 5f64, 3), 1), TABLEBASE_WIN - 3f64);
+        assert_eq!(score_from_table(score_to_table(-TABLEBASE_WIN + 5f64, 3), 1), -TABLEBASE_WIN + 3f64);
+        assert_eq!(score_from_table(score_to_table(MIN + 4f64, 4), 2), MIN + 2f64);
+        assert_eq!(score_from_table(score_to_table(2.5, 4), 2), 2.5);
+    }
+
+    /// Each line starts with its own root move, best first,
+    /// and the first is the move a plain search picks
+    #[test]
+    fn analysis_lines_are_distinct_and_ordered() {
+        let fen = "4k3/8/8/3q4/8/2N5/3R4/4K3 w - - 0 1";
+        let lines = TomatoAgent::new(3).analyze(&mut MoveAPI::new(fen), 1f64, 4);
+        assert_eq!(lines.len(), 4);
+
+        for (i, line) in lines.iter().enumerate() {
+            assert!(lines[..i].iter().all(|earlier| earlier.chess_move != line.chess_move));
+        }
+        assert!(lines.windows(2).all(|pair| pair[0].score >= pair[1].score));
+
+        let best_move = TomatoAgent::new(3).search_root(&mut MoveAPI::new(fen), 1f64).1;
+        assert!(best_move == Some(lines[0].chess_move));
+    }
+
+    /// One thread searches the same tree every time
+    #[test]
+    fn single_thread_search_repeats() {
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+        let search = || {
+            let mut agent = TomatoAgent::new(4).with_threads(1);
+            let (score, best_move) = agent.search_root(&mut MoveAPI::new(fen), 1f64);
+            (score, best_move.unwrap(), agent.count)
+        };
+        let (first_score, first_move, first_count) = search();
+        for _ in 0..2 {
+            let (score, best_move, count) = search();
+            assert!(best_move == first_move);
+            assert_eq!(score, first_score);
+            assert_eq!(count, first_count);
+        }
+    }
+
+    /// Helper threads may change the move but not a forced mate
+    #[test]
+    fn threads_agree_on_mate() {
+        let fen = "6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1";
+        let (single_score, _) = TomatoAgent::new(3).search_root(&mut MoveAPI::new(fen), 1f64);
+        assert_eq!(single_score, MAX - 1f64);
+
+        let mut move_api = MoveAPI::new(fen);
+        let (score, best_move) = TomatoAgent::new(3).with_threads(4).search_root(&mut move_api, 1f64);
+        assert_eq!(score, single_score);
+        assert!(move_api.get_legal_moves().contains(&best_move.unwrap()));
+    }
+
+    /// The node limit stops the search at the same node every time
+    #[test]
+    fn node_limited_search_repeats() {
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+        let search = || {
+            let mut agent = TomatoAgent::new(6).with_node_limit(5000);
+            let best_move = agent.choose_best_move(&mut MoveAPI::new(fen), 1f64);
+            (best_move, agent.count)
+        };
+        let (first_move, first_count) = search();
+        let (best_move, count) = search();
+        assert!(best_move == first_move);
+        assert_eq!(count, first_count);
+    }
+
+    /// A limit that stops the first ply still gives a move
+    #[test]
+    fn tiny_node_limit_plays_a_legal_move() {
+        let mut move_api = MoveAPI::new(fen::STARTING_BOARD);
+        let best_move = TomatoAgent::new(4).with_node_limit(1).choose_best_move(&mut move_api, 1f64);
+        assert!(move_api.get_legal_moves().contains(&best_move));
+    }
+
+    /// A weakened agent analyses several lines but keeps to its node budget
+    #[test]
+    fn skill_agent_keeps_to_node_limit() {
+        let mut game = ChessGame::new(MoveAPI::new(fen::STARTING_BOARD));
+        let mut agent = TomatoAgent::new(32).with_skill(Skill::new(15)).with_node_limit(3000);
+        let chosen = agent.getAgentMove(&mut game).unwrap();
+        assert!(agent.count <= 3000);
+        assert!(game.get_legal_moves().contains(&chosen));
+    }
+
+    /// A personality's opening is followed while the game stays on one of its lines
+    #[test]
+    fn personality_follows_its_openings() {
+        let mut personality = Personality::new("test");
+        personality.openings = vec![String::from("e2e4 e7e5 g1f3"), String::from("e2e4 e7e5 f2f4"), String::from("d2d4 d7d5")];
+        let mut agent = TomatoAgent::new(1).with_personality(&personality);
+
+        let mut move_api = MoveAPI::new(fen::STARTING_BOARD);
+        for text in ["e2e4", "e7e5"] {
+            let m = move_api.find_coordinate_move(text).unwrap();
+            move_api.exec_move(m);
+        }
+        let expected = [move_api.find_coordinate_move("g1f3").unwrap(), move_api.find_coordinate_move("f2f4").unwrap()];
+        for _ in 0..20 {
+            let chosen = agent.get_opening_move(&mut move_api).unwrap();
+            assert!(expected.contains(&chosen));
+        }
+
+        let m = move_api.find_coordinate_move("b1c3").unwrap();
+        move_api.exec_move(m);
+        assert!(agent.get_opening_move(&mut move_api).is_none());
+    }
+
+    /// A risky personality's analysis stops at the move time however deep it may go
+    #[test]
+    fn risky_agent_keeps_to_move_time() {
+        let mut game = ChessGame::new(MoveAPI::new(fen::STARTING_BOARD));
+        let mut agent = TomatoAgent::new(32).with_move_time(Duration::from_millis(100));
+        agent.risk = 0.5;
+        let start = Instant::now();
+        let chosen = agent.getAgentMove(&mut game).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(game.get_legal_moves().contains(&chosen));
+    }
+}
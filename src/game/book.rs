@@ -0,0 +1,161 @@
+use std::fs;
+
+use rand::Rng;
+
+use crate::chess::{cmove::Move, move_api::MoveAPI, polyglot, variant::Variant};
+
+/// Book read by the server when a request does not name one
+pub const DEFAULT_BOOK_FILE: &str = "books/default.bin";
+
+/// Plies after which the agent stops looking in the book
+pub const DEFAULT_BOOK_DEPTH: usize = 16;
+
+/// Size of an entry in a Polyglot book
+const ENTRY_SIZE: usize = 16;
+
+/// One position-move pair of a Polyglot book.
+/// Entries are 16 big endian bytes: key (8), move (2), weight (2), learn (4)
+#[derive(Copy, Clone, Debug)]
+pub struct BookEntry {
+    pub key: u64,
+    pub chess_move: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+impl BookEntry {
+    fn to_bytes(self) -> [u8; ENTRY_SIZE] {
+        let mut out = [0; ENTRY_SIZE];
+        out[0..8].copy_from_slice(&self.key.to_be_bytes());
+        out[8..10].copy_from_slice(&self.chess_move.to_be_bytes());
+        out[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        out[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        out
+    }
+}
+
+/// How to pick among the book moves of a position
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BookSelection {
+    /// Random, in proportion to the weights
+    WeightedRandom,
+    /// Always the move with the highest weight
+    Best,
+}
+
+impl BookSelection {
+    /// Look up a selection by the name used in requests
+    pub fn from_name(name: &str) -> Option<BookSelection> {
+        match name {
+            "weighted" => Some(BookSelection::WeightedRandom),
+            "best" => Some(BookSelection::Best),
+            _ => None,
+        }
+    }
+}
+
+/// Opening book in the Polyglot `.bin` format, entries sorted by key
+pub struct OpeningBook {
+    entries: Vec<BookEntry>,
+}
+
+impl OpeningBook {
+    pub fn load(path: &str) -> Result<OpeningBook, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        OpeningBook::from_bytes(&bytes)
+    }
+
+    /// Sort the entries by key as the format requires,
+    /// moves of a position go best first
+    pub fn from_entries(mut entries: Vec<BookEntry>) -> OpeningBook {
+        entries.sort_by(|a, b| a.key.cmp(&b.key).then(b.weight.cmp(&a.weight)));
+        OpeningBook { entries }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let bytes: Vec<u8> = self.entries.iter().flat_map(|e| e.to_bytes()).collect();
+        fs::write(path, bytes).map_err(|e| format!("Could not write {}: {}", path, e))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<OpeningBook, String> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(String::from("Book file has the wrong size"));
+        }
+
+        let mut entries: Vec<BookEntry> = bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(|c| BookEntry {
+                key: u64::from_be_bytes(c[0..8].try_into().unwrap()),
+                chess_move: u16::from_be_bytes([c[8], c[9]]),
+                weight: u16::from_be_bytes([c[10], c[11]]),
+                learn: u32::from_be_bytes(c[12..16].try_into().unwrap()),
+            })
+            .collect();
+
+        // books are written sorted, but lookups rely on it
+        entries.sort_by_key(|e| e.key);
+        Ok(OpeningBook { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Every entry stored for `key`
+    pub fn get_entries(&self, key: u64) -> &[BookEntry] {
+        let start = self.entries.partition_point(|e| e.key < key);
+        let end = self.entries.partition_point(|e| e.key <= key);
+        &self.entries[start..end]
+    }
+
+    /// Legal moves the book knows for the current position, with their weights.
+    /// Books are written for standard chess, other variants have no book moves
+    pub fn get_moves(&self, move_api: &mut MoveAPI) -> Vec<(Move, u16)> {
+        if move_api.get_variant() != Variant::Standard {
+            return Vec::new();
+        }
+
+        let board = *move_api.get_board_ref();
+        let entries = self.get_entries(polyglot::hash(&board));
+        if entries.is_empty() {
+            return Vec::new();
+        }
+
+        // entries are matched against legal moves, so a key
+        // collision can never produce an illegal move
+        let legal_moves = move_api.get_legal_moves();
+        entries
+            .iter()
+            .filter_map(|entry| {
+                legal_moves
+                    .iter()
+                    .find(|m| polyglot::encode_move(&board, m) == entry.chess_move)
+                    .map(|m| (*m, entry.weight))
+            })
+            .collect()
+    }
+
+    /// A book move for the current position, if there is one
+    pub fn choose_move<R: Rng>(&self, move_api: &mut MoveAPI, selection: BookSelection, rng: &mut R) -> Option<Move> {
+        let moves = self.get_moves(move_api);
+
+        match selection {
+            BookSelection::Best => moves.iter().max_by_key(|(_, weight)| *weight).map(|(m, _)| *m),
+            BookSelection::WeightedRandom => {
+                let total: u32 = moves.iter().map(|(_, weight)| *weight as u32).sum();
+                if total == 0 {
+                    return None;
+                }
+
+                let mut pick = rng.gen_range(0..total);
+                for (m, weight) in moves {
+                    if pick < weight as u32 {
+                        return Some(m);
+                    }
+                    pick -= weight as u32;
+                }
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use crate::chess::{board::Board, color::Color, piece::Piece, variant::Variant};
+
+use super::evaluator::Score;
+use super::kpk;
+
+/// Score of an endgame known to be won, above anything the
+/// evaluators give, below the scores the search gives mates
+const KNOWN_WIN: Score = 100f64;
+
+/// What a recognized endgame does to the normal evaluation
+enum Verdict {
+    /// Replace it, positive is good for White
+    Exact(Score),
+    /// Multiply it, drawish endgames move the score toward 0
+    Scale(f64),
+}
+
+/// Pieces of each type of one side, indexed by Piece
+type Material = [u32; 6];
+
+fn get_material(board: &Board, color: Color) -> Material {
+    let mut out = [0; 6];
+    for piece in [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King] {
+        out[piece as usize] = board.get_color_piece_board(piece, color).count_ones();
+    }
+    out
+}
+
+/// Material of a side holding its king and `pieces`
+fn is_exactly(material: &Material, pieces: &[Piece]) -> bool {
+    let mut expected = [0u32, 0, 0, 0, 0, 1];
+    for piece in pieces {
+        expected[*piece as usize] += 1;
+    }
+    *material == expected
+}
+
+/// Squares of the pieces of a type, counted from a1 = 0 to h8 = 63
+fn get_squares(board: &Board, piece: Piece, color: Color) -> Vec<usize> {
+    let bitboard = board.get_color_piece_board(piece, color);
+    (0..64).filter(|i| bitboard & (1u64 << (63 - i)) != 0).map(|i| i ^ 56).collect()
+}
+
+fn get_square(board: &Board, piece: Piece, color: Color) -> usize {
+    get_squares(board, piece, color)[0]
+}
+
+fn file_of(square: usize) -> i32 {
+    (square & 7) as i32
+}
+
+fn rank_of(square: usize) -> i32 {
+    (square >> 3) as i32
+}
+
+fn distance(a: usize, b: usize) -> i32 {
+    (file_of(a) - file_of(b)).abs().max((rank_of(a) - rank_of(b)).abs())
+}
+
+fn is_dark(square: usize) -> bool {
+    (file_of(square) + rank_of(square)) % 2 == 0
+}
+
+/// Larger the nearer the square is to an edge, 0 in the center
+fn push_to_edge(square: usize) -> f64 {
+    let edge = file_of(square).min(7 - file_of(square)).min(rank_of(square)).min(7 - rank_of(square));
+    (3 - edge) as f64
+}
+
+/// Larger the nearer the kings are, mates need the attacking king close
+fn push_close(a: usize, b: usize) -> f64 {
+    (7 - distance(a, b)) as f64 * 0.5
+}
+
+/// The evaluation of `board` with what is known about its endgame applied.
+/// `score` is the normal evaluation, positive is good for White
+pub fn evaluate(board: &Board, score: Score) -> Score {
+    match recognize(board) {
+        Some(Verdict::Exact(value)) => value,
+        Some(Verdict::Scale(factor)) => score * factor,
+        None => score,
+    }
+}
+
+fn recognize(board: &Board) -> Option<Verdict> {
+    if board.variant != Variant::Standard {
+        return None;
+    }
+
+    let white = get_material(board, Color::White);
+    let black = get_material(board, Color::Black);
+
+    for (strong, weak, strong_material, weak_material) in [
+        (Color::White, Color::Black, &white, &black),
+        (Color::Black, Color::White, &black, &white),
+    ] {
+        let sign = if strong == Color::White { 1f64 } else { -1f64 };
+        let verdict = if is_exactly(strong_material, &[Piece::Pawn]) && is_exactly(weak_material, &[]) {
+            Some(evaluate_kpk(board, strong, weak))
+        } else if is_exactly(weak_material, &[])
+            && (is_exactly(strong_material, &[Piece::Queen]) || is_exactly(strong_material, &[Piece::Rook]))
+        {
+            Some(evaluate_kxk(board, strong, weak))
+        } else if is_exactly(strong_material, &[Piece::Bishop, Piece::Knight]) && is_exactly(weak_material, &[]) {
+            Some(evaluate_kbnk(board, strong, weak))
+        } else if is_exactly(strong_material, &[Piece::Rook]) && is_exactly(weak_material, &[Piece::Pawn]) {
+            Some(evaluate_krkp(board, strong, weak))
+        } else if is_wrong_rook_pawn(board, strong, weak, strong_material, weak_material) {
+            Some(0f64)
+        } else {
+            None
+        };
+
+        if let Some(value) = verdict {
+            return Some(Verdict::Exact(sign * value));
+        }
+    }
+
+    get_opposite_bishops_scale(board, &white, &black).map(Verdict::Scale)
+}
+
+/// From the bitbase, won positions score more the further the pawn is
+fn evaluate_kpk(board: &Board, strong: Color, weak: Color) -> Score {
+    let strong_king = get_square(board, Piece::King, strong);
+    let weak_king = get_square(board, Piece::King, weak);
+    let pawn = get_square(board, Piece::Pawn, strong);
+
+    let is_strong_white = strong == Color::White;
+    if !kpk::bitbase().is_win(is_strong_white, board.turn == strong, strong_king, weak_king, pawn) {
+        return 0f64;
+    }
+    let rank = if is_strong_white { rank_of(pawn) } else { 7 - rank_of(pawn) };
+    KNOWN_WIN + rank as f64
+}
+
+/// Queen or rook against a lone king: drive the king to the edge
+fn evaluate_kxk(board: &Board, strong: Color, weak: Color) -> Score {
+    let strong_king = get_square(board, Piece::King, strong);
+    let weak_king = get_square(board, Piece::King, weak);
+    let material = board.get_material_score(strong);
+
+    KNOWN_WIN + material + push_to_edge(weak_king) + push_close(strong_king, weak_king)
+}
+
+/// Bishop and knight mate only in a corner of the bishop's color
+fn evaluate_kbnk(board: &Board, strong: Color, weak: Color) -> Score {
+    let strong_king = get_square(board, Piece::King, strong);
+    let weak_king = get_square(board, Piece::King, weak);
+    let bishop = get_square(board, Piece::Bishop, strong);
+
+    // a1 and h8 are dark, a8 and h1 light
+    let corners = if is_dark(bishop) { [0, 63] } else { [56, 7] };
+    let corner_distance = corners.iter().map(|c| distance(weak_king, *c)).min().unwrap();
+    let material = board.get_material_score(strong);
+
+    KNOWN_WIN + material + (7 - corner_distance) as f64 + push_close(strong_king, weak_king)
+}
+
+/// Rook against pawn: won when the rook side's king stops the pawn or the
+/// defending king is far away, otherwise a race between the kings
+fn evaluate_krkp(board: &Board, strong: Color, weak: Color) -> Score {
+    // look at the board with the pawn running down toward rank 1
+    let flip = if weak == Color::Black { 0 } else { 56 };
+    let strong_king = get_square(board, Piece::King, strong) ^ flip;
+    let weak_king = get_square(board, Piece::King, weak) ^ flip;
+    let rook = get_square(board, Piece::Rook, strong) ^ flip;
+    let pawn = get_square(board, Piece::Pawn, weak) ^ flip;
+    let queening = file_of(pawn) as usize;
+    let is_strong_to_move = board.turn == strong;
+
+    let rook_value = board.get_piece_score(Piece::Rook, strong);
+    let is_king_in_front = file_of(strong_king) == file_of(pawn) && rank_of(strong_king) < rank_of(pawn);
+    let is_king_far = distance(weak_king, pawn) >= 3 + !is_strong_to_move as i32 && distance(weak_king, rook) >= 3;
+
+    if is_king_in_front || is_king_far {
+        rook_value - distance(strong_king, pawn) as f64 * 0.1
+    } else if rank_of(weak_king) <= 2
+        && distance(weak_king, pawn) == 1
+        && rank_of(strong_king) >= 3
+        && distance(strong_king, pawn) > 2 + is_strong_to_move as i32
+    {
+        // the pawn is far advanced and its king supports it
+        0.8 - distance(strong_king, pawn) as f64 * 0.08
+    } else {
+        let below = pawn - 8;
+        let race = distance(strong_king, below) - distance(weak_king, below) - distance(pawn, queening);
+        2f64 - race as f64 * 0.08
+    }
+}
+
+/// Bishop and rook pawns where the bishop does not cover the promotion
+/// square: the defending king in the corner cannot be driven out
+fn is_wrong_rook_pawn(board: &Board, strong: Color, weak: Color, strong_material: &Material, weak_material: &Material) -> bool {
+    let pawn_count = strong_material[Piece::Pawn as usize];
+    if pawn_count == 0 || !is_exactly(weak_material, &[]) {
+        return false;
+    }
+    let mut pieces = vec![Piece::Bishop];
+    pieces.extend(std::iter::repeat_n(Piece::Pawn, pawn_count as usize));
+    if !is_exactly(strong_material, &pieces) {
+        return false;
+    }
+
+    let pawns = get_squares(board, Piece::Pawn, strong);
+    let file = file_of(pawns[0]);
+    if (file != 0 && file != 7) || pawns.iter().any(|p| file_of(*p) != file) {
+        return false;
+    }
+
+    let promotion_rank = if strong == Color::White { 7 } else { 0 };
+    let promotion = (8 * promotion_rank + file) as usize;
+    let bishop = get_square(board, Piece::Bishop, strong);
+    let weak_king = get_square(board, Piece::King, weak);
+
+    is_dark(bishop) != is_dark(promotion) && distance(weak_king, promotion) <= 1
+}
+
+/// Bishops of opposite colors with nothing else but pawns draw often,
+/// more so when one side is at most a pawn up
+fn get_opposite_bishops_scale(board: &Board, white: &Material, black: &Material) -> Option<f64> {
+    let has_only_bishop = |material: &Material| {
+        material[Piece::Bishop as usize] == 1
+            && material[Piece::Knight as usize] == 0
+            && material[Piece::Rook as usize] == 0
+            && material[Piece::Queen as usize] == 0
+    };
+    if !has_only_bishop(white) || !has_only_bishop(black) {
+        return None;
+    }
+
+    let white_bishop = get_square(board, Piece::Bishop, Color::White);
+    let black_bishop = get_square(board, Piece::Bishop, Color::Black);
+    if is_dark(white_bishop) == is_dark(black_bishop) {
+        return None;
+    }
+
+    let pawn_difference = (white[Piece::Pawn as usize] as i32 - black[Piece::Pawn as usize] as i32).abs();
+    Some(if pawn_difference <= 1 { 0.25 } else { 0.5 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::move_api::MoveAPI;
+
+    /// Endgames with the range `evaluate` must give
+    /// for them when the normal evaluation says 3
+    const ENDGAME_SUITE: [(&str, f64, f64); 16] = [
+        // KPK: the side to move decides the opposition
+        ("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1", 0.0, 0.0),
+        ("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1", 50.0, 1000.0),
+        ("8/8/8/4p3/4k3/8/4K3/8 b - - 0 1", 0.0, 0.0),
+        ("8/8/8/4p3/4k3/8/4K3/8 w - - 0 1", -1000.0, -50.0),
+        // a king on a key square wins whoever moves, stalemate does not
+        ("4k3/8/4K3/8/4P3/8/8/8 w - - 0 1", 50.0, 1000.0),
+        ("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1", 0.0, 0.0),
+        ("k7/8/1K6/P7/8/8/8/8 w - - 0 1", 0.0, 0.0),
+        ("7k/8/8/8/P7/8/8/K7 w - - 0 1", 50.0, 1000.0),
+        ("8/8/8/3k4/8/8/8/3QK3 w - - 0 1", 50.0, 1000.0),
+        ("3rk3/8/8/8/3K4/8/8/8 w - - 0 1", -1000.0, -50.0),
+        ("8/8/8/3k4/8/8/8/2BNK3 w - - 0 1", 50.0, 1000.0),
+        // the rook side's king stands in front of the pawn
+        ("8/8/8/8/3k4/8/4p3/4K2R w - - 0 1", 4.0, 5.0),
+        // a dark bishop cannot drive the king from a8
+        ("k7/8/8/P7/8/8/8/K1B5 w - - 0 1", 0.0, 0.0),
+        ("k7/8/8/P7/8/8/8/KB6 w - - 0 1", 3.0, 3.0),
+        // opposite colored bishops scale the evaluation down
+        ("4k3/5b2/8/3P4/4P3/8/8/2B1K3 w - - 0 1", 1.5, 1.5),
+        ("4k3/5b2/8/3P4/8/8/8/2B1K3 w - - 0 1", 0.75, 0.75),
+    ];
+
+    #[test]
+    fn endgame_suite() {
+        for (fen, low, high) in ENDGAME_SUITE {
+            let score = evaluate(MoveAPI::new(fen).get_board_ref(), 3.0);
+            assert!((low..=high).contains(&score), "{} scored {}", fen, score);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::fs;
+use std::sync::Arc;
+
+use crate::chess::{board::Board, color::Color, move_api::MoveAPI, move_generator::MoveGenerator, nnue::Network, piece::Piece};
+
+/// Evaluation in pawns, positive is good for White
+pub type Score = f64;
+
+/// Name of the evaluator used when a request does not pick one
+pub const DEFAULT_EVALUATOR: &str = "material-mobility";
+
+/// Where the `tuned` evaluator reads its parameters from
+pub const TUNED_PARAMS_FILE: &str = "eval_params.txt";
+
+/// Where the `nnue` evaluator reads its network from. On the command
+/// line `nnue:<path>` reads the network at `path` instead, the server
+/// loads the one its `--nnue` option names
+pub const NNUE_FILE: &str = "nets/default.nnue";
+
+pub const PARAM_COUNT: usize = 7;
+
+/// Parameter names as written in parameter files
+pub const PARAM_NAMES: [&str; PARAM_COUNT] = [
+    "pawn",
+    "knight",
+    "bishop",
+    "rook",
+    "queen",
+    "mobility",
+    "queen_mobility_ratio",
+];
+
+/// Weights of the material + mobility evaluation.
+/// The evaluation is linear in these, see `MaterialMobilityEvaluator::get_features`
+#[derive(Copy, Clone, Debug)]
+pub struct EvalParams {
+    pub weights: [f64; PARAM_COUNT],
+}
+
+impl EvalParams {
+    /// The hand-picked values the engine started with
+    pub fn new() -> Self {
+        EvalParams {
+            weights: [1f64, 3f64, 3.1f64, 5f64, 9f64, 0.001, 0.001],
+        }
+    }
+
+    /// Read a parameter file of `name value` lines,
+    /// missing parameters keep their default value
+    pub fn load(path: &str) -> Result<EvalParams, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        let mut params = EvalParams::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let split: Vec<&str> = line.split_whitespace().collect();
+            if split.len() != 2 {
+                return Err(format!("Malformed line: {}", line));
+            }
+            let index = PARAM_NAMES
+                .iter()
+                .position(|name| *name == split[0])
+                .ok_or(format!("Unknown parameter: {}", split[0]))?;
+            params.weights[index] = split[1]
+                .parse::<f64>()
+                .map_err(|_| format!("Bad value for {}: {}", split[0], split[1]))?;
+        }
+        Ok(params)
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut out = String::from("# material + mobility evaluation parameters\n");
+        for (name, weight) in PARAM_NAMES.iter().zip(self.weights) {
+            out += &format!("{} {}\n", name, weight);
+        }
+        fs::write(path, out)
+    }
+
+    pub fn evaluate_features(&self, features: &[f64; PARAM_COUNT]) -> Score {
+        self.weights.iter().zip(features).map(|(w, f)| w * f).sum()
+    }
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        EvalParams::new()
+    }
+}
+
+/// Static evaluation used at the leaves of the search
+pub trait Evaluator: Send + Sync {
+    fn evaluate(&self, board: &Board) -> Score;
+
+    /// Called before searching from `move_api`, so evaluators
+    /// can attach incremental state to it
+    fn prepare(&self, _move_api: &mut MoveAPI) {}
+
+    /// Evaluate the current position of a prepared `move_api`
+    fn evaluate_position(&self, move_api: &MoveAPI) -> Score {
+        self.evaluate(move_api.get_board_ref())
+    }
+}
+
+impl Evaluator for Box<dyn Evaluator> {
+    fn evaluate(&self, board: &Board) -> Score {
+        self.as_ref().evaluate(board)
+    }
+
+    fn prepare(&self, move_api: &mut MoveAPI) {
+        self.as_ref().prepare(move_api)
+    }
+
+    fn evaluate_position(&self, move_api: &MoveAPI) -> Score {
+        self.as_ref().evaluate_position(move_api)
+    }
+}
+
+/// Look up an evaluator by the name used on the command line. Evaluators
+/// that read a file fail with the reason the file could not be loaded
+pub fn from_name(name: &str) -> Result<Box<dyn Evaluator>, String> {
+    let path = match name {
+        "nnue" => NNUE_FILE,
+        _ => match name.strip_prefix("nnue:") {
+            Some(path) => path,
+            None => return from_fixed_name(name, None),
+        },
+    };
+    let network = Network::load(path)?;
+    from_fixed_name("nnue", Some(&Arc::new(network)))
+}
+
+/// Look up an evaluator by a name that reads no path of its own, as the
+/// server takes them from requests. `nnue` plays `network`, loaded
+/// beforehand by the caller
+pub fn from_fixed_name(name: &str, network: Option<&Arc<Network>>) -> Result<Box<dyn Evaluator>, String> {
+    match name {
+        "material-mobility" => Ok(Box::new(MaterialMobilityEvaluator::new())),
+        "material" => Ok(Box::new(MaterialEvaluator)),
+        "tuned" => EvalParams::load(TUNED_PARAMS_FILE)
+            .map(|params| Box::new(MaterialMobilityEvaluator::with_params(params)) as Box<dyn Evaluator>),
+        "nnue" => match network {
+            Some(network) => Ok(Box::new(NnueEvaluator::new(network.clone()))),
+            None => Err(String::from("No network is loaded for nnue")),
+        },
+        _ => Err(format!("Unknown evaluator {}", name)),
+    }
+}
+
+/// Counts material only
+pub struct MaterialEvaluator;
+
+impl Evaluator for MaterialEvaluator {
+    fn evaluate(&self, board: &Board) -> Score {
+        board.get_material_score(Color::White) - board.get_material_score(Color::Black)
+    }
+}
+
+/// Material plus a small bonus for pseudo-legal mobility
+pub struct MaterialMobilityEvaluator {
+    move_generator: MoveGenerator,
+    params: EvalParams,
+}
+
+impl MaterialMobilityEvaluator {
+    pub fn new() -> Self {
+        MaterialMobilityEvaluator::with_params(EvalParams::new())
+    }
+
+    pub fn with_params(params: EvalParams) -> Self {
+        MaterialMobilityEvaluator {
+            move_generator: MoveGenerator::new(),
+            params,
+        }
+    }
+
+    /// White-minus-Black terms that the parameters are multiplied with
+    pub fn get_features(&self, board: &Board) -> [f64; PARAM_COUNT] {
+        let mut out = [0f64; PARAM_COUNT];
+
+        let pieces = [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen];
+        for (i, piece) in pieces.into_iter().enumerate() {
+            // pieces in a Crazyhouse pocket count as material
+            let w_count = (board.get_color_piece_board(piece, Color::White).count_ones()
+                + board.get_pocket(piece, Color::White) as u32) as f64;
+            let b_count = (board.get_color_piece_board(piece, Color::Black).count_ones()
+                + board.get_pocket(piece, Color::Black) as u32) as f64;
+            out[i] = w_count - b_count;
+        }
+
+        let w_dev = self.move_generator.get_moves_color(board, Color::White).len() as f64;
+        let b_dev = self.move_generator.get_moves_color(board, Color::Black).len() as f64;
+        out[5] = w_dev - b_dev;
+
+        let w_queen = self.move_generator.get_piece_moves(board, Piece::Queen, Color::White).len() as f64;
+        let b_queen = self.move_generator.get_piece_moves(board, Piece::Queen, Color::Black).len() as f64;
+
+        // a stuck or missing white queen would divide by zero
+        if w_queen > 0f64 {
+            out[6] = b_queen / w_queen;
+        }
+        out
+    }
+}
+
+impl Default for MaterialMobilityEvaluator {
+    fn default() -> Self {
+        MaterialMobilityEvaluator::new()
+    }
+}
+
+impl Evaluator for MaterialMobilityEvaluator {
+    fn evaluate(&self, board: &Board) -> Score {
+        self.params.evaluate_features(&self.get_features(board))
+    }
+}
+
+/// Evaluation by a network, see `chess::nnue`.
+/// Searches keep the accumulators up to date move by move
+pub struct NnueEvaluator {
+    network: Arc<Network>,
+}
+
+impl NnueEvaluator {
+    pub fn new(network: Arc<Network>) -> Self {
+        NnueEvaluator { network }
+    }
+
+    fn to_score(centipawns: i32, turn: Color) -> Score {
+        let score = centipawns as f64 / 100f64;
+        match turn {
+            Color::White => score,
+            Color::Black => -score,
+        }
+    }
+}
+
+impl Evaluator for NnueEvaluator {
+    fn evaluate(&self, board: &Board) -> Score {
+        let accumulator = self.network.refresh(board);
+        NnueEvaluator::to_score(self.network.evaluate(&accumulator, board.turn), board.turn)
+    }
+
+    fn prepare(&self, move_api: &mut MoveAPI) {
+        let is_prepared = match move_api.get_accumulators() {
+            Some(accumulators) => Arc::ptr_eq(accumulators.get_network(), &self.network),
+            None => false,
+        };
+        if !is_prepared {
+            move_api.enable_nnue(self.network.clone());
+        }
+    }
+
+    fn evaluate_position(&self, move_api: &MoveAPI) -> Score {
+        let turn = move_api.get_turn_color();
+        match move_api.get_accumulators() {
+            Some(accumulators) if Arc::ptr_eq(accumulators.get_network(), &self.network) => {
+                NnueEvaluator::to_score(self.network.evaluate(accumulators.get_current(), turn), turn)
+            }
+            _ => self.evaluate(move_api.get_board_ref()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only the command line names may read a network from a path
+    #[test]
+    fn fixed_names_read_no_paths() {
+        assert!(from_fixed_name("nnue:nets/tiny.nnue", None).is_err());
+        assert!(from_fixed_name("nnue", None).is_err());
+        assert!(from_name("nnue:nets/tiny.nnue").is_ok());
+
+        let network = Arc::new(Network::load("nets/tiny.nnue").unwrap());
+        assert!(from_fixed_name("nnue", Some(&network)).is_ok());
+    }
+}
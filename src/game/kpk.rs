@@ -0,0 +1,194 @@
+use std::sync::OnceLock;
+
+/// King and pawn against king, solved by retrograde analysis.
+///
+/// Positions are stored from the side with the pawn, which plays White and
+/// pushes up the board. Squares count from a1 = 0 to h8 = 63 and the pawn
+/// is mirrored onto files a to d, which leaves 24 pawn squares:
+/// 2 sides to move * 24 * 64 * 64 = 196608 positions, one bit each
+const POSITIONS: usize = 2 * 24 * 64 * 64;
+
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+fn file_of(square: usize) -> i32 {
+    (square & 7) as i32
+}
+
+fn rank_of(square: usize) -> i32 {
+    (square >> 3) as i32
+}
+
+fn distance(a: usize, b: usize) -> i32 {
+    (file_of(a) - file_of(b)).abs().max((rank_of(a) - rank_of(b)).abs())
+}
+
+/// Squares a king on `square` reaches
+fn king_moves(square: usize) -> impl Iterator<Item = usize> {
+    (0..64).filter(move |s| distance(square, *s) == 1)
+}
+
+fn is_pawn_attack(pawn: usize, square: usize) -> bool {
+    rank_of(square) == rank_of(pawn) + 1 && (file_of(square) - file_of(pawn)).abs() == 1
+}
+
+/// `is_white` to move, pawn on files a to d and ranks 2 to 7
+fn get_index(is_white: bool, white_king: usize, black_king: usize, pawn: usize) -> usize {
+    white_king
+        | (black_king << 6)
+        | ((!is_white as usize) << 12)
+        | ((file_of(pawn) as usize) << 13)
+        | (((6 - rank_of(pawn)) as usize) << 15)
+}
+
+/// Rules that decide a position without looking at its moves
+fn get_initial_result(is_white: bool, white_king: usize, black_king: usize, pawn: usize) -> u8 {
+    if distance(white_king, black_king) <= 1 || white_king == pawn || black_king == pawn {
+        return INVALID;
+    }
+    if is_white && is_pawn_attack(pawn, black_king) {
+        return INVALID;
+    }
+
+    if is_white {
+        // a safe promotion wins
+        let promotion = pawn + 8;
+        if rank_of(pawn) == 6
+            && white_king != promotion
+            && (distance(black_king, promotion) > 1 || distance(white_king, promotion) == 1)
+        {
+            return WIN;
+        }
+        return UNKNOWN;
+    }
+
+    // stalemate, or the pawn is lost
+    let is_stalemate = king_moves(black_king).all(|s| distance(white_king, s) <= 1 || is_pawn_attack(pawn, s));
+    let is_pawn_lost = distance(black_king, pawn) == 1 && distance(white_king, pawn) > 1;
+    if is_stalemate || is_pawn_lost {
+        return DRAW;
+    }
+    UNKNOWN
+}
+
+/// Result of the side to move from the results of its moves:
+/// White needs one winning move, Black one drawing move
+fn classify(db: &[u8], is_white: bool, white_king: usize, black_king: usize, pawn: usize) -> u8 {
+    let mut results = 0;
+
+    if is_white {
+        for s in king_moves(white_king) {
+            results |= db[get_index(false, s, black_king, pawn)];
+        }
+        let push = pawn + 8;
+        if rank_of(pawn) < 6 && push != white_king && push != black_king {
+            results |= db[get_index(false, white_king, black_king, push)];
+
+            let double_push = push + 8;
+            if rank_of(pawn) == 1 && double_push != white_king && double_push != black_king {
+                results |= db[get_index(false, white_king, black_king, double_push)];
+            }
+        }
+
+        if results & WIN != 0 {
+            WIN
+        } else if results & UNKNOWN != 0 {
+            UNKNOWN
+        } else {
+            DRAW
+        }
+    } else {
+        for s in king_moves(black_king) {
+            results |= db[get_index(true, white_king, s, pawn)];
+        }
+
+        if results & DRAW != 0 {
+            DRAW
+        } else if results & UNKNOWN != 0 {
+            UNKNOWN
+        } else {
+            WIN
+        }
+    }
+}
+
+/// Pieces of an index, the inverse of `get_index`
+fn get_pieces(index: usize) -> (bool, usize, usize, usize) {
+    let white_king = index & 63;
+    let black_king = (index >> 6) & 63;
+    let is_white = (index >> 12) & 1 == 0;
+    let file = (index >> 13) & 3;
+    let rank = 6 - (index >> 15);
+    (is_white, white_king, black_king, 8 * rank + file)
+}
+
+/// Won positions of KPK, one bit each
+pub struct KpkBitbase {
+    wins: Vec<u64>,
+}
+
+impl KpkBitbase {
+    /// Mark what the rules decide, then keep classifying
+    /// the unknown positions until nothing changes
+    pub fn generate() -> KpkBitbase {
+        let mut db: Vec<u8> = (0..POSITIONS)
+            .map(|index| {
+                let (is_white, white_king, black_king, pawn) = get_pieces(index);
+                get_initial_result(is_white, white_king, black_king, pawn)
+            })
+            .collect();
+
+        let mut is_changed = true;
+        while is_changed {
+            is_changed = false;
+            for index in 0..POSITIONS {
+                if db[index] != UNKNOWN {
+                    continue;
+                }
+                let (is_white, white_king, black_king, pawn) = get_pieces(index);
+                let result = classify(&db, is_white, white_king, black_king, pawn);
+                if result != UNKNOWN {
+                    db[index] = result;
+                    is_changed = true;
+                }
+            }
+        }
+
+        let mut wins = vec![0u64; POSITIONS / 64];
+        for (index, result) in db.into_iter().enumerate() {
+            if result == WIN {
+                wins[index / 64] |= 1 << (index % 64);
+            }
+        }
+        KpkBitbase { wins }
+    }
+
+    pub fn get_win_count(&self) -> u32 {
+        self.wins.iter().map(|w| w.count_ones()).sum()
+    }
+
+    /// Whether the side with the pawn wins. Squares count from a1 = 0,
+    /// `is_strong_to_move` tells whether the side with the pawn moves next
+    /// and `is_strong_white` which color it plays
+    pub fn is_win(&self, is_strong_white: bool, is_strong_to_move: bool, strong_king: usize, weak_king: usize, pawn: usize) -> bool {
+        // the side with the pawn always pushes up the board
+        let flip_ranks = if is_strong_white { 0 } else { 56 };
+        let (mut strong_king, mut weak_king, mut pawn) = (strong_king ^ flip_ranks, weak_king ^ flip_ranks, pawn ^ flip_ranks);
+        if file_of(pawn) > 3 {
+            strong_king ^= 7;
+            weak_king ^= 7;
+            pawn ^= 7;
+        }
+
+        let index = get_index(is_strong_to_move, strong_king, weak_king, pawn);
+        self.wins[index / 64] & (1 << (index % 64)) != 0
+    }
+}
+
+/// The bitbase, generated on first use
+pub fn bitbase() -> &'static KpkBitbase {
+    static BITBASE: OnceLock<KpkBitbase> = OnceLock::new();
+    BITBASE.get_or_init(KpkBitbase::generate)
+}
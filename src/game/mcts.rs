@@ -0,0 +1,411 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::{rngs::ThreadRng, Rng};
+
+use crate::api::chess_api::{ChessGame, GameAPI};
+use crate::chess::{board::Board, cmove::Move, color::Color, move_api::MoveAPI, variant::Outcome};
+
+use super::agent::Agent;
+use super::evaluator::{Evaluator, MaterialMobilityEvaluator};
+
+/// Exploration constant of UCT, sqrt(2) balances a result range of 0 to 1
+pub const DEFAULT_EXPLORATION: f64 = std::f64::consts::SQRT_2;
+
+/// Random playouts stop here and the evaluator judges the position
+const MAX_PLAYOUT_PLIES: usize = 200;
+
+/// Plies of an evaluation-guided playout before the evaluator judges the position
+const GUIDED_PLAYOUT_PLIES: usize = 4;
+
+/// Chance an evaluation-guided playout plays a random move instead of the best looking one
+const GUIDED_RANDOM_CHANCE: f64 = 0.1;
+
+/// Evaluation in pawns that gives 10 to 1 odds for the side ahead
+const PAWNS_PER_ODDS: f64 = 4.0;
+
+/// How a playout from a new node is run
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Playout {
+    /// Random legal moves until the game ends or MAX_PLAYOUT_PLIES
+    Random,
+    /// A few moves the evaluator likes best, then the evaluation of the position
+    Guided,
+}
+
+impl Playout {
+    pub fn from_name(name: &str) -> Option<Playout> {
+        match name {
+            "random" => Some(Playout::Random),
+            "guided" => Some(Playout::Guided),
+            _ => None,
+        }
+    }
+}
+
+struct Node {
+    /// Move into the node, None at the root
+    chess_move: Option<Move>,
+    /// Side that played `chess_move`
+    color: Color,
+    children: Vec<usize>,
+    /// Legal moves without a child yet, None until the node is first selected
+    untried: Option<Vec<Move>>,
+    /// Result for White when the game is over in the node
+    result: Option<f64>,
+    visits: u32,
+    /// Sum of the results for `color`
+    total: f64,
+}
+
+impl Node {
+    fn new(chess_move: Option<Move>, color: Color) -> Self {
+        Node {
+            chess_move,
+            color,
+            children: Vec::new(),
+            untried: None,
+            result: None,
+            visits: 0,
+            total: 0.0,
+        }
+    }
+}
+
+/// Monte Carlo tree search with UCT selection. Every iteration walks the
+/// tree to a node with untried moves, adds one child, plays out from it
+/// and adds the result to every node on the way. The tree below the
+/// position reached after the opponent's reply is kept for the next move
+pub struct MctsAgent<E: Evaluator = MaterialMobilityEvaluator> {
+    random_generator: ThreadRng,
+    evaluator: Arc<E>,
+    iterations: usize,
+    move_time: Option<Duration>,
+    playout: Playout,
+    exploration: f64,
+    /// Nodes of the tree, the root is the first
+    tree: Vec<Node>,
+    root_board: Option<Board>,
+    last_score: Option<f64>,
+}
+
+impl<E: Evaluator> MctsAgent<E> {
+    pub fn with_evaluator(iterations: usize, evaluator: E) -> Self {
+        MctsAgent {
+            random_generator: rand::thread_rng(),
+            evaluator: Arc::new(evaluator),
+            iterations: iterations.max(1),
+            move_time: None,
+            playout: Playout::Random,
+            exploration: DEFAULT_EXPLORATION,
+            tree: Vec::new(),
+            root_board: None,
+            last_score: None,
+        }
+    }
+
+    /// Stop searching once `time` is spent,
+    /// the iteration count the agent was built with stays the limit
+    pub fn with_move_time(mut self, time: Duration) -> Self {
+        self.move_time = Some(time);
+        self
+    }
+
+    pub fn with_playout(mut self, playout: Playout) -> Self {
+        self.playout = playout;
+        self
+    }
+
+    pub fn with_exploration(mut self, exploration: f64) -> Self {
+        self.exploration = exploration;
+        self
+    }
+
+    /// Score of the last move in pawns for the side that played it,
+    /// from the win rate of the move
+    pub fn get_last_score(&self) -> Option<f64> {
+        self.last_score
+    }
+
+    /// Chance White wins given an evaluation in pawns for White
+    fn get_win_chance(score: f64) -> f64 {
+        1.0 / (1.0 + 10f64.powf(-score / PAWNS_PER_ODDS))
+    }
+
+    fn get_result(outcome: Outcome) -> f64 {
+        match outcome {
+            Outcome::Win(Color::White) => 1.0,
+            Outcome::Win(Color::Black) => 0.0,
+            _ => 0.5,
+        }
+    }
+
+    /// Result for White when the game is over in the current position
+    fn get_game_result(move_api: &mut MoveAPI, moves: &[Move]) -> Option<f64> {
+        if moves.is_empty() {
+            return Some(Self::get_result(move_api.get_outcome()));
+        }
+        if move_api.get_board_ref().halfmove_clock >= 100 {
+            return Some(0.5);
+        }
+        None
+    }
+
+    /// Keep the subtree of `board` when it is one or two plies below the
+    /// old root, the rest of the tree is dropped
+    fn reuse_tree(&mut self, move_api: &mut MoveAPI, board: &Board) {
+        let Some(root_board) = self.root_board.filter(|_| !self.tree.is_empty()) else {
+            self.tree.clear();
+            return;
+        };
+
+        let mut new_root = (root_board == *board).then_some(0);
+        move_api.set_board(root_board);
+        'search: for &child in &self.tree[0].children {
+            if new_root.is_some() {
+                break;
+            }
+            move_api.exec_move(self.tree[child].chess_move.unwrap());
+            if move_api.get_board_ref() == board {
+                new_root = Some(child);
+                break;
+            }
+            for &grandchild in &self.tree[child].children {
+                move_api.exec_move(self.tree[grandchild].chess_move.unwrap());
+                if move_api.get_board_ref() == board {
+                    new_root = Some(grandchild);
+                    break 'search;
+                }
+                move_api.undo_move();
+            }
+            move_api.undo_move();
+        }
+
+        let old_tree = std::mem::take(&mut self.tree);
+        if let Some(root) = new_root {
+            Self::copy_subtree(&old_tree, root, &mut self.tree);
+            self.tree[0].chess_move = None;
+        }
+    }
+
+    fn copy_subtree(old_tree: &[Node], node: usize, tree: &mut Vec<Node>) -> usize {
+        let old = &old_tree[node];
+        let index = tree.len();
+        tree.push(Node {
+            chess_move: old.chess_move,
+            color: old.color,
+            children: Vec::new(),
+            untried: old.untried.clone(),
+            result: old.result,
+            visits: old.visits,
+            total: old.total,
+        });
+        let children = old.children.iter().map(|&child| Self::copy_subtree(old_tree, child, tree)).collect();
+        tree[index].children = children;
+        index
+    }
+
+    /// The child of `node` with the highest upper confidence bound
+    fn select_child(&self, node: usize) -> usize {
+        let log_visits = (self.tree[node].visits.max(1) as f64).ln();
+        let bound = |child: usize| {
+            let child = &self.tree[child];
+            let visits = child.visits.max(1) as f64;
+            child.total / visits + self.exploration * (log_visits / visits).sqrt()
+        };
+        *self.tree[node]
+            .children
+            .iter()
+            .max_by(|a, b| bound(**a).total_cmp(&bound(**b)))
+            .unwrap()
+    }
+
+    /// Result for White of a playout from the current position
+    fn run_playout(&mut self, move_api: &mut MoveAPI) -> f64 {
+        let plies = match self.playout {
+            Playout::Random => MAX_PLAYOUT_PLIES,
+            Playout::Guided => GUIDED_PLAYOUT_PLIES,
+        };
+        for _ in 0..plies {
+            let moves = move_api.get_legal_moves();
+            if let Some(result) = Self::get_game_result(move_api, &moves) {
+                return result;
+            }
+            let is_random = self.playout == Playout::Random || self.random_generator.gen_bool(GUIDED_RANDOM_CHANCE);
+            let chosen = if is_random {
+                moves[self.random_generator.gen_range(0..moves.len())]
+            } else {
+                self.get_best_looking_move(move_api, &moves)
+            };
+            move_api.exec_move(chosen);
+        }
+        Self::get_win_chance(self.evaluator.evaluate(move_api.get_board_ref()))
+    }
+
+    /// The move with the best evaluation one ply ahead
+    fn get_best_looking_move(&self, move_api: &mut MoveAPI, moves: &[Move]) -> Move {
+        let color = if move_api.get_turn_color() == Color::White { 1.0 } else { -1.0 };
+        let mut best = (moves[0], f64::MIN);
+        for &m in moves {
+            move_api.exec_move(m);
+            let score = color * self.evaluator.evaluate(move_api.get_board_ref());
+            move_api.undo_move();
+            if score > best.1 {
+                best = (m, score);
+            }
+        }
+        best.0
+    }
+
+    /// Select, expand, play out and backpropagate once
+    fn run_iteration(&mut self, move_api: &mut MoveAPI, root_board: Board) {
+        move_api.set_board(root_board);
+        let mut path = vec![0];
+        let mut node = 0;
+        let result = loop {
+            if self.tree[node].untried.is_none() {
+                let moves = move_api.get_legal_moves();
+                self.tree[node].result = Self::get_game_result(move_api, &moves);
+                self.tree[node].untried = Some(moves);
+            }
+            if let Some(result) = self.tree[node].result {
+                break result;
+            }
+
+            let untried = self.tree[node].untried.as_mut().unwrap();
+            if !untried.is_empty() {
+                let chosen = untried.swap_remove(self.random_generator.gen_range(0..untried.len()));
+                let child = self.tree.len();
+                self.tree.push(Node::new(Some(chosen), move_api.get_turn_color()));
+                self.tree[node].children.push(child);
+                move_api.exec_move(chosen);
+                path.push(child);
+                break self.run_playout(move_api);
+            }
+
+            node = self.select_child(node);
+            move_api.exec_move(self.tree[node].chess_move.unwrap());
+            path.push(node);
+        };
+
+        for node in path {
+            let node = &mut self.tree[node];
+            node.visits += 1;
+            node.total += if node.color == Color::White { result } else { 1.0 - result };
+        }
+    }
+
+    /// Search from `board` within the budget and play the most visited move
+    pub fn choose_move(&mut self, board: Board) -> Option<Move> {
+        let mut move_api = MoveAPI::from_board(board);
+        self.reuse_tree(&mut move_api, &board);
+        let reused = self.tree.first().map_or(0, |root| root.visits);
+        if self.tree.is_empty() {
+            self.tree.push(Node::new(None, board.turn.get_opposite()));
+        }
+        self.root_board = Some(board);
+
+        let now = Instant::now();
+        let mut iterations = 0;
+        while iterations < self.iterations && self.move_time.is_none_or(|time| now.elapsed() < time) {
+            self.run_iteration(&mut move_api, board);
+            iterations += 1;
+        }
+
+        let best = *self.tree[0].children.iter().max_by_key(|&&child| self.tree[child].visits)?;
+        let best = &self.tree[best];
+        let win_rate = (best.total / best.visits.max(1) as f64).clamp(0.001, 0.999);
+        self.last_score = Some(PAWNS_PER_ODDS * (win_rate / (1.0 - win_rate)).log10());
+        eprintln!(
+            "MCTS: {} iterations ({} reused), {} visits, win rate {:.3}",
+            iterations,
+            reused,
+            best.visits,
+            win_rate
+        );
+        best.chess_move
+    }
+}
+
+impl<E: Evaluator> Agent for MctsAgent<E> {
+    fn getAgentMove(&mut self, board_logic: &mut ChessGame) -> Option<Move> {
+        self.last_score = None;
+        if board_logic.get_legal_moves().is_empty() {
+            return None;
+        }
+        self.choose_move(board_logic.get_board())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::fen;
+
+    fn new_agent(iterations: usize) -> MctsAgent {
+        MctsAgent::with_evaluator(iterations, MaterialMobilityEvaluator::new()).with_playout(Playout::Guided)
+    }
+
+    /// Every node below the root was visited once when it was added
+    /// and once for each visit to one of its children. A node where the
+    /// game is over has no children and keeps its result
+    fn check_visits(agent: &MctsAgent, node: usize) {
+        let children: u32 = agent.tree[node].children.iter().map(|&child| agent.tree[child].visits).sum();
+        let added = if node == 0 { 0 } else { 1 };
+        if agent.tree[node].result.is_none() {
+            assert_eq!(agent.tree[node].visits, children + added);
+        } else {
+            assert!(agent.tree[node].children.is_empty());
+        }
+        for &child in &agent.tree[node].children {
+            check_visits(agent, child);
+        }
+    }
+
+    #[test]
+    fn visits_add_up() {
+        let mut agent = new_agent(500);
+        agent.choose_move(Board::new(fen::STARTING_BOARD)).unwrap();
+        assert_eq!(agent.tree[0].visits, 500);
+        check_visits(&agent, 0);
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let board = Board::new("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1");
+        let chosen = new_agent(3000).choose_move(board).unwrap();
+        let mut move_api = MoveAPI::from_board(board);
+        move_api.exec_move(chosen);
+        assert!(move_api.get_outcome() == Outcome::Win(Color::White));
+    }
+
+    /// After a move and its reply the subtree below them is searched on
+    #[test]
+    fn reused_tree_keeps_visits() {
+        let board = Board::new(fen::STARTING_BOARD);
+        let mut agent = new_agent(2000);
+        let chosen = agent.choose_move(board).unwrap();
+
+        let child = *agent.tree[0].children.iter().find(|&&child| agent.tree[child].chess_move == Some(chosen)).unwrap();
+        let reply = *agent.tree[child].children.iter().max_by_key(|&&grandchild| agent.tree[grandchild].visits).unwrap();
+        let visits = agent.tree[reply].visits;
+        assert!(visits > 0);
+
+        let mut move_api = MoveAPI::from_board(board);
+        move_api.exec_move(chosen);
+        move_api.exec_move(agent.tree[reply].chess_move.unwrap());
+        agent.iterations = 100;
+        agent.choose_move(*move_api.get_board_ref()).unwrap();
+        assert_eq!(agent.tree[0].visits, visits + 100);
+    }
+
+    #[test]
+    fn move_time_gives_legal_move() {
+        let mut move_api = MoveAPI::new(fen::STARTING_BOARD);
+        let mut agent = new_agent(usize::MAX).with_move_time(Duration::from_millis(50));
+        let start = Instant::now();
+        let chosen = agent.choose_move(*move_api.get_board_ref()).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(move_api.get_legal_moves().contains(&chosen));
+    }
+}
@@ -0,0 +1,274 @@
+use std::fs;
+
+use crate::chess::{bitboard_util::mask, board::Board, color::Color, move_generator::MoveGenerator, position::Position};
+
+use super::evaluator::{EvalParams, Evaluator, MaterialMobilityEvaluator, Score};
+
+/// Where the server reads personalities from
+pub const DEFAULT_PERSONALITY_FILE: &str = "personalities.json";
+
+/// Indices of the material and mobility weights in `EvalParams`
+const MATERIAL_PARAMS: std::ops::Range<usize> = 0..5;
+const MOBILITY_PARAMS: std::ops::Range<usize> = 5..7;
+
+/// A playing style, read from a personality file like
+///
+/// ```json
+/// { "personalities": [
+///     { "name": "attacker", "description": "Goes for the king",
+///       "material": 0.9, "mobility": 3, "kingAttack": 0.05, "pawnStructure": 0,
+///       "contempt": 0.3, "risk": 0.1, "openings": ["e2e4 e7e5 g1f3 b8c6 f1c4"] } ] }
+/// ```
+///
+/// Missing fields keep the values of `Personality::new`
+#[derive(Clone, Debug)]
+pub struct Personality {
+    pub name: String,
+    pub description: String,
+    /// Multiplies the material weights of the evaluation
+    pub material: f64,
+    /// Multiplies the mobility weights of the evaluation
+    pub mobility: f64,
+    /// Pawns for every pseudo-legal move into the squares around the enemy king
+    pub king_attack: f64,
+    /// Pawns lost for every doubled or isolated pawn
+    pub pawn_structure: f64,
+    /// Pawns a draw costs the agent, negative values make it seek draws
+    pub contempt: f64,
+    /// Root moves this many pawns below the best are played too, at random
+    pub risk: f64,
+    /// Lines of coordinate moves from the starting position, played while the game follows one
+    pub openings: Vec<String>,
+}
+
+impl Personality {
+    /// The plain style of the default evaluator
+    pub fn new(name: &str) -> Self {
+        Personality {
+            name: name.to_string(),
+            description: String::new(),
+            material: 1f64,
+            mobility: 1f64,
+            king_attack: 0f64,
+            pawn_structure: 0f64,
+            contempt: 0f64,
+            risk: 0f64,
+            openings: Vec::new(),
+        }
+    }
+
+    fn from_json(value: &json::JsonValue) -> Result<Personality, String> {
+        let name = value["name"].as_str().ok_or("A personality has no name")?;
+        let mut personality = Personality::new(name);
+        if let Some(description) = value["description"].as_str() {
+            personality.description = description.to_string();
+        }
+
+        let weights = [
+            ("material", &mut personality.material),
+            ("mobility", &mut personality.mobility),
+            ("kingAttack", &mut personality.king_attack),
+            ("pawnStructure", &mut personality.pawn_structure),
+            ("contempt", &mut personality.contempt),
+            ("risk", &mut personality.risk),
+        ];
+        for (key, weight) in weights {
+            if value[key].is_null() {
+                continue;
+            }
+            *weight = value[key].as_f64().ok_or(format!("Bad value for {} in {}", key, name))?;
+        }
+        if personality.risk < 0f64 {
+            return Err(format!("Negative risk in {}", name));
+        }
+
+        for line in value["openings"].members() {
+            let line = line.as_str().ok_or(format!("Bad opening in {}", name))?;
+            personality.openings.push(line.to_string());
+        }
+        Ok(personality)
+    }
+
+    /// Every personality of a personality file
+    pub fn load_all(path: &str) -> Result<Vec<Personality>, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        let value = json::parse(&text).map_err(|e| format!("Could not parse {}: {}", path, e))?;
+        if !value["personalities"].is_array() {
+            return Err(format!("{} has no personalities list", path));
+        }
+
+        let mut out: Vec<Personality> = Vec::new();
+        for member in value["personalities"].members() {
+            let personality = Personality::from_json(member)?;
+            if out.iter().any(|p| p.name == personality.name) {
+                return Err(format!("Personality {} is defined twice", personality.name));
+            }
+            out.push(personality);
+        }
+        Ok(out)
+    }
+
+    /// The personality called `name` in the file at `path`
+    pub fn load(path: &str, name: &str) -> Result<Personality, String> {
+        Personality::load_all(path)?
+            .into_iter()
+            .find(|p| p.name == name)
+            .ok_or(format!("Unknown personality {}", name))
+    }
+
+    pub fn get_evaluator(&self) -> PersonalityEvaluator {
+        PersonalityEvaluator::new(self)
+    }
+}
+
+/// The material + mobility evaluation with the weights of a personality,
+/// plus its king attack and pawn structure terms
+pub struct PersonalityEvaluator {
+    base: MaterialMobilityEvaluator,
+    move_generator: MoveGenerator,
+    king_attack: f64,
+    pawn_structure: f64,
+}
+
+impl PersonalityEvaluator {
+    pub fn new(personality: &Personality) -> Self {
+        let mut params = EvalParams::new();
+        for i in MATERIAL_PARAMS {
+            params.weights[i] *= personality.material;
+        }
+        for i in MOBILITY_PARAMS {
+            params.weights[i] *= personality.mobility;
+        }
+        PersonalityEvaluator {
+            base: MaterialMobilityEvaluator::with_params(params),
+            move_generator: MoveGenerator::new(),
+            king_attack: personality.king_attack,
+            pawn_structure: personality.pawn_structure,
+        }
+    }
+
+    /// Pseudo-legal moves of `color` into the king's square
+    /// and the squares next to it of the other side
+    fn get_king_attacks(&self, board: &Board, color: Color) -> usize {
+        let kings = board.kings & board.get_pieces(color.get_opposite());
+        if kings == 0 {
+            return 0;
+        }
+        let king = Position::index(kings.leading_zeros() as usize);
+        self.move_generator
+            .get_moves_color(board, color)
+            .iter()
+            .filter(|m| {
+                !m.is_drop
+                    && (m.end.get_row() - king.get_row()).abs() <= 1
+                    && (m.end.get_col() - king.get_col()).abs() <= 1
+            })
+            .count()
+    }
+
+    /// Doubled pawns past the first on a file, plus pawns without a pawn of their color on a neighbouring file
+    fn get_pawn_weaknesses(board: &Board, color: Color) -> u32 {
+        let pawns = board.pawns & board.get_pieces(color);
+        let mut files = [0u32; 8];
+        for i in 0..64 {
+            if pawns & mask(i) != 0 {
+                files[i % 8] += 1;
+            }
+        }
+
+        let mut out = 0;
+        for file in 0..8 {
+            out += files[file].saturating_sub(1);
+            let left = if file > 0 { files[file - 1] } else { 0 };
+            let right = if file < 7 { files[file + 1] } else { 0 };
+            if left + right == 0 {
+                out += files[file];
+            }
+        }
+        out
+    }
+}
+
+impl Evaluator for PersonalityEvaluator {
+    fn evaluate(&self, board: &Board) -> Score {
+        let mut score = self.base.evaluate(board);
+        if self.king_attack != 0f64 {
+            let attacks = self.get_king_attacks(board, Color::White) as f64 - self.get_king_attacks(board, Color::Black) as f64;
+            score += self.king_attack * attacks;
+        }
+        if self.pawn_structure != 0f64 {
+            let weaknesses = Self::get_pawn_weaknesses(board, Color::White) as f64
+                - Self::get_pawn_weaknesses(board, Color::Black) as f64;
+            score -= self.pawn_structure * weaknesses;
+        }
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_path() -> String {
+        format!("{}/{}", env!("CARGO_MANIFEST_DIR"), DEFAULT_PERSONALITY_FILE)
+    }
+
+    #[test]
+    fn reads_personality_file() {
+        let personalities = Personality::load_all(&get_path()).unwrap();
+        assert_eq!(personalities.len(), 5);
+
+        let attacker = Personality::load(&get_path(), "attacker").unwrap();
+        assert_eq!((attacker.material, attacker.mobility, attacker.king_attack), (0.9, 3.0, 0.05));
+        assert_eq!((attacker.contempt, attacker.risk), (0.5, 0.1));
+        assert_eq!(attacker.openings.len(), 3);
+
+        // fields left out keep the plain style
+        let balanced = Personality::load(&get_path(), "balanced").unwrap();
+        assert_eq!((balanced.material, balanced.king_attack, balanced.risk), (1.0, 0.0, 0.0));
+        assert!(balanced.openings.is_empty());
+
+        assert!(Personality::load(&get_path(), "nobody").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_personalities() {
+        let parse = |text: &str| Personality::from_json(&json::parse(text).unwrap());
+        assert!(parse(r#"{ "name": "a", "risk": 0.2 }"#).is_ok());
+        assert!(parse(r#"{ "description": "no name" }"#).is_err());
+        assert!(parse(r#"{ "name": "a", "material": "lots" }"#).is_err());
+        assert!(parse(r#"{ "name": "a", "risk": -1 }"#).is_err());
+        assert!(parse(r#"{ "name": "a", "openings": [1] }"#).is_err());
+
+        let path = std::env::temp_dir().join(format!("personalities-{}.json", std::process::id()));
+        fs::write(&path, r#"{ "personalities": [ { "name": "a" }, { "name": "a" } ] }"#).unwrap();
+        let loaded = Personality::load_all(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap_err(), "Personality a is defined twice");
+    }
+
+    /// The attacker likes White's pieces around Black's king more than
+    /// the same style without the king attack term, and the other way round
+    #[test]
+    fn king_attack_favours_the_attacker() {
+        let attacker = Personality::load(&get_path(), "attacker").unwrap();
+        let neutral = Personality { king_attack: 0f64, ..attacker.clone() };
+        let (attacker, neutral) = (attacker.get_evaluator(), neutral.get_evaluator());
+
+        let white_attacks = Board::new("6k1/5ppp/4N3/6Q1/8/8/5PPP/6K1 w - - 0 1");
+        assert!(attacker.evaluate(&white_attacks) > neutral.evaluate(&white_attacks));
+        let black_attacks = Board::new("6k1/5ppp/8/8/6q1/4n3/5PPP/6K1 b - - 0 1");
+        assert!(attacker.evaluate(&black_attacks) < neutral.evaluate(&black_attacks));
+    }
+
+    #[test]
+    fn pawn_structure_counts_weak_pawns() {
+        let board = Board::new("4k3/pppp4/8/8/8/P1P5/P1P5/4K3 w - - 0 1");
+        assert_eq!(PersonalityEvaluator::get_pawn_weaknesses(&board, Color::White), 6);
+        assert_eq!(PersonalityEvaluator::get_pawn_weaknesses(&board, Color::Black), 0);
+
+        let positional = Personality::load(&get_path(), "positional").unwrap();
+        let careless = Personality { pawn_structure: 0f64, ..positional.clone() };
+        assert!(positional.get_evaluator().evaluate(&board) < careless.get_evaluator().evaluate(&board));
+    }
+}
@@ -0,0 +1,173 @@
+use rand::Rng;
+
+use crate::chess::cmove::Move;
+
+use super::agent::AnalysisLine;
+
+/// Full strength, the search result is played as it is
+pub const MAX_SKILL_LEVEL: u32 = 20;
+
+/// Elo a target Elo of level 0 and of level 20 stand for. A rough guess
+/// that no games were played to calibrate, the levels in between are
+/// spread evenly over the range
+const MIN_ELO: u32 = 600;
+const MAX_ELO: u32 = 2000;
+
+/// Root moves a weakened agent chooses from
+const SKILL_LINES: usize = 4;
+
+/// Chance of a random legal move at level 0, falling to none at level 20
+const MAX_BLUNDER_CHANCE: f64 = 0.25;
+
+/// How well the agent plays, from 0 to MAX_SKILL_LEVEL. Lower levels search
+/// shallower, pick among the best few root moves with more noise,
+/// and now and then play any legal move
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Skill {
+    level: u32,
+}
+
+impl Skill {
+    pub fn new(level: u32) -> Self {
+        Skill {
+            level: level.min(MAX_SKILL_LEVEL),
+        }
+    }
+
+    /// The level a target Elo maps to. The mapping is not calibrated,
+    /// it only orders the levels from MIN_ELO to MAX_ELO
+    pub fn from_elo(elo: u32) -> Self {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO);
+        let level = ((elo - MIN_ELO) as f64 * MAX_SKILL_LEVEL as f64 / (MAX_ELO - MIN_ELO) as f64).round();
+        Skill::new(level as u32)
+    }
+
+    pub fn get_level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn is_full_strength(&self) -> bool {
+        self.level == MAX_SKILL_LEVEL
+    }
+
+    /// Deepest search of the level, None at full strength
+    pub fn get_max_depth(&self) -> Option<usize> {
+        match self.is_full_strength() {
+            true => None,
+            false => Some(1 + self.level as usize / 5),
+        }
+    }
+
+    /// Root moves searched for `choose_move`
+    pub fn get_lines(&self) -> usize {
+        if self.is_full_strength() {
+            1
+        } else {
+            SKILL_LINES
+        }
+    }
+
+    pub fn get_blunder_chance(&self) -> f64 {
+        MAX_BLUNDER_CHANCE * (MAX_SKILL_LEVEL - self.level) as f64 / MAX_SKILL_LEVEL as f64
+    }
+
+    /// Pick from `lines`, best first with scores in pawns, or from
+    /// `legal_moves` for a blunder. Every line gets a random bonus that
+    /// grows with its distance from the best score and with the weakness
+    /// of the level, the way Stockfish weakens its skill levels.
+    /// At full strength the best line is played as it is
+    pub fn choose_move<R: Rng>(&self, lines: &[AnalysisLine], legal_moves: &[Move], rng: &mut R) -> Option<Move> {
+        if self.is_full_strength() {
+            return lines.first().map(|line| line.chess_move);
+        }
+        if !legal_moves.is_empty() && rng.gen_bool(self.get_blunder_chance()) {
+            return Some(legal_moves[rng.gen_range(0..legal_moves.len())]);
+        }
+
+        let top = lines.first()?.score * 100.0;
+        let worst = lines.last()?.score * 100.0;
+        let delta = (top - worst).min(100.0);
+        let weakness = 120.0 - 2.0 * self.level as f64;
+
+        let mut best = None;
+        let mut best_value = f64::MIN;
+        for line in lines {
+            let score = line.score * 100.0;
+            let push = (weakness * (top - score) + delta * rng.gen_range(0.0..weakness)) / 128.0;
+            if score + push > best_value {
+                best_value = score + push;
+                best = Some(line.chess_move);
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::chess::{fen, move_api::MoveAPI};
+
+    /// The first four legal moves of the starting position
+    /// as lines half a pawn apart
+    fn get_lines() -> (Vec<AnalysisLine>, Vec<Move>) {
+        let legal_moves = MoveAPI::new(fen::STARTING_BOARD).get_legal_moves();
+        let lines = legal_moves
+            .iter()
+            .take(SKILL_LINES)
+            .enumerate()
+            .map(|(i, m)| AnalysisLine { chess_move: *m, score: 0.3 - 0.5 * i as f64, pv: vec![*m] })
+            .collect();
+        (lines, legal_moves)
+    }
+
+    #[test]
+    fn full_strength_plays_best_line() {
+        let (lines, legal_moves) = get_lines();
+        let skill = Skill::new(MAX_SKILL_LEVEL);
+        assert_eq!(skill.get_blunder_chance(), 0.0);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..200 {
+            assert!(skill.choose_move(&lines, &legal_moves, &mut rng) == Some(lines[0].chess_move));
+        }
+    }
+
+    /// Without a blunder the move is one of the lines, and a blunder is still legal
+    #[test]
+    fn lower_levels_choose_analysed_moves() {
+        let (lines, legal_moves) = get_lines();
+        let mut rng = StdRng::seed_from_u64(2);
+        for level in 0..MAX_SKILL_LEVEL {
+            let skill = Skill::new(level);
+            for _ in 0..50 {
+                let chosen = skill.choose_move(&lines, &[], &mut rng).unwrap();
+                assert!(lines.iter().any(|line| line.chess_move == chosen));
+                let chosen = skill.choose_move(&lines, &legal_moves, &mut rng).unwrap();
+                assert!(legal_moves.contains(&chosen));
+            }
+        }
+        assert!(Skill::new(0).choose_move(&[], &[], &mut rng).is_none());
+    }
+
+    #[test]
+    fn weaker_levels_blunder_more() {
+        assert_eq!(Skill::new(0).get_blunder_chance(), MAX_BLUNDER_CHANCE);
+        for level in 1..=MAX_SKILL_LEVEL {
+            assert!(Skill::new(level).get_blunder_chance() < Skill::new(level - 1).get_blunder_chance());
+        }
+    }
+
+    #[test]
+    fn elo_maps_to_levels_in_order() {
+        assert_eq!(Skill::from_elo(0).get_level(), 0);
+        assert_eq!(Skill::from_elo(MIN_ELO).get_level(), 0);
+        assert_eq!(Skill::from_elo(MAX_ELO).get_level(), MAX_SKILL_LEVEL);
+        assert_eq!(Skill::from_elo(5000).get_level(), MAX_SKILL_LEVEL);
+        for elo in 0..3000 {
+            assert!(Skill::from_elo(elo + 1).get_level() >= Skill::from_elo(elo).get_level());
+        }
+        assert_eq!(Skill::new(50).get_level(), MAX_SKILL_LEVEL);
+    }
+}
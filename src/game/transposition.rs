@@ -0,0 +1,130 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::chess::{cmove::Move, piece::PIECE_TYPES, position::Position};
+
+/// Default number of entries (must be a power of two)
+pub const DEFAULT_ENTRIES: usize = 1 << 20;
+
+/// How the stored score relates to the true value of the position
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+/// A decoded transposition table entry
+#[derive(Copy, Clone)]
+pub struct TableEntry {
+    pub depth: usize,
+    pub score: f64,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+}
+
+/// Lock-free transposition table shared between search threads.
+///
+/// Each slot stores `key ^ data` next to `data`. A torn write from two
+/// threads racing on the same slot makes the check in `probe` fail,
+/// so readers never see a mix of two entries.
+pub struct TranspositionTable {
+    keys: Vec<AtomicU64>,
+    data: Vec<AtomicU64>,
+    index_mask: usize,
+}
+
+/// Data layout (low to high bits):
+/// score as f32 (32) | depth (8) | bound (2) | has move (1)
+/// | start (6) | end (6) | piece (3) | promotion (3) | is drop (1)
+fn encode(entry: &TableEntry) -> u64 {
+    let mut out = (entry.score as f32).to_bits() as u64;
+    out |= (entry.depth.min(u8::MAX as usize) as u64) << 32;
+    out |= (entry.bound as u64) << 40;
+
+    if let Some(m) = entry.best_move {
+        let promotion = match m.promotion {
+            Some(piece) => piece as u64 + 1,
+            None => 0,
+        };
+        out |= 1 << 42;
+        out |= (m.start.to_index() as u64) << 43;
+        out |= (m.end.to_index() as u64) << 49;
+        out |= (m.piece as u64) << 55;
+        out |= promotion << 58;
+        out |= (m.is_drop as u64) << 61;
+    }
+    out
+}
+
+fn decode(data: u64) -> TableEntry {
+    let score = f32::from_bits(data as u32) as f64;
+    let depth = ((data >> 32) & 0xFF) as usize;
+    let bound = match (data >> 40) & 0b11 {
+        0 => Bound::Exact,
+        1 => Bound::Lower,
+        _ => Bound::Upper,
+    };
+
+    let best_move = if (data >> 42) & 1 == 1 {
+        let start = Position::index(((data >> 43) & 0x3F) as usize);
+        let end = Position::index(((data >> 49) & 0x3F) as usize);
+        let piece = PIECE_TYPES[((data >> 55) & 0b111) as usize];
+        let promotion = match (data >> 58) & 0b111 {
+            0 => None,
+            p => Some(PIECE_TYPES[p as usize - 1]),
+        };
+        if (data >> 61) & 1 == 1 {
+            Some(Move::drop(piece, end))
+        } else {
+            Some(Move::new(start, end, piece, promotion))
+        }
+    } else {
+        None
+    };
+
+    TableEntry {
+        depth,
+        score,
+        bound,
+        best_move,
+    }
+}
+
+impl TranspositionTable {
+    /// Create a table with `entries` slots, rounded up to a power of two
+    pub fn new(entries: usize) -> Self {
+        let size = entries.max(1).next_power_of_two();
+        TranspositionTable {
+            keys: (0..size).map(|_| AtomicU64::new(0)).collect(),
+            data: (0..size).map(|_| AtomicU64::new(0)).collect(),
+            index_mask: size - 1,
+        }
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TableEntry> {
+        let index = key as usize & self.index_mask;
+        let stored_key = self.keys[index].load(Ordering::Relaxed);
+        let data = self.data[index].load(Ordering::Relaxed);
+
+        if stored_key ^ data != key || data == 0 {
+            return None;
+        }
+        Some(decode(data))
+    }
+
+    /// Depth-preferred replacement: a shallower result for
+    /// the same position never overwrites a deeper one
+    pub fn store(&self, key: u64, entry: TableEntry) {
+        let index = key as usize & self.index_mask;
+
+        if let Some(existing) = self.probe(key) {
+            if existing.depth > entry.depth {
+                return;
+            }
+        }
+
+        let data = encode(&entry);
+        self.keys[index].store(key ^ data, Ordering::Relaxed);
+        self.data[index].store(data, Ordering::Relaxed);
+    }
+}
@@ -1,9 +1,22 @@
 mod chess;
 mod api;
 mod game;
+mod tuner;
+mod book_builder;
+mod match_runner;
+mod tablebase_builder;
+mod syzygy_builder;
+mod xboard;
+mod tournament;
+mod datagen;
+mod bench;
 
 use chess::fen;
 use chess::move_api::MoveAPI;
+use chess::polyglot;
+use chess::syzygy::{self, Tablebases};
+use game::book::OpeningBook;
+use chess::dtm::{self, DtmTablebases};
 use std::time::Instant;
 use api::chess_api::GameAPI;
 
@@ -54,11 +67,108 @@ fn _all_perft() {
     );
     _perft_up_to("n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 6", 5);
 }
+/// Book moves for `fen` with their weights
+fn print_book_moves(path: &str, fen: &str) -> bool {
+    let opening_book = match OpeningBook::load(path) {
+        Ok(opening_book) => opening_book,
+        Err(msg) => {
+            println!("{}", msg);
+            return false;
+        }
+    };
+
+    let mut move_api = MoveAPI::new(fen);
+    let board = *move_api.get_board_ref();
+    println!("{} entries, key {:016x}", opening_book.len(), polyglot::hash(&board));
+    for (m, weight) in opening_book.get_moves(&mut move_api) {
+        println!("{} {}", polyglot::move_to_string(polyglot::encode_move(&board, &m)), weight);
+    }
+    true
+}
+
+/// Tablebase results of a position and of its moves
+fn print_tablebase_probe(dir: &str, fen: &str) -> bool {
+    let tablebases = Tablebases::open(dir);
+    println!("{} tables in {}, up to {} pieces", tablebases.len(), dir, tablebases.get_max_pieces());
+
+    let mut move_api = MoveAPI::new(fen);
+    let (wdl, dtz) = match (tablebases.probe_wdl(&mut move_api), tablebases.probe_dtz(&mut move_api)) {
+        (Some(wdl), Some(dtz)) => (wdl, dtz),
+        _ => {
+            println!("FAIL no table for {}", fen);
+            return false;
+        }
+    };
+    println!("{}: {}, DTZ {}", fen, wdl.get_name(), dtz);
+
+    for (m, dtz) in tablebases.probe_root(&mut move_api).unwrap_or_default() {
+        println!("{} {}", m.to_string(), dtz);
+    }
+    true
+}
+
+/// Results of a position and of its moves in generated tables
+fn print_dtm_probe(dir: &str, fen: &str) -> bool {
+    let tables = DtmTablebases::open(dir);
+    println!("{} tables in {}", tables.len(), dir);
+
+    let mut move_api = MoveAPI::new(fen);
+    let result = match tables.probe(move_api.get_board_ref()) {
+        Some(result) => result,
+        None => {
+            println!("FAIL no table for {}", fen);
+            return false;
+        }
+    };
+    println!("{}: {}", fen, result.get_description());
+
+    for (m, result) in tables.probe_root(&mut move_api).unwrap_or_default() {
+        println!("{} {}", m.to_string(), result.get_description());
+    }
+    true
+}
+
 mod server;
 
 #[rustfmt::skip]
 fn main() {
 
-    server::server();
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("tune") => tuner::run(&args[2..]),
+        Some("build-book") => book_builder::run(&args[2..]),
+        Some("match") => match_runner::run(&args[2..]),
+        Some("tournament") => tournament::run(&args[2..]),
+        Some("datagen") => datagen::run(&args[2..]),
+        Some("bench") => bench::run(&args[2..]),
+        Some("book-probe") => {
+            let Some(path) = args.get(2) else {
+                println!("Usage: book-probe <book file> [fen]");
+                std::process::exit(1);
+            };
+            let fen = args.get(3).map(String::as_str).unwrap_or(fen::STARTING_BOARD);
+            if !print_book_moves(path, fen) {
+                std::process::exit(1);
+            }
+        }
+        Some("syzygy-probe") => {
+            let dir = args.get(2).map(String::as_str).unwrap_or(syzygy::DEFAULT_SYZYGY_DIR);
+            let fen = args.get(3).map(String::as_str).unwrap_or("8/8/8/8/8/2k5/8/KQ6 w - - 0 1");
+            if !print_tablebase_probe(dir, fen) {
+                std::process::exit(1);
+            }
+        }
+        Some("xboard") => xboard::run(),
+        Some("build-tablebase") => tablebase_builder::run(&args[2..]),
+        Some("build-syzygy") => syzygy_builder::run(&args[2..]),
+        Some("dtm-probe") => {
+            let dir = args.get(2).map(String::as_str).unwrap_or(dtm::DEFAULT_DTM_DIR);
+            let fen = args.get(3).map(String::as_str).unwrap_or("8/8/8/8/8/2k5/8/KQ6 w - - 0 1");
+            if !print_dtm_probe(dir, fen) {
+                std::process::exit(1);
+            }
+        }
+        _ => { let _ = server::server(&args[1..]); }
+    }
 
 }
@@ -0,0 +1,1170 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::api::chess_api::ChessGame;
+use crate::chess::board::Board;
+use crate::chess::cmove::Move;
+use crate::chess::color::Color;
+use crate::chess::fen;
+use crate::chess::move_api::MoveAPI;
+use crate::chess::pgn::{self, PgnGame};
+use crate::chess::variant::{Outcome, Variant};
+use crate::game::agent::{Agent, RandomAgent, TomatoAgent};
+use crate::game::evaluator::{self, Evaluator};
+use crate::game::mcts::{self, MctsAgent, Playout};
+use crate::game::personality::{self, Personality};
+use crate::game::skill::{Skill, MAX_SKILL_LEVEL};
+
+/// Openings played when no file is given, each one twice with colors swapped
+pub const DEFAULT_OPENINGS: [&str; 8] = [
+    fen::STARTING_BOARD,
+    // 1.e4 e5
+    "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2",
+    // 1.d4 d5
+    "rnbqkbnr/ppp1pppp/8/3p4/3P4/8/PPP1PPPP/RNBQKBNR w KQkq d6 0 2",
+    // Sicilian
+    "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+    // French
+    "rnbqkbnr/pppp1ppp/4p3/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+    // Caro-Kann
+    "rnbqkbnr/pp1ppppp/2p5/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+    // English
+    "rnbqkbnr/pppp1ppp/8/4p3/2P5/8/PP1PPPPP/RNBQKBNR w KQkq e6 0 2",
+    // 1.d4 Nf6
+    "rnbqkb1r/pppppppp/5n2/8/3P4/8/PPP1PPPP/RNBQKBNR w KQkq - 1 2",
+];
+
+/// Depth a player searches to when its spec gives no limit
+const DEFAULT_DEPTH: usize = 4;
+
+/// Iterations of an MCTS player whose spec gives no limit
+const DEFAULT_ITERATIONS: usize = 10000;
+
+/// Depth limit of an agent a move time or node limit stops
+const MAX_DEPTH: usize = 32;
+
+/// Centipawns a mate score counts as
+pub const MATE_SCORE: f64 = 100000.0;
+
+/// Time a player gets on top of its move time before it loses on time
+const MOVE_TIME_GRACE: Duration = Duration::from_secs(5);
+
+/// Time a UCI engine gets for a depth limited search,
+/// and for answering uci and isready
+const UCI_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Standard deviations on either side of the mean
+/// a 95% confidence interval spans
+const Z_95: f64 = 1.96;
+
+/// Something that plays moves in a match: one of the agents,
+/// or an engine running in a child process
+pub trait Player {
+    fn get_name(&self) -> String;
+
+    /// Forget everything about the last game
+    fn new_game(&mut self) -> Result<(), String>;
+
+    /// The move to play on `board`, reached by the coordinate `moves` from
+    /// `start_fen`, and its score in centipawns for the side to move if the
+    /// player gave one
+    fn choose_move(&mut self, start_fen: &str, moves: &[String], board: &Board) -> Result<(Move, Option<f64>), String>;
+
+    /// Longest a move may take before the player loses on time, None for no limit
+    fn get_time_limit(&self) -> Option<Duration>;
+}
+
+/// When a player stops searching, whichever limit comes first.
+/// None of them set means DEFAULT_DEPTH
+#[derive(Clone, Copy, Default)]
+pub struct SearchLimits {
+    pub depth: Option<usize>,
+    pub move_time: Option<Duration>,
+    pub nodes: Option<usize>,
+}
+
+/// How to build a player, parsed from a command line spec:
+///
+/// - `tomato:depth=3,evaluator=material,threads=1,movetime=100,nodes=20000`,
+///   also with `skill=0..20` or `elo=...` (an uncalibrated guess) for a weakened agent and
+///   `personality=name` for a style from the personality file
+/// - `mcts:iterations=5000,playout=guided,exploration=1.4,evaluator=material`,
+///   also with `movetime`
+/// - `random`
+/// - `uci:/path/to/engine,depth=6`, also with `movetime` and `nodes`
+///
+/// Every spec also takes `name=...`
+#[derive(Clone)]
+pub enum PlayerSpec {
+    Tomato {
+        name: Option<String>,
+        evaluator: String,
+        threads: usize,
+        limits: SearchLimits,
+        skill: Option<Skill>,
+        personality: Option<String>,
+    },
+    Mcts {
+        name: Option<String>,
+        evaluator: String,
+        iterations: Option<usize>,
+        move_time: Option<Duration>,
+        playout: Playout,
+        exploration: f64,
+    },
+    Random {
+        name: Option<String>,
+    },
+    Uci {
+        name: Option<String>,
+        path: String,
+        limits: SearchLimits,
+    },
+}
+
+impl PlayerSpec {
+    pub fn parse(spec: &str) -> Result<PlayerSpec, String> {
+        let (kind, options) = spec.split_once(':').unwrap_or((spec, ""));
+        let mut fields = options.split(',').filter(|field| !field.is_empty());
+
+        let path = match kind {
+            "uci" => Some(fields.next().ok_or(format!("{} needs the path of an engine", spec))?.to_string()),
+            _ => None,
+        };
+
+        let mut name = None;
+        let mut evaluator = "material-mobility".to_string();
+        let mut threads = 1;
+        let mut limits = SearchLimits::default();
+        let mut skill = None;
+        let mut personality = None;
+        let mut iterations = None;
+        let mut playout = Playout::Random;
+        let mut exploration = mcts::DEFAULT_EXPLORATION;
+        for field in fields {
+            let (key, value) = field.split_once('=').ok_or(format!("{} is not key=value", field))?;
+            let bad_value = || format!("bad value for {}: {}", key, value);
+            match key {
+                "name" => name = Some(value.to_string()),
+                "depth" => limits.depth = Some(value.parse::<usize>().ok().filter(|d| *d > 0).ok_or_else(bad_value)?),
+                "evaluator" => evaluator = value.to_string(),
+                "threads" => threads = value.parse::<usize>().map_err(|_| bad_value())?,
+                "movetime" => limits.move_time = Some(Duration::from_millis(value.parse::<u64>().map_err(|_| bad_value())?)),
+                "nodes" => limits.nodes = Some(value.parse::<usize>().map_err(|_| bad_value())?),
+                "skill" => skill = Some(Skill::new(value.parse::<u32>().ok().filter(|l| *l <= MAX_SKILL_LEVEL).ok_or_else(bad_value)?)),
+                "personality" => personality = Some(value.to_string()),
+                "elo" => skill = Some(Skill::from_elo(value.parse::<u32>().map_err(|_| bad_value())?)),
+                "iterations" => iterations = Some(value.parse::<usize>().ok().filter(|i| *i > 0).ok_or_else(bad_value)?),
+                "playout" => playout = Playout::from_name(value).ok_or_else(bad_value)?,
+                "exploration" => exploration = value.parse::<f64>().map_err(|_| bad_value())?,
+                _ => return Err(format!("unknown option {} in {}", key, spec)),
+            }
+        }
+
+        match (kind, path) {
+            ("tomato", _) => Ok(PlayerSpec::Tomato { name, evaluator, threads, limits, skill, personality }),
+            ("mcts", _) => Ok(PlayerSpec::Mcts {
+                name,
+                evaluator,
+                iterations,
+                move_time: limits.move_time,
+                playout,
+                exploration,
+            }),
+            ("random", _) => Ok(PlayerSpec::Random { name }),
+            ("uci", Some(path)) => Ok(PlayerSpec::Uci { name, path, limits }),
+            _ => Err(format!("unknown player {}, expected tomato, mcts, random or uci", kind)),
+        }
+    }
+
+    /// Start the player, a UCI engine is spawned and asked whether it is ready
+    pub fn create(&self) -> Result<Box<dyn Player>, String> {
+        match self {
+            PlayerSpec::Tomato { name, evaluator, threads, limits, skill, personality } => {
+                let personality = match personality {
+                    Some(name) => Some(Personality::load(personality::DEFAULT_PERSONALITY_FILE, name)?),
+                    None => None,
+                };
+                if personality.is_none() {
+                    evaluator::from_name(evaluator)?;
+                }
+                let name = name.clone().unwrap_or_else(|| {
+                    let mut name = "tomato".to_string();
+                    if let Some(personality) = &personality {
+                        name += &format!("-{}", personality.name);
+                    } else if evaluator != "material-mobility" {
+                        name += &format!("-{}", evaluator);
+                    }
+                    match (limits.depth, limits.move_time, limits.nodes) {
+                        (None, None, None) => name += &format!("-d{}", DEFAULT_DEPTH),
+                        (depth, move_time, nodes) => {
+                            name += &depth.map(|depth| format!("-d{}", depth)).unwrap_or_default();
+                            name += &move_time.map(|time| format!("-{}ms", time.as_millis())).unwrap_or_default();
+                            name += &nodes.map(|nodes| format!("-n{}", nodes)).unwrap_or_default();
+                        }
+                    }
+                    name += &skill.map(|skill| format!("-s{}", skill.get_level())).unwrap_or_default();
+                    name
+                });
+                Ok(Box::new(AgentPlayer {
+                    name,
+                    evaluator: evaluator.clone(),
+                    threads: *threads,
+                    limits: *limits,
+                    skill: *skill,
+                    personality,
+                    agent: None,
+                }))
+            }
+            PlayerSpec::Mcts { name, evaluator, iterations, move_time, playout, exploration } => {
+                evaluator::from_name(evaluator)?;
+                // a move time alone lets the iterations grow as far as it allows
+                let iterations = match (iterations, move_time) {
+                    (Some(iterations), _) => *iterations,
+                    (None, None) => DEFAULT_ITERATIONS,
+                    (None, Some(_)) => usize::MAX,
+                };
+                let name = name.clone().unwrap_or_else(|| {
+                    let mut name = "mcts".to_string();
+                    if *playout == Playout::Guided {
+                        name += "-guided";
+                    }
+                    if iterations != usize::MAX {
+                        name += &format!("-i{}", iterations);
+                    }
+                    name += &move_time.map(|time| format!("-{}ms", time.as_millis())).unwrap_or_default();
+                    name
+                });
+                Ok(Box::new(MctsPlayer {
+                    name,
+                    evaluator: evaluator.clone(),
+                    iterations,
+                    move_time: *move_time,
+                    playout: *playout,
+                    exploration: *exploration,
+                    agent: None,
+                }))
+            }
+            PlayerSpec::Random { name } => Ok(Box::new(RandomPlayer {
+                name: name.clone().unwrap_or("random".to_string()),
+                agent: RandomAgent::new(),
+            })),
+            PlayerSpec::Uci { name, path, limits } => Ok(Box::new(UciPlayer::start(path, name.clone(), *limits)?)),
+        }
+    }
+}
+
+/// A TomatoAgent, built again for every game so no table carries over
+struct AgentPlayer {
+    name: String,
+    evaluator: String,
+    threads: usize,
+    limits: SearchLimits,
+    skill: Option<Skill>,
+    personality: Option<Personality>,
+    agent: Option<TomatoAgent<Box<dyn Evaluator>>>,
+}
+
+impl Player for AgentPlayer {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn new_game(&mut self) -> Result<(), String> {
+        let evaluator = match &self.personality {
+            Some(personality) => Box::new(personality.get_evaluator()),
+            None => evaluator::from_name(&self.evaluator)?,
+        };
+        // a move time or node limit lets the depth grow as far as it allows
+        let depth = match self.limits {
+            SearchLimits { depth: Some(depth), .. } => depth,
+            SearchLimits { move_time: None, nodes: None, .. } => DEFAULT_DEPTH,
+            _ => MAX_DEPTH,
+        };
+        let mut agent = TomatoAgent::with_evaluator(depth, evaluator).with_threads(self.threads);
+        if let Some(time) = self.limits.move_time {
+            agent = agent.with_move_time(time);
+        }
+        if let Some(nodes) = self.limits.nodes {
+            agent = agent.with_node_limit(nodes);
+        }
+        if let Some(skill) = self.skill {
+            agent = agent.with_skill(skill);
+        }
+        if let Some(personality) = &self.personality {
+            agent = agent.with_personality(personality);
+        }
+        self.agent = Some(agent);
+        Ok(())
+    }
+
+    fn choose_move(&mut self, _start_fen: &str, _moves: &[String], board: &Board) -> Result<(Move, Option<f64>), String> {
+        if self.agent.is_none() {
+            self.new_game()?;
+        }
+        let agent = self.agent.as_mut().unwrap();
+        let mut game = ChessGame::new(MoveAPI::from_board(*board));
+        let m = agent.getAgentMove(&mut game).ok_or("no move found")?;
+        let score = agent.get_last_score().map(|score| (score * 100.0).clamp(-MATE_SCORE, MATE_SCORE));
+        Ok((m, score))
+    }
+
+    fn get_time_limit(&self) -> Option<Duration> {
+        self.limits.move_time.map(|time| time + MOVE_TIME_GRACE)
+    }
+}
+
+/// An MctsAgent, kept through a game so its tree carries over between moves
+struct MctsPlayer {
+    name: String,
+    evaluator: String,
+    iterations: usize,
+    move_time: Option<Duration>,
+    playout: Playout,
+    exploration: f64,
+    agent: Option<MctsAgent<Box<dyn Evaluator>>>,
+}
+
+impl Player for MctsPlayer {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn new_game(&mut self) -> Result<(), String> {
+        let evaluator = evaluator::from_name(&self.evaluator)?;
+        let mut agent = MctsAgent::with_evaluator(self.iterations, evaluator)
+            .with_playout(self.playout)
+            .with_exploration(self.exploration);
+        if let Some(time) = self.move_time {
+            agent = agent.with_move_time(time);
+        }
+        self.agent = Some(agent);
+        Ok(())
+    }
+
+    fn choose_move(&mut self, _start_fen: &str, _moves: &[String], board: &Board) -> Result<(Move, Option<f64>), String> {
+        if self.agent.is_none() {
+            self.new_game()?;
+        }
+        let agent = self.agent.as_mut().unwrap();
+        let mut game = ChessGame::new(MoveAPI::from_board(*board));
+        let m = agent.getAgentMove(&mut game).ok_or("no move found")?;
+        let score = agent.get_last_score().map(|score| score * 100.0);
+        Ok((m, score))
+    }
+
+    fn get_time_limit(&self) -> Option<Duration> {
+        self.move_time.map(|time| time + MOVE_TIME_GRACE)
+    }
+}
+
+struct RandomPlayer {
+    name: String,
+    agent: RandomAgent,
+}
+
+impl Player for RandomPlayer {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn new_game(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn choose_move(&mut self, _start_fen: &str, _moves: &[String], board: &Board) -> Result<(Move, Option<f64>), String> {
+        let mut game = ChessGame::new(MoveAPI::from_board(*board));
+        let m = self.agent.getAgentMove(&mut game).ok_or("no move found")?;
+        Ok((m, None))
+    }
+
+    fn get_time_limit(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// An engine speaking UCI on its standard input and output. A thread
+/// reads its output so that a hanging engine can be timed out
+pub struct UciPlayer {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    limits: SearchLimits,
+}
+
+impl UciPlayer {
+    /// Spawn the engine at `path` and wait for uciok and readyok.
+    /// Without a `name` the engine's own `id name` is used
+    pub fn start(path: &str, name: Option<String>, limits: SearchLimits) -> Result<UciPlayer, String> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("could not start {}: {}", path, e))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let file_name = Path::new(path).file_name().map(|f| f.to_string_lossy().to_string());
+        let mut player = UciPlayer {
+            name: name.clone().or(file_name).unwrap_or(path.to_string()),
+            child,
+            stdin,
+            lines,
+            limits,
+        };
+
+        player.send("uci")?;
+        let deadline = Instant::now() + UCI_TIMEOUT;
+        loop {
+            let line = player.read_line(deadline, "uciok")?;
+            if let (Some(id_name), None) = (line.strip_prefix("id name "), &name) {
+                player.name = id_name.trim().to_string();
+            }
+            if line.trim() == "uciok" {
+                break;
+            }
+        }
+        player.wait_ready()?;
+        Ok(player)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", command)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("{} stopped reading: {}", self.name, e))
+    }
+
+    /// The engine's next line of output, `waiting_for` names what is awaited in errors
+    fn read_line(&mut self, deadline: Instant, waiting_for: &str) -> Result<String, String> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.lines.recv_timeout(timeout) {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Timeout) => Err(format!("{} timed out waiting for {}", self.name, waiting_for)),
+            Err(RecvTimeoutError::Disconnected) => Err(format!("{} exited waiting for {}", self.name, waiting_for)),
+        }
+    }
+
+    fn wait_ready(&mut self) -> Result<(), String> {
+        self.send("isready")?;
+        let deadline = Instant::now() + UCI_TIMEOUT;
+        while self.read_line(deadline, "readyok")?.trim() != "readyok" {}
+        Ok(())
+    }
+}
+
+/// Score of an `info` line in centipawns, mates counted as MATE_SCORE
+fn parse_uci_score(line: &str) -> Option<f64> {
+    let mut tokens = line.split_whitespace();
+    tokens.find(|token| *token == "score")?;
+    let kind = tokens.next()?;
+    let value = tokens.next()?.parse::<f64>().ok()?;
+    match kind {
+        "cp" => Some(value),
+        "mate" if value < 0.0 => Some(-MATE_SCORE),
+        "mate" => Some(MATE_SCORE),
+        _ => None,
+    }
+}
+
+impl Player for UciPlayer {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn new_game(&mut self) -> Result<(), String> {
+        self.send("ucinewgame")?;
+        self.wait_ready()
+    }
+
+    fn choose_move(&mut self, start_fen: &str, moves: &[String], board: &Board) -> Result<(Move, Option<f64>), String> {
+        let position = match moves {
+            [] => format!("position fen {}", start_fen),
+            _ => format!("position fen {} moves {}", start_fen, moves.join(" ")),
+        };
+        self.send(&position)?;
+
+        let limits = self.limits;
+        let is_unlimited = limits.depth.is_none() && limits.move_time.is_none() && limits.nodes.is_none();
+        let mut go = "go".to_string();
+        if let Some(depth) = if is_unlimited { Some(DEFAULT_DEPTH) } else { limits.depth } {
+            go += &format!(" depth {}", depth);
+        }
+        if let Some(nodes) = limits.nodes {
+            go += &format!(" nodes {}", nodes);
+        }
+        if let Some(time) = limits.move_time {
+            go += &format!(" movetime {}", time.as_millis());
+        }
+        self.send(&go)?;
+
+        let deadline = Instant::now() + self.get_time_limit().unwrap();
+        let mut score = None;
+        loop {
+            let line = self.read_line(deadline, "bestmove")?;
+            if line.starts_with("info") {
+                score = parse_uci_score(&line).or(score);
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("bestmove") {
+                let text = rest.split_whitespace().next().unwrap_or("");
+                let m = MoveAPI::from_board(*board)
+                    .find_coordinate_move(text)
+                    .ok_or(format!("{} played an illegal move: {}", self.name, text))?;
+                return Ok((m, score));
+            }
+        }
+    }
+
+    /// A depth or node limited search gets UCI_TIMEOUT
+    fn get_time_limit(&self) -> Option<Duration> {
+        Some(self.limits.move_time.map_or(UCI_TIMEOUT, |time| time + MOVE_TIME_GRACE))
+    }
+}
+
+impl Drop for UciPlayer {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        for _ in 0..50 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// When a game ends early. A streak of 0 moves turns its adjudication off
+#[derive(Clone)]
+pub struct GameSettings {
+    /// Plies after which the game is drawn
+    pub max_plies: usize,
+    /// Centipawns both players must agree one side is ahead by
+    pub resign_score: f64,
+    /// Moves of each player the resign score must hold for
+    pub resign_moves: usize,
+    /// Centipawns both players must agree the game is within
+    pub draw_score: f64,
+    /// Moves of each player the draw score must hold for
+    pub draw_moves: usize,
+    /// Move number before which no draw is adjudicated
+    pub draw_after: usize,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            max_plies: 400,
+            resign_score: 1000.0,
+            resign_moves: 3,
+            draw_score: 10.0,
+            draw_moves: 8,
+            draw_after: 40,
+        }
+    }
+}
+
+impl GameSettings {
+    /// Set the setting a command line option like `--max-plies` stands for,
+    /// false if the option is not one of them
+    pub fn set_option(&mut self, option: &str, value: &str) -> Result<bool, String> {
+        let number = || value.parse::<f64>().map_err(|_| format!("{} needs a number", option));
+        let count = || value.parse::<usize>().map_err(|_| format!("{} needs a whole number", option));
+        match option {
+            "--max-plies" => self.max_plies = count()?,
+            "--resign-score" => self.resign_score = number()?,
+            "--resign-moves" => self.resign_moves = count()?,
+            "--draw-score" => self.draw_score = number()?,
+            "--draw-moves" => self.draw_moves = count()?,
+            "--draw-after" => self.draw_after = count()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// A finished game
+pub struct GameRecord {
+    pub white: String,
+    pub black: String,
+    pub start_fen: String,
+    /// Moves in SAN
+    pub moves: Vec<String>,
+    /// Score each move was played with, in centipawns for the side that played it
+    pub scores: Vec<Option<f64>>,
+    pub outcome: Outcome,
+    pub termination: String,
+    /// The player that crashed, timed out or played an illegal move
+    pub failed: Option<Color>,
+}
+
+impl GameRecord {
+    /// 1 for a white win, 0.5 for a draw, 0 for a black win
+    pub fn get_white_score(&self) -> f64 {
+        match self.outcome {
+            Outcome::Win(Color::White) => 1.0,
+            Outcome::Win(Color::Black) => 0.0,
+            _ => 0.5,
+        }
+    }
+
+    pub fn get_result(&self) -> &'static str {
+        match self.outcome {
+            Outcome::Win(Color::White) => "1-0",
+            Outcome::Win(Color::Black) => "0-1",
+            _ => "1/2-1/2",
+        }
+    }
+
+    pub fn to_pgn_game(&self, event: &str, round: usize) -> PgnGame {
+        let mut tags = vec![
+            ("Event".to_string(), event.to_string()),
+            ("Site".to_string(), "?".to_string()),
+            ("Date".to_string(), "????.??.??".to_string()),
+            ("Round".to_string(), round.to_string()),
+            ("White".to_string(), self.white.clone()),
+            ("Black".to_string(), self.black.clone()),
+            ("Result".to_string(), self.get_result().to_string()),
+        ];
+        if self.start_fen != fen::STARTING_BOARD {
+            tags.push(("SetUp".to_string(), "1".to_string()));
+            tags.push(("FEN".to_string(), self.start_fen.clone()));
+        }
+        tags.push(("Termination".to_string(), self.termination.clone()));
+        PgnGame {
+            tags,
+            moves: self.moves.clone(),
+        }
+    }
+}
+
+/// The part of a FEN that decides whether a position repeats
+fn get_position_key(board: &Board) -> String {
+    board.get_fen().split_whitespace().take(4).collect::<Vec<_>>().join(" ")
+}
+
+/// No pawns and no major pieces, and at most one minor piece on the board
+fn is_insufficient_material(board: &Board) -> bool {
+    board.variant == Variant::Standard
+        && board.pawns | board.rooks | board.queens == 0
+        && (board.knights | board.bishops).count_ones() <= 1
+}
+
+/// How many plies in a row a score from White's perspective has stayed
+/// beyond or within a threshold
+#[derive(Default)]
+struct Streaks {
+    white_ahead: usize,
+    black_ahead: usize,
+    level: usize,
+}
+
+impl Streaks {
+    fn add(&mut self, white_score: Option<f64>, settings: &GameSettings) {
+        let Some(score) = white_score else {
+            *self = Streaks::default();
+            return;
+        };
+        self.white_ahead = if score >= settings.resign_score { self.white_ahead + 1 } else { 0 };
+        self.black_ahead = if score <= -settings.resign_score { self.black_ahead + 1 } else { 0 };
+        self.level = if score.abs() <= settings.draw_score { self.level + 1 } else { 0 };
+    }
+}
+
+/// What `f` returns for `player`. A player that panics loses
+/// the game instead of taking the match down with it
+fn catch_crash<T>(player: &mut dyn Player, f: impl FnOnce(&mut dyn Player) -> Result<T, String>) -> Result<T, String> {
+    let name = player.get_name();
+    panic::catch_unwind(AssertUnwindSafe(|| f(player))).unwrap_or_else(|_| Err(format!("{} crashed", name)))
+}
+
+/// Play one game from `start_fen`. A player that fails loses the game
+pub fn play_game<'a>(white: &'a mut dyn Player, black: &'a mut dyn Player, start_fen: &str, settings: &GameSettings) -> GameRecord {
+    let mut record = GameRecord {
+        white: white.get_name(),
+        black: black.get_name(),
+        start_fen: start_fen.to_string(),
+        moves: Vec::new(),
+        scores: Vec::new(),
+        outcome: Outcome::Draw,
+        termination: String::new(),
+        failed: None,
+    };
+
+    for (player, color) in [(&mut *white, Color::White), (&mut *black, Color::Black)] {
+        if let Err(msg) = catch_crash(player, |player| player.new_game()) {
+            record.outcome = Outcome::Win(color.get_opposite());
+            record.termination = msg;
+            record.failed = Some(color);
+            return record;
+        }
+    }
+
+    let mut move_api = MoveAPI::new(start_fen);
+    let mut coordinates = Vec::new();
+    let mut positions = vec![get_position_key(move_api.get_board_ref())];
+    let mut streaks = Streaks::default();
+    let first_move_number = move_api.get_board_ref().fullmove_clock.max(1) as usize;
+
+    loop {
+        let board = *move_api.get_board_ref();
+        let ending = match move_api.get_outcome() {
+            Outcome::Win(color) if move_api.is_in_check() => Some((Outcome::Win(color), "checkmate")),
+            Outcome::Win(color) => Some((Outcome::Win(color), "variant rules")),
+            Outcome::Draw if move_api.get_legal_moves().is_empty() => Some((Outcome::Draw, "stalemate")),
+            Outcome::Draw => Some((Outcome::Draw, "variant rules")),
+            Outcome::Ongoing if board.halfmove_clock >= 100 => Some((Outcome::Draw, "fifty-move rule")),
+            Outcome::Ongoing if positions.iter().filter(|p| **p == positions[positions.len() - 1]).count() >= 3 => {
+                Some((Outcome::Draw, "threefold repetition"))
+            }
+            Outcome::Ongoing if is_insufficient_material(&board) => Some((Outcome::Draw, "insufficient material")),
+            Outcome::Ongoing if coordinates.len() >= settings.max_plies => Some((Outcome::Draw, "move limit")),
+            Outcome::Ongoing if settings.resign_moves > 0 && streaks.white_ahead >= 2 * settings.resign_moves => {
+                Some((Outcome::Win(Color::White), "adjudicated by score"))
+            }
+            Outcome::Ongoing if settings.resign_moves > 0 && streaks.black_ahead >= 2 * settings.resign_moves => {
+                Some((Outcome::Win(Color::Black), "adjudicated by score"))
+            }
+            Outcome::Ongoing
+                if settings.draw_moves > 0
+                    && streaks.level >= 2 * settings.draw_moves
+                    && first_move_number + coordinates.len() / 2 > settings.draw_after =>
+            {
+                Some((Outcome::Draw, "adjudicated by score"))
+            }
+            Outcome::Ongoing => None,
+        };
+        if let Some((outcome, termination)) = ending {
+            record.outcome = outcome;
+            record.termination = termination.to_string();
+            return record;
+        }
+
+        let turn = board.turn;
+        let player = if turn == Color::White { &mut *white } else { &mut *black };
+        let legal_moves = move_api.get_legal_moves();
+        let started = Instant::now();
+        let chosen = catch_crash(player, |player| player.choose_move(start_fen, &coordinates, &board))
+            .and_then(|(m, score)| match legal_moves.contains(&m) {
+                true => Ok((m, score)),
+                false => Err(format!("{} played an illegal move: {}", player.get_name(), m.get_coordinates(turn))),
+            })
+            .and_then(|chosen| match player.get_time_limit() {
+                Some(limit) if started.elapsed() > limit => Err(format!("{} lost on time", player.get_name())),
+                _ => Ok(chosen),
+            });
+        let (m, score) = match chosen {
+            Ok(chosen) => chosen,
+            Err(msg) => {
+                record.outcome = Outcome::Win(turn.get_opposite());
+                record.termination = msg;
+                record.failed = Some(turn);
+                return record;
+            }
+        };
+
+        let white_score = score.map(|score| if turn == Color::White { score } else { -score });
+        streaks.add(white_score, settings);
+        record.moves.push(pgn::get_san(&mut move_api, &m));
+        record.scores.push(score);
+        coordinates.push(m.get_coordinates(turn));
+        move_api.exec_move(m);
+        positions.push(get_position_key(move_api.get_board_ref()));
+    }
+}
+
+/// Elo difference that makes the expected score `score`
+pub fn get_elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/// Expected score of a player `elo` points stronger
+pub fn get_expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Wins, draws and losses of the first player of a match
+#[derive(Clone, Copy, Default)]
+pub struct MatchScore {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl MatchScore {
+    pub fn add(&mut self, score: f64) {
+        if score == 1.0 {
+            self.wins += 1;
+        } else if score == 0.0 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+
+    pub fn get_games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Points per game, an even score before the first game
+    pub fn get_score(&self) -> f64 {
+        if self.get_games() == 0 {
+            return 0.5;
+        }
+        (self.wins as f64 + 0.5 * self.draws as f64) / self.get_games() as f64
+    }
+
+    /// Variance of the points of one game
+    fn get_variance(&self) -> f64 {
+        if self.get_games() == 0 {
+            return 0.0;
+        }
+        let score = self.get_score();
+        let n = self.get_games() as f64;
+        (self.wins as f64 * (1.0 - score).powi(2) + self.draws as f64 * (0.5 - score).powi(2) + self.losses as f64 * score.powi(2)) / n
+    }
+
+    /// Elo difference and the half width of its 95% confidence interval
+    pub fn get_elo(&self) -> (f64, f64) {
+        if self.get_games() == 0 {
+            return (0.0, f64::INFINITY);
+        }
+        let score = self.get_score();
+        let error = Z_95 * (self.get_variance() / self.get_games() as f64).sqrt();
+        let low = get_elo((score - error).max(0.0));
+        let high = get_elo((score + error).min(1.0));
+        // a match won or lost outright has no finite bounds
+        let error = if low.is_finite() && high.is_finite() { (high - low) / 2.0 } else { f64::INFINITY };
+        (get_elo(score), error)
+    }
+
+    /// Log-likelihood ratio of `elo1` against `elo0`, from the normal
+    /// approximation of the score's distribution
+    pub fn get_llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let variance = self.get_variance();
+        if self.get_games() == 0 || variance == 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (get_expected_score(elo0), get_expected_score(elo1));
+        self.get_games() as f64 * (s1 - s0) * (2.0 * self.get_score() - s0 - s1) / (2.0 * variance)
+    }
+}
+
+/// A sequential probability ratio test of H0: elo = elo0 against H1: elo = elo1
+struct Sprt {
+    elo0: f64,
+    elo1: f64,
+    alpha: f64,
+    beta: f64,
+}
+
+impl Sprt {
+    /// LLR below which H0 is accepted and above which H1 is
+    fn get_bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    /// The accepted hypothesis, None while the test goes on
+    fn get_decision(&self, score: &MatchScore) -> Option<&'static str> {
+        let llr = score.get_llr(self.elo0, self.elo1);
+        let (lower, upper) = self.get_bounds();
+        if llr <= lower {
+            Some("H0")
+        } else if llr >= upper {
+            Some("H1")
+        } else {
+            None
+        }
+    }
+}
+
+struct MatchOptions {
+    games: usize,
+    openings: Vec<String>,
+    pgn_path: Option<String>,
+    sprt: Sprt,
+    settings: GameSettings,
+}
+
+/// One FEN per line, blank lines and lines starting with # are skipped
+pub fn load_openings(path: &str) -> Result<Vec<String>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+    let openings: Vec<String> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    if openings.is_empty() {
+        return Err(format!("no openings in {}", path));
+    }
+    Ok(openings)
+}
+
+fn parse_options(args: &[String]) -> Result<MatchOptions, String> {
+    let mut options = MatchOptions {
+        games: 16,
+        openings: DEFAULT_OPENINGS.iter().map(|fen| fen.to_string()).collect(),
+        pgn_path: None,
+        sprt: Sprt {
+            elo0: 0.0,
+            elo1: 5.0,
+            alpha: 0.05,
+            beta: 0.05,
+        },
+        settings: GameSettings::default(),
+    };
+
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        let value = rest.next().ok_or(format!("{} needs a value", arg))?;
+        if options.settings.set_option(arg, value)? {
+            continue;
+        }
+        let number = || value.parse::<f64>().map_err(|_| format!("{} needs a number", arg));
+        match arg.as_str() {
+            "--games" => options.games = value.parse::<usize>().map_err(|_| format!("{} needs a whole number", arg))?,
+            "--openings" => options.openings = load_openings(value)?,
+            "--pgn" => options.pgn_path = Some(value.clone()),
+            "--elo0" => options.sprt.elo0 = number()?,
+            "--elo1" => options.sprt.elo1 = number()?,
+            "--alpha" => options.sprt.alpha = number()?,
+            "--beta" => options.sprt.beta = number()?,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn print_score(a: &str, b: &str, score: &MatchScore, sprt: &Sprt) {
+    let (elo, error) = score.get_elo();
+    let (lower, upper) = sprt.get_bounds();
+    println!(
+        "Score of {} vs {}: {} - {} - {} [{:.3}] {}",
+        a,
+        b,
+        score.wins,
+        score.losses,
+        score.draws,
+        score.get_score(),
+        score.get_games()
+    );
+    println!("Elo difference: {:+.1} +/- {:.1}", elo, error);
+    println!(
+        "SPRT elo0 {} elo1 {}: LLR {:.2} ({:.2}, {:.2})",
+        sprt.elo0,
+        sprt.elo1,
+        score.get_llr(sprt.elo0, sprt.elo1),
+        lower,
+        upper
+    );
+}
+
+/// Usage: match <player A> <player B> [--games N] [--openings file] [--pgn file]
+///        [--elo0 E] [--elo1 E] [--alpha A] [--beta B] [--max-plies N]
+///        [--resign-score CP] [--resign-moves N] [--draw-score CP] [--draw-moves N] [--draw-after N]
+///
+/// Plays `games` games (default 16) between two players, see PlayerSpec for
+/// how to write them. Game i starts from opening i / 2 of the file, or of a
+/// built-in list, and A plays White in the even games. Games end by the
+/// rules, by the move limit, or by adjudication once both players agree
+/// on their scores for long enough. Reports A's Elo difference with a 95%
+/// interval and the SPRT log-likelihood ratio, and stops early once the
+/// SPRT accepts a hypothesis
+pub fn run(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: match <player A> <player B> [--games N] [--openings file] [--pgn file] [--elo0 E] [--elo1 E] [--alpha A] [--beta B]");
+        println!("       [--max-plies N] [--resign-score CP] [--resign-moves N] [--draw-score CP] [--draw-moves N] [--draw-after N]");
+        println!("Players: tomato:depth=3,evaluator=material,threads=1,movetime=100,nodes=20000 | random | uci:/path/to/engine,depth=6");
+        return;
+    }
+
+    let (specs, options) = match (PlayerSpec::parse(&args[0]), PlayerSpec::parse(&args[1]), parse_options(&args[2..])) {
+        (Ok(a), Ok(b), Ok(options)) => ([a, b], options),
+        (Err(msg), _, _) | (_, Err(msg), _) | (_, _, Err(msg)) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    let mut players = Vec::new();
+    for spec in &specs {
+        match spec.create() {
+            Ok(player) => players.push(player),
+            Err(msg) => {
+                println!("{}", msg);
+                return;
+            }
+        }
+    }
+    let names = [players[0].get_name(), players[1].get_name()];
+
+    let mut pgn_file = match &options.pgn_path {
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                println!("Could not create {}: {}", path, e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let event = format!("{} vs {}", names[0], names[1]);
+    let mut score = MatchScore::default();
+    let now = Instant::now();
+    for game in 0..options.games {
+        let opening = &options.openings[(game / 2) % options.openings.len()];
+        let a_is_white = game % 2 == 0;
+        let (white, black) = match players.split_at_mut(1) {
+            (a, b) if a_is_white => (&mut a[0], &mut b[0]),
+            (a, b) => (&mut b[0], &mut a[0]),
+        };
+        let record = play_game(white.as_mut(), black.as_mut(), opening, &options.settings);
+
+        let white_score = record.get_white_score();
+        score.add(if a_is_white { white_score } else { 1.0 - white_score });
+        println!(
+            "Game {}/{}: {} vs {} {} ({})",
+            game + 1,
+            options.games,
+            record.white,
+            record.black,
+            record.get_result(),
+            record.termination
+        );
+
+        if let Some(file) = pgn_file.as_mut() {
+            if let Err(e) = file.write_all(record.to_pgn_game(&event, game + 1).to_pgn().as_bytes()) {
+                println!("Could not write the PGN: {}", e);
+            }
+        }
+
+        // start a player that failed again, so one crash does not lose the rest of the match
+        if let Some(color) = record.failed {
+            let index = if (color == Color::White) == a_is_white { 0 } else { 1 };
+            match specs[index].create() {
+                Ok(player) => players[index] = player,
+                Err(msg) => {
+                    println!("{}", msg);
+                    break;
+                }
+            }
+        }
+
+        if let Some(hypothesis) = options.sprt.get_decision(&score) {
+            println!("SPRT: {} accepted", hypothesis);
+            break;
+        }
+    }
+
+    print_score(&names[0], &names[1], &score, &options.sprt);
+    println!("{:.1}s", now.elapsed().as_secs_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_score(wins: u32, draws: u32, losses: u32) -> MatchScore {
+        MatchScore { wins, draws, losses }
+    }
+
+    #[test]
+    fn elo_matches_expected_score() {
+        assert_eq!(get_elo(0.5), 0.0);
+        for score in [0.1, 0.25, 0.6, 0.9] {
+            assert!((get_expected_score(get_elo(score)) - score).abs() < 1e-9);
+        }
+        // 3 to 1 odds are about 191 Elo
+        assert!((get_elo(0.75) - 190.85).abs() < 0.01);
+    }
+
+    #[test]
+    fn elo_error_of_outright_result_is_infinite() {
+        let (elo, error) = new_score(10, 0, 0).get_elo();
+        assert!(elo.is_infinite() && elo > 0.0);
+        assert!(error.is_infinite());
+
+        let (elo, error) = new_score(6, 2, 2).get_elo();
+        assert!(elo > 0.0 && error.is_finite());
+    }
+
+    #[test]
+    fn no_games_give_even_score() {
+        let score = MatchScore::default();
+        assert_eq!(score.get_score(), 0.5);
+        assert_eq!(score.get_elo(), (0.0, f64::INFINITY));
+        assert_eq!(score.get_llr(0.0, 10.0), 0.0);
+    }
+
+    /// The LLR favours H1 exactly when the score is past
+    /// the midpoint of the two hypotheses' expected scores
+    #[test]
+    fn llr_sign_flips_at_midpoint() {
+        let midpoint = (get_expected_score(0.0) + get_expected_score(10.0)) / 2.0;
+        let above = new_score(51, 0, 49);
+        let below = new_score(50, 0, 50);
+        assert!(above.get_score() > midpoint && below.get_score() < midpoint);
+        assert!(above.get_llr(0.0, 10.0) > 0.0);
+        assert!(below.get_llr(0.0, 10.0) < 0.0);
+    }
+
+    #[test]
+    fn sprt_bounds_are_standard() {
+        let sprt = Sprt {
+            elo0: 0.0,
+            elo1: 5.0,
+            alpha: 0.05,
+            beta: 0.1,
+        };
+        let (lower, upper) = sprt.get_bounds();
+        assert!((lower - (0.1f64 / 0.95).ln()).abs() < 1e-12);
+        assert!((upper - (0.9f64 / 0.05).ln()).abs() < 1e-12);
+        assert!((lower + 2.2513).abs() < 1e-4 && (upper - 2.8904).abs() < 1e-4);
+
+        assert_eq!(sprt.get_decision(&new_score(1200, 0, 900)), Some("H1"));
+        assert_eq!(sprt.get_decision(&new_score(900, 0, 1200)), Some("H0"));
+        assert_eq!(sprt.get_decision(&new_score(5, 0, 5)), None);
+    }
+
+    fn play_random(start_fen: &str) -> GameRecord {
+        let mut white = PlayerSpec::parse("random").unwrap().create().unwrap();
+        let mut black = PlayerSpec::parse("random").unwrap().create().unwrap();
+        play_game(white.as_mut(), black.as_mut(), start_fen, &GameSettings::default())
+    }
+
+    /// Each way a game is drawn by the rules is named in the record
+    #[test]
+    fn draws_name_their_reason() {
+        let cases = [
+            ("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", "stalemate"),
+            ("4k3/8/8/8/8/8/4P3/4K3 w - - 100 80", "fifty-move rule"),
+            ("4k3/8/8/8/8/8/8/4KB2 w - - 0 1", "insufficient material"),
+        ];
+        for (start_fen, termination) in cases {
+            let record = play_random(start_fen);
+            assert_eq!(record.get_result(), "1/2-1/2");
+            assert_eq!(record.termination, termination);
+        }
+    }
+}
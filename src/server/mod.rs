@@ -1,8 +1,9 @@
 use actix_web::{ web, App, HttpServer, HttpRequest, HttpResponse };
 use actix_cors::Cors;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
-use crate::{chess::{move_api::{MoveAPI, self}, position::Position, cmove::Move, fen, piece::{fen_to_piece, Piece, self}, color::Color}, game::agent::{RandomAgent, Agent, TomatoAgent}, api::chess_api::{GameAPI, ChessGame, Message::{Info, Error}}};
+use crate::{chess::{move_api::{MoveAPI, self}, position::Position, cmove::Move, fen, piece::{fen_to_piece, Piece, self}, color::Color, nnue::Network, syzygy::{self, Tablebases}, dtm::{self, DtmTablebases}, variant::Variant}, game::{agent::{RandomAgent, Agent, TomatoAgent, AnalysisLine}, book::{self, BookSelection, OpeningBook}, evaluator::{self, Evaluator}, kpk, personality::{self, Personality}, skill::{self, Skill}, transposition}, api::chess_api::{GameAPI, ChessGame, Message::{Info, Error}}};
 
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -14,6 +15,78 @@ struct MoveRequest {
     endCol: u32,
     promotion: String,
     depth: u32,
+    #[serde(default = "default_threads")]
+    threads: u32,
+    #[serde(default = "default_evaluator")]
+    evaluator: String,
+    #[serde(default = "default_variant")]
+    variant: String,
+    /// Letter of a pocket piece to drop on the end square (Crazyhouse),
+    /// the start square is ignored for drops
+    #[serde(default)]
+    drop: String,
+    /// Play from the server's opening book before searching
+    #[serde(default = "default_book")]
+    book: bool,
+    #[serde(default = "default_book_depth", rename = "bookDepth")]
+    book_depth: u32,
+    /// "weighted" or "best"
+    #[serde(default = "default_book_selection", rename = "bookSelection")]
+    book_selection: String,
+    /// Probe the server's Syzygy tables
+    #[serde(default = "default_syzygy")]
+    syzygy: bool,
+    /// Play from the server's distance-to-mate tables
+    #[serde(default = "default_dtm")]
+    dtm: bool,
+    /// 0 to 20, lower levels search shallower and play weaker moves
+    #[serde(default = "default_skill_level", rename = "skillLevel")]
+    skill_level: u32,
+    /// Elo the agent aims to play at, takes the place of the
+    /// skill level when set. 0 turns it off. The levels are not
+    /// calibrated against rated play, see `Skill::from_elo`
+    #[serde(default, rename = "targetElo")]
+    target_elo: u32,
+    /// Name of a personality from the personality file, its weights
+    /// take the place of the evaluator. Empty plays without one
+    #[serde(default)]
+    personality: String,
+}
+
+fn default_threads() -> u32 {
+    1
+}
+
+fn default_book() -> bool {
+    true
+}
+
+fn default_book_depth() -> u32 {
+    book::DEFAULT_BOOK_DEPTH as u32
+}
+
+fn default_book_selection() -> String {
+    String::from("weighted")
+}
+
+fn default_syzygy() -> bool {
+    true
+}
+
+fn default_dtm() -> bool {
+    true
+}
+
+fn default_skill_level() -> u32 {
+    skill::MAX_SKILL_LEVEL
+}
+
+fn default_evaluator() -> String {
+    String::from(evaluator::DEFAULT_EVALUATOR)
+}
+
+fn default_variant() -> String {
+    String::from(Variant::Standard.get_name())
 }
 
 
@@ -62,38 +135,235 @@ impl MoveResponse {
 
 
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AnalysisRequest {
+    fen: String,
+    depth: u32,
+    lines: u32,
+    #[serde(default = "default_threads")]
+    threads: u32,
+    #[serde(default = "default_evaluator")]
+    evaluator: String,
+    #[serde(default = "default_variant")]
+    variant: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AnalysisLineResponse {
+    start_row: u32,
+    start_col: u32,
+    end_row: u32,
+    end_col: u32,
+    notation: String,
+    score: f64,
+    pv: Vec<String>,
+}
+
+impl AnalysisLineResponse {
+    fn new(line: &AnalysisLine) -> Self {
+        AnalysisLineResponse {
+            start_row: line.chess_move.start.get_row() as u32,
+            start_col: line.chess_move.start.get_col() as u32,
+            end_row: line.chess_move.end.get_row() as u32,
+            end_col: line.chess_move.end.get_col() as u32,
+            notation: line.chess_move.to_string(),
+            score: line.score,
+            pv: line.pv.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AnalysisResponse {
+    fen: String,
+    lines: Vec<AnalysisLineResponse>,
+}
+
+async fn analyze(
+    data: web::Json<AnalysisRequest>,
+    config: web::Data<ServerConfig>,
+    tables: web::Data<ServerTables>,
+    _req: HttpRequest,
+) -> HttpResponse {
+    let threads = match config.check_threads(data.threads) {
+        Ok(threads) => threads,
+        Err(msg) => return HttpResponse::BadRequest().body(msg),
+    };
+    if let Err(msg) = config.check_analysis(data.depth, data.lines) {
+        return HttpResponse::BadRequest().body(msg);
+    }
+    let evaluator = match evaluator::from_fixed_name(&data.evaluator, tables.network.as_ref()) {
+        Ok(e) => e,
+        Err(msg) => return HttpResponse::BadRequest().body(msg),
+    };
+
+    let variant = match Variant::from_name(&data.variant) {
+        Some(v) => v,
+        None => return HttpResponse::BadRequest().body(format!("Unknown variant {}", data.variant)),
+    };
+    if let Err(msg) = fen::validate(&data.fen, variant) {
+        return HttpResponse::BadRequest().body(format!("Illegal position: {}", msg));
+    }
+
+    let mut move_api = MoveAPI::with_variant(&data.fen, variant);
+    let color = match move_api.get_turn_color() {
+        Color::White => 1f64,
+        Color::Black => -1f64,
+    };
+
+    let mut agent = TomatoAgent::with_table_entries(data.depth as usize, evaluator, config.table_entries).with_threads(threads);
+    let lines = agent.analyze(&mut move_api, color, data.lines as usize);
+
+    HttpResponse::Ok().json(AnalysisResponse {
+        fen: data.fen.clone(),
+        lines: lines.iter().map(AnalysisLineResponse::new).collect(),
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct NewGameRequest {
+    #[serde(default = "default_variant")]
+    variant: String,
+    /// Start from a random Chess960 array, standard rules only
+    #[serde(default)]
+    chess960: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct NewGameResponse {
+    fen: String,
+    variant: String,
+    turn: String,
+}
+
+/// Starting position of a game in the requested variant, later
+/// requests about the game pass the same variant name back.
+/// A Chess960 game is a standard one, its FEN tells the castling rooks
+async fn new_game(data: web::Json<NewGameRequest>, _req: HttpRequest) -> HttpResponse {
+    let variant = match Variant::from_name(&data.variant) {
+        Some(v) => v,
+        None => return HttpResponse::BadRequest().body(format!("Unknown variant {}", data.variant)),
+    };
+
+    let start_fen = match (data.chess960, variant) {
+        (false, _) => String::from(variant.get_start_fen()),
+        (true, Variant::Standard) => fen::random_chess960_start_position(),
+        (true, _) => return HttpResponse::BadRequest().body("Chess960 is only played with standard rules"),
+    };
+
+    let move_api = MoveAPI::with_variant(&start_fen, variant);
+    let turn = match move_api.get_turn_color() {
+        Color::White => String::from("white"),
+        Color::Black => String::from("black"),
+    };
+
+    HttpResponse::Ok().json(NewGameResponse {
+        fen: move_api.get_board_ref().get_fen(),
+        variant: String::from(variant.get_name()),
+        turn,
+    })
+}
+
 // todo wrap this in result in case req fails
-async fn try_move(data: web::Json<MoveRequest>, _req: HttpRequest) -> HttpResponse {
+async fn try_move(
+    data: web::Json<MoveRequest>,
+    personalities: web::Data<Vec<Personality>>,
+    config: web::Data<ServerConfig>,
+    tables: web::Data<ServerTables>,
+    _req: HttpRequest,
+) -> HttpResponse {
     println!("request got!");
+    let threads = match config.check_threads(data.threads) {
+        Ok(threads) => threads,
+        Err(msg) => return MoveResponse::illegal_move(msg),
+    };
+    let personality = match data.personality.as_str() {
+        "" => None,
+        name => match personalities.iter().find(|p| p.name == name) {
+            Some(p) => Some(p),
+            None => return MoveResponse::illegal_move(format!("Unknown personality {}", name)),
+        },
+    };
+    let evaluator = match (personality, evaluator::from_fixed_name(&data.evaluator, tables.network.as_ref())) {
+        (Some(p), _) => Box::new(p.get_evaluator()) as Box<dyn Evaluator>,
+        (None, Ok(e)) => e,
+        (None, Err(msg)) => return MoveResponse::illegal_move(msg),
+    };
+    let variant = match Variant::from_name(&data.variant) {
+        Some(v) => v,
+        None => return MoveResponse::illegal_move(format!("Unknown variant {}", data.variant)),
+    };
+    if let Err(msg) = fen::validate(&data.fen, variant) {
+        return MoveResponse::illegal_move(format!("Illegal position: {}", msg));
+    }
+    let book_selection = match BookSelection::from_name(&data.book_selection) {
+        Some(s) => s,
+        None => return MoveResponse::illegal_move(format!("Unknown book selection {}", data.book_selection)),
+    };
+    if data.skill_level > skill::MAX_SKILL_LEVEL {
+        return MoveResponse::illegal_move(format!("Skill level must be 0 to {}", skill::MAX_SKILL_LEVEL));
+    }
+    let skill = match data.target_elo {
+        0 => Skill::new(data.skill_level),
+        elo => Skill::from_elo(elo),
+    };
+
     let start = Position::new(data.startRow as i8, data.startCol as i8);
     let end = Position::new(data.endRow as i8, data.endCol as i8);
 
     println!("Attempting: {} {}", start.to_string(), end.to_string());
-    let mut move_api = MoveAPI::new(&data.fen);
+    let mut move_api = MoveAPI::with_variant(&data.fen, variant);
 
     let mut game_api = ChessGame::new(move_api);
     game_api.dbg();
-    let option = game_api.get_piece(start);
+    let mut user_move = match data.drop.chars().next() {
+        Some(letter) => Move::drop(fen_to_piece(letter).0, end),
+        None => {
+            let option = game_api.get_piece(start);
 
-    let piece;
-    match option {
-        None => return MoveResponse::illegal_move(String::from("No piece selected")),
-        Some(p) => piece = p,
-    }
+            let piece;
+            match option {
+                None => return MoveResponse::illegal_move(String::from("No piece selected")),
+                Some(p) => piece = p,
+            }
 
-    let mut user_move = Move::new(
-        start,  
-        end,
-        piece,
-        None
-    );
+            Move::new(
+                start,  
+                end,
+                piece,
+                None
+            )
+        }
+    };
 
     let result = game_api.try_move(user_move);
     
     match result {
         Info(msg) => {
             println!("{}",msg);
-            let mut agent = TomatoAgent::new(data.depth as usize);
+            let mut agent = TomatoAgent::with_table_entries(data.depth as usize, evaluator, config.table_entries)
+                .with_threads(threads)
+                .with_skill(skill);
+            if let Some(personality) = personality {
+                agent = agent.with_personality(personality);
+            }
+
+            if let Some(opening_book) = tables.book.as_ref().filter(|_| data.book) {
+                agent = agent.with_book(opening_book.clone(), data.book_depth as usize, book_selection);
+            }
+
+            if let Some(tablebases) = tables.tablebases.as_ref().filter(|_| data.syzygy) {
+                agent = agent.with_tablebases(tablebases.clone());
+            }
+
+            if let Some(dtm_tables) = tables.dtm_tables.as_ref().filter(|_| data.dtm) {
+                agent = agent.with_dtm_tables(dtm_tables.clone());
+            }
 
             println!("Agent thinking...");
             let option = agent.getAgentMove(&mut game_api);
@@ -156,21 +426,219 @@ async fn try_move(data: web::Json<MoveRequest>, _req: HttpRequest) -> HttpRespon
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PersonalityResponse {
+    name: String,
+    description: String,
+}
+
+/// Names and descriptions of the personalities a move request can pick
+async fn list_personalities(personalities: web::Data<Vec<Personality>>) -> HttpResponse {
+    let list: Vec<PersonalityResponse> = personalities
+        .iter()
+        .map(|p| PersonalityResponse {
+            name: p.name.clone(),
+            description: p.description.clone(),
+        })
+        .collect();
+    HttpResponse::Ok().json(list)
+}
+
+/// Deepest analysis a request may ask for unless `--max-analysis-depth` says otherwise
+const DEFAULT_MAX_ANALYSIS_DEPTH: usize = 10;
+/// Most analysis lines a request may ask for unless `--max-analysis-lines` says otherwise
+const DEFAULT_MAX_ANALYSIS_LINES: usize = 5;
+
+/// Settings given on the command line when the server starts
+struct ServerConfig {
+    /// Most search threads a request may ask for
+    max_threads: usize,
+    /// Deepest search an analysis request may ask for
+    max_analysis_depth: usize,
+    /// Most lines an analysis request may ask for
+    max_analysis_lines: usize,
+    /// Transposition table slots each request searches with
+    table_entries: usize,
+    /// Polyglot book requests can play from, empty for none
+    book: String,
+    /// Network the `nnue` evaluator plays, empty for none
+    nnue: String,
+    /// Directory of Syzygy tables, empty for none
+    syzygy: String,
+    /// Directory of generated distance-to-mate tables, empty for none
+    dtm: String,
+}
+
+impl ServerConfig {
+    /// Usage: [--max-threads N] [--max-analysis-depth N] [--max-analysis-lines N]
+    ///        [--table-entries N] [--book PATH] [--nnue PATH] [--syzygy DIR] [--dtm DIR]
+    ///
+    /// `max-threads` defaults to the number of cores, `max-analysis-depth` to
+    /// `DEFAULT_MAX_ANALYSIS_DEPTH`, `max-analysis-lines` to
+    /// `DEFAULT_MAX_ANALYSIS_LINES`, `table-entries` to
+    /// `transposition::DEFAULT_ENTRIES`, `book` to
+    /// `book::DEFAULT_BOOK_FILE`, `nnue` to `evaluator::NNUE_FILE`,
+    /// `syzygy` to `syzygy::DEFAULT_SYZYGY_DIR` and `dtm` to `dtm::DEFAULT_DTM_DIR`
+    fn parse(args: &[String]) -> Result<ServerConfig, String> {
+        let mut config = ServerConfig {
+            max_threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            max_analysis_depth: DEFAULT_MAX_ANALYSIS_DEPTH,
+            max_analysis_lines: DEFAULT_MAX_ANALYSIS_LINES,
+            table_entries: transposition::DEFAULT_ENTRIES,
+            book: String::from(book::DEFAULT_BOOK_FILE),
+            nnue: String::from(evaluator::NNUE_FILE),
+            syzygy: String::from(syzygy::DEFAULT_SYZYGY_DIR),
+            dtm: String::from(dtm::DEFAULT_DTM_DIR),
+        };
+
+        let mut rest = args.iter();
+        while let Some(arg) = rest.next() {
+            let value = rest.next().ok_or(format!("{} needs a value", arg))?;
+            let positive = || value.parse::<usize>().ok().filter(|n| *n > 0).ok_or(format!("{} needs a positive whole number", arg));
+            match arg.as_str() {
+                "--max-threads" => config.max_threads = positive()?,
+                "--max-analysis-depth" => config.max_analysis_depth = positive()?,
+                "--max-analysis-lines" => config.max_analysis_lines = positive()?,
+                "--table-entries" => config.table_entries = positive()?,
+                "--book" => config.book = value.clone(),
+                "--nnue" => config.nnue = value.clone(),
+                "--syzygy" => config.syzygy = value.clone(),
+                "--dtm" => config.dtm = value.clone(),
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The thread count a request asked for, if the server allows it
+    fn check_threads(&self, threads: u32) -> Result<usize, String> {
+        match threads as usize {
+            threads if threads > self.max_threads => {
+                Err(format!("At most {} threads are allowed, {} were asked for", self.max_threads, threads))
+            }
+            threads => Ok(threads.max(1)),
+        }
+    }
+
+    /// Whether the server allows an analysis `depth` plies deep with `lines` lines
+    fn check_analysis(&self, depth: u32, lines: u32) -> Result<(), String> {
+        if depth as usize > self.max_analysis_depth {
+            Err(format!("At most depth {} is allowed, {} was asked for", self.max_analysis_depth, depth))
+        } else if lines as usize > self.max_analysis_lines {
+            Err(format!("At most {} lines are allowed, {} were asked for", self.max_analysis_lines, lines))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Tables and the network read once when the server starts,
+/// requests only turn them on or off
+struct ServerTables {
+    book: Option<Arc<OpeningBook>>,
+    network: Option<Arc<Network>>,
+    tablebases: Option<Arc<Tablebases>>,
+    dtm_tables: Option<Arc<DtmTablebases>>,
+}
+
+impl ServerTables {
+    /// A missing table is not an error, requests just play without it
+    fn load(config: &ServerConfig) -> ServerTables {
+        let book = match config.book.as_str() {
+            "" => None,
+            path => match OpeningBook::load(path) {
+                Ok(opening_book) => Some(Arc::new(opening_book)),
+                Err(msg) => {
+                    println!("{}", msg);
+                    None
+                }
+            },
+        };
+        println!("Opening book: {}", if book.is_some() { config.book.as_str() } else { "none" });
+
+        let network = match config.nnue.as_str() {
+            "" => None,
+            path => match Network::load(path) {
+                Ok(network) => Some(Arc::new(network)),
+                Err(msg) => {
+                    println!("{}", msg);
+                    None
+                }
+            },
+        };
+        println!("NNUE network: {}", if network.is_some() { config.nnue.as_str() } else { "none" });
+
+        // Syzygy files are only listed here, each is read the first time it is probed
+        let tablebases = Some(config.syzygy.as_str())
+            .filter(|dir| !dir.is_empty())
+            .map(Tablebases::open)
+            .filter(|t| t.len() > 0)
+            .map(Arc::new);
+        println!("Syzygy tables: {}", tablebases.as_ref().map_or(0, |t| t.len()));
+
+        let dtm_tables = Some(config.dtm.as_str())
+            .filter(|dir| !dir.is_empty())
+            .map(DtmTablebases::open)
+            .filter(|t| t.len() > 0)
+            .map(Arc::new);
+        println!("DTM tables: {}", dtm_tables.as_ref().map_or(0, |t| t.len()));
+        ServerTables { book, network, tablebases, dtm_tables }
+    }
+}
+
+/// Start the server with the settings of `args`, see `ServerConfig::parse`
+pub fn server(args: &[String]) -> std::io::Result<()> {
+    match ServerConfig::parse(args) {
+        Ok(config) => serve(config),
+        Err(msg) => {
+            println!("{}", msg);
+            Ok(())
+        }
+    }
+}
+
 #[actix_web::main] // or #[tokio::main]
-pub async fn server() -> std::io::Result<()> {
+async fn serve(config: ServerConfig) -> std::io::Result<()> {
     println!("Starting server");
+    println!("Search threads per request: at most {}", config.max_threads);
+    println!("Analysis: at most depth {} and {} lines", config.max_analysis_depth, config.max_analysis_lines);
+    println!("Transposition table entries per request: {}", config.table_entries);
+
+    // built before the first request needs it
+    let bitbase = kpk::bitbase();
+    println!("KPK bitbase: {} won positions", bitbase.get_win_count());
+
+    // a missing file is not an error, requests just cannot pick a personality
+    let personalities = match Personality::load_all(personality::DEFAULT_PERSONALITY_FILE) {
+        Ok(personalities) => personalities,
+        Err(msg) => {
+            println!("{}", msg);
+            Vec::new()
+        }
+    };
+    println!("Personalities: {}", personalities.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", "));
+    let personalities = web::Data::new(personalities);
+    let tables = web::Data::new(ServerTables::load(&config));
+    let config = web::Data::new(config);
 
-    HttpServer::new(|| {
+    HttpServer::new(move || {
 
         let cors = Cors::permissive()
         .allowed_origin("http://localhost:5173")
-        .allowed_methods(vec!["POST"])
+        .allowed_methods(vec!["GET", "POST"])
         .allow_any_header();
 
     
         App::new()
         .wrap(cors)
+        .app_data(personalities.clone())
+        .app_data(config.clone())
+        .app_data(tables.clone())
         .service(web::resource("/requestMove").route(web::post().to(try_move)))
+        .service(web::resource("/analyze").route(web::post().to(analyze)))
+        .service(web::resource("/newGame").route(web::post().to(new_game)))
+        .service(web::resource("/personalities").route(web::get().to(list_personalities)))
     })
     .bind(("127.0.0.1", 3131))?
     .run()
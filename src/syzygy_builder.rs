@@ -0,0 +1,195 @@
+use std::fs;
+use std::path::Path;
+
+use crate::chess::board::Board;
+use crate::chess::dtm::{Dtm, DtmTablebases, Signature};
+use crate::chess::fen;
+use crate::chess::move_api::MoveAPI;
+use crate::chess::piece::Piece;
+use crate::chess::syzygy::{self, Wdl};
+use crate::tablebase_builder;
+
+/// Plies past which a win no longer beats the fifty move rule
+const FIFTY_MOVE_PLIES: i32 = 100;
+
+/// The moves of a decisive position as the DTZ count sees them
+struct DtzMoves {
+    /// A capture, pawn move or mate keeps the result right away
+    is_zeroing_best: bool,
+    /// Positions of the same table the other moves lead to, the
+    /// ones lost for the opponent when the position is won
+    children: Vec<u32>,
+}
+
+/// Usage: build-syzygy <output dir> <signature>...
+///
+/// Writes Syzygy WDL and DTZ files for signatures like KQvKR with up to four
+/// men, together with the smaller tables their captures and promotions lead
+/// to. Results come from the generated distance-to-mate tables. Every value
+/// is Huffman coded on its own, so the files are larger than the published
+/// ones but read the same
+pub fn run(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: build-syzygy <output dir> <signature>...");
+        return;
+    }
+
+    let mut signatures = Vec::new();
+    for name in &args[1..] {
+        match Signature::parse(name) {
+            Ok(signature) => signatures.push(signature.normalize()),
+            Err(msg) => {
+                println!("{}", msg);
+                return;
+            }
+        }
+    }
+
+    match build(Path::new(&args[0]), &signatures) {
+        Ok(names) => {
+            for name in names {
+                println!("Wrote {}", name);
+            }
+        }
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+/// Write the tables of `signatures` and of the tables they lead to into `dir`,
+/// returns the names of the tables written
+pub fn build(dir: &Path, signatures: &[Signature]) -> Result<Vec<String>, String> {
+    let mut tables = DtmTablebases::new();
+    for signature in signatures {
+        tablebase_builder::generate_with_successors(signature, &mut tables);
+    }
+
+    fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    let mut names = Vec::new();
+    tablebase_builder::collect_names(signatures, &mut names);
+    for name in &names {
+        let signature = Signature::parse(name)?;
+        let positions = get_positions(&signature, &tables)?;
+        syzygy::write_table(dir, name, &positions)?;
+    }
+    Ok(names)
+}
+
+/// Every position of a generated table with its result and DTZ for the side to move
+fn get_positions(signature: &Signature, tables: &DtmTablebases) -> Result<Vec<(Board, Wdl, i32)>, String> {
+    let table = tables.get(signature).ok_or(format!("{} is not generated", signature.get_name()))?;
+    let dtz = get_dtz(signature, tables);
+
+    let mut out = Vec::new();
+    for (index, result) in table.get_results() {
+        let board = signature.get_board(index).unwrap();
+        let wdl = match result {
+            Dtm::Win(_) => Wdl::Win,
+            Dtm::Draw => Wdl::Draw,
+            Dtm::Loss(_) => Wdl::Loss,
+        };
+        if dtz[index].abs() > FIFTY_MOVE_PLIES {
+            return Err(format!("{} is won past the fifty move rule, such tables are not written", board.get_fen()));
+        }
+        out.push((board, wdl, dtz[index]));
+    }
+    Ok(out)
+}
+
+/// DTZ in plies of every index of a generated table, 0 for draws.
+///
+/// Zeroing moves and mates count one ply. Then the plies are settled in
+/// order: a won position takes one more than its fastest lost child, a lost
+/// one one more than its slowest won child once all of them are settled
+fn get_dtz(signature: &Signature, tables: &DtmTablebases) -> Vec<i32> {
+    let table = tables.get(signature).unwrap();
+    let mut move_api = MoveAPI::new(fen::STARTING_BOARD);
+    let mut results = vec![Dtm::Draw; signature.get_size()];
+    let mut dtz = vec![0i32; signature.get_size()];
+    let mut pending = Vec::new();
+    let mut moves_of = Vec::new();
+
+    for (index, result) in table.get_results() {
+        results[index] = result;
+        if result == Dtm::Draw {
+            continue;
+        }
+        if result == Dtm::Loss(0) {
+            dtz[index] = -1;
+            continue;
+        }
+
+        let board = signature.get_board(index).unwrap();
+        let is_win = matches!(result, Dtm::Win(_));
+        let mut moves = DtzMoves {
+            is_zeroing_best: false,
+            children: Vec::new(),
+        };
+        move_api.set_board(board);
+        for m in move_api.get_legal_moves() {
+            move_api.exec_move(m);
+            let child = *move_api.get_board_ref();
+            move_api.undo_move();
+
+            let count = |board: &Board| (board.white_pieces | board.black_pieces).count_ones();
+            let child_result = tables.probe(&child).expect("smaller tables are generated first");
+            if m.piece == Piece::Pawn || count(&board) != count(&child) {
+                // a losing side has to take any zeroing move it finds
+                moves.is_zeroing_best |= !is_win || matches!(child_result, Dtm::Loss(_));
+            } else if child_result == Dtm::Loss(0) {
+                moves.is_zeroing_best |= is_win;
+            } else if !is_win || matches!(child_result, Dtm::Loss(_)) {
+                moves.children.push(signature.get_index(&child) as u32);
+            }
+        }
+        pending.push(index);
+        moves_of.push(moves);
+    }
+
+    let mut plies = 1;
+    while !pending.is_empty() {
+        let mut settled = Vec::new();
+        for (i, index) in pending.iter().enumerate() {
+            let moves = &moves_of[i];
+            let children = moves.children.iter().map(|child| dtz[*child as usize]);
+            let value = if matches!(results[*index], Dtm::Win(_)) {
+                if moves.is_zeroing_best {
+                    Some(1)
+                } else {
+                    children.filter(|d| *d < 0).map(|d| 1 - d).min()
+                }
+            } else if children.clone().any(|d| d == 0) {
+                None
+            } else {
+                Some(children.map(|d| d + 1).max().unwrap_or(1).max(if moves.is_zeroing_best { 1 } else { 0 }))
+            };
+            if value == Some(plies) {
+                settled.push(i);
+            }
+        }
+        assert!(plies <= 2 * FIFTY_MOVE_PLIES || !settled.is_empty(), "{} has positions DTZ never settles", signature.get_name());
+
+        for i in settled.iter().rev() {
+            let index = pending.swap_remove(*i);
+            moves_of.swap_remove(*i);
+            dtz[index] = if matches!(results[index], Dtm::Win(_)) { plies } else { -plies };
+        }
+        plies += 1;
+    }
+    dtz
+}
+
+/// KQvK, KRvK, KPvK and the tables they lead to, written to
+/// target/syzygy-test once for every test run
+#[cfg(test)]
+pub fn build_test_tables() -> &'static Path {
+    use std::path::PathBuf;
+    use std::sync::OnceLock;
+
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("syzygy-test");
+        let signatures: Vec<Signature> = ["KQvK", "KRvK", "KPvK"].iter().map(|name| Signature::parse(name).unwrap()).collect();
+        build(&dir, &signatures).unwrap();
+        dir
+    })
+}
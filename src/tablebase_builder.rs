@@ -0,0 +1,327 @@
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::chess::board::Board;
+use crate::chess::dtm::{self, Dtm, DtmTable, DtmTablebases, Signature, DRAW, ILLEGAL, MAX_PLIES};
+use crate::chess::fen;
+use crate::chess::move_api::MoveAPI;
+use crate::chess::move_generator::MoveGenerator;
+use crate::chess::piece::Piece;
+use crate::chess::unmove_generator::UnmoveGenerator;
+
+/// What the moves of a position that leave the table lead to
+struct Exits {
+    /// Fastest win through a capture or promotion, in plies
+    best_win: Option<usize>,
+    /// Longest loss through one
+    longest_loss: usize,
+    has_draw: bool,
+}
+
+/// Usage: build-tablebase <output dir> <signature>...
+///
+/// Generates the tables of signatures like KQvKR with up to four men,
+/// together with the smaller tables their captures and promotions lead to,
+/// and writes every one of them to the directory
+pub fn run(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: build-tablebase <output dir> <signature>...");
+        return;
+    }
+
+    let mut signatures = Vec::new();
+    for name in &args[1..] {
+        match Signature::parse(name) {
+            Ok(signature) => signatures.push(signature.normalize()),
+            Err(msg) => {
+                println!("{}", msg);
+                return;
+            }
+        }
+    }
+
+    let mut tables = DtmTablebases::new();
+    for signature in &signatures {
+        generate_with_successors(signature, &mut tables);
+    }
+
+    if let Err(e) = fs::create_dir_all(&args[0]) {
+        println!("Could not create {}: {}", args[0], e);
+        return;
+    }
+    let mut names: Vec<String> = Vec::new();
+    collect_names(&signatures, &mut names);
+    for name in names {
+        let table = tables.get(&Signature::parse(&name).unwrap()).unwrap();
+        let path = Path::new(&args[0]).join(format!("{}.{}", name, dtm::DTM_EXTENSION));
+        match table.save(&path) {
+            Ok(()) => println!("Wrote {}", path.display()),
+            Err(msg) => println!("{}", msg),
+        }
+    }
+}
+
+/// Names of the signatures and all tables they lead to
+pub fn collect_names(signatures: &[Signature], names: &mut Vec<String>) {
+    for signature in signatures {
+        let name = signature.get_name();
+        if names.contains(&name) {
+            continue;
+        }
+        names.push(name);
+        collect_names(&signature.get_successors(), names);
+    }
+}
+
+/// Generate the tables `signature` needs first, then its own,
+/// skipping the ones already in `tables`
+pub fn generate_with_successors(signature: &Signature, tables: &mut DtmTablebases) {
+    if tables.get(signature).is_some() {
+        return;
+    }
+    for successor in signature.get_successors() {
+        generate_with_successors(&successor, tables);
+    }
+
+    let now = Instant::now();
+    let table = generate(signature, tables);
+    print_summary(&table, now.elapsed().as_secs_f64());
+    tables.insert(table);
+}
+
+fn print_summary(table: &DtmTable, seconds: f64) {
+    let (mut wins, mut draws, mut losses, mut longest) = (0, 0, 0, 0);
+    for (_, result) in table.get_results() {
+        match result {
+            Dtm::Win(plies) => {
+                wins += 1;
+                longest = longest.max(plies);
+            }
+            Dtm::Draw => draws += 1,
+            Dtm::Loss(_) => losses += 1,
+        }
+    }
+    println!(
+        "{}: {} wins, {} draws, {} losses for the side to move, longest mate {} moves, {:.1}s",
+        table.get_signature().get_name(),
+        wins,
+        draws,
+        losses,
+        longest.div_ceil(2),
+        seconds
+    );
+}
+
+/// Whether a move left the table: it captured or promoted
+fn is_exit(before: &Board, after: &Board) -> bool {
+    let count = |board: &Board| (board.white_pieces | board.black_pieces).count_ones();
+    count(before) != count(after) || before.get_piece_board(Piece::Pawn).count_ones() != after.get_piece_board(Piece::Pawn).count_ones()
+}
+
+/// Retrograde analysis of one signature, the tables its
+/// captures and promotions lead to must be in `tables`.
+///
+/// Each position first counts its moves that stay in the table, as distinct
+/// stored children, and looks its other moves up. Then the plies are settled
+/// in order from the checkmates: predecessors of a position lost in `n` plies
+/// win in `n + 1`, and a position whose children all turned out won for the
+/// opponent loses once the last of them is settled. What is left is drawn
+pub fn generate(signature: &Signature, tables: &DtmTablebases) -> DtmTable {
+    let size = signature.get_size();
+    let move_generator = MoveGenerator::new();
+    let unmove_generator = UnmoveGenerator::new();
+    let mut move_api = MoveAPI::new(fen::STARTING_BOARD);
+
+    let mut values = vec![ILLEGAL; size];
+    let mut child_counts = vec![0u8; size];
+    let mut longest_exit_losses = vec![0u8; size];
+    let mut can_lose = vec![false; size];
+    let mut pending: Vec<Vec<u32>> = vec![Vec::new(); MAX_PLIES + 1];
+
+    for index in 0..size {
+        let board = match signature.get_board(index) {
+            Some(board) => board,
+            None => continue,
+        };
+        // the side that just moved may not be in check
+        if move_generator.is_attacked_king(&board, board.turn) {
+            continue;
+        }
+        values[index] = DRAW;
+
+        move_api.set_board(board);
+        let moves = move_api.get_legal_moves();
+        if moves.is_empty() {
+            if move_generator.is_attacked_king(&board, board.turn.get_opposite()) {
+                pending[0].push(index as u32);
+            }
+            continue;
+        }
+
+        let mut children = Vec::new();
+        let mut exits = Exits {
+            best_win: None,
+            longest_loss: 0,
+            has_draw: false,
+        };
+        for m in moves {
+            move_api.exec_move(m);
+            let child = *move_api.get_board_ref();
+            move_api.undo_move();
+
+            if !is_exit(&board, &child) {
+                children.push(signature.get_index(&child));
+                continue;
+            }
+            match tables.probe(&child).expect("smaller tables are generated first").before() {
+                Dtm::Win(plies) => {
+                    let plies = plies as usize;
+                    exits.best_win = Some(exits.best_win.map_or(plies, |best| best.min(plies)));
+                }
+                Dtm::Loss(plies) => exits.longest_loss = exits.longest_loss.max(plies as usize),
+                Dtm::Draw => exits.has_draw = true,
+            }
+        }
+        children.sort_unstable();
+        children.dedup();
+
+        child_counts[index] = children.len() as u8;
+        longest_exit_losses[index] = exits.longest_loss as u8;
+        can_lose[index] = exits.best_win.is_none() && !exits.has_draw;
+        if let Some(plies) = exits.best_win {
+            pending[plies].push(index as u32);
+        } else if children.is_empty() && can_lose[index] {
+            pending[exits.longest_loss].push(index as u32);
+        }
+    }
+
+    for plies in 0..=MAX_PLIES {
+        // a position may be pending more than once, it is settled the first time
+        let mut settled = Vec::new();
+        for index in pending[plies].iter().map(|index| *index as usize) {
+            if values[index] == DRAW {
+                values[index] = plies as u8 + 1;
+                settled.push(index);
+            }
+        }
+
+        let is_loss = plies % 2 == 0;
+        for index in settled {
+            let board = signature.get_board(index).unwrap();
+            let mut parents: Vec<usize> = unmove_generator
+                .get_predecessors(&board)
+                .iter()
+                .map(|parent| signature.get_index(parent))
+                .collect();
+            parents.sort_unstable();
+            parents.dedup();
+
+            for parent in parents {
+                if values[parent] != DRAW {
+                    continue;
+                }
+                if is_loss {
+                    assert!(plies < MAX_PLIES, "{} has mates longer than a table holds", signature.get_name());
+                    pending[plies + 1].push(parent as u32);
+                    continue;
+                }
+
+                child_counts[parent] -= 1;
+                if child_counts[parent] == 0 && can_lose[parent] {
+                    let loss = (plies + 1).max(longest_exit_losses[parent] as usize);
+                    pending[loss].push(parent as u32);
+                }
+            }
+        }
+    }
+
+    DtmTable::new(signature.clone(), values)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use super::*;
+    use crate::chess::color::Color;
+    use crate::game::kpk;
+
+    /// Positions of generated tables with their results for the side to move
+    const DTM_SUITE: [(&str, Dtm); 9] = [
+        ("7k/8/6K1/8/8/8/Q7/8 w - - 0 1", Dtm::Win(1)),
+        ("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1", Dtm::Loss(0)),
+        ("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", Dtm::Draw),
+        ("8/8/8/8/8/6k1/6q1/7K w - - 0 1", Dtm::Loss(0)),
+        ("7k/8/6K1/8/8/8/8/R7 w - - 0 1", Dtm::Win(1)),
+        ("7K/8/6k1/8/8/8/8/r7 b - - 0 1", Dtm::Win(1)),
+        ("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1", Dtm::Draw),
+        ("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1", Dtm::Loss(28)),
+        ("7k/8/7K/7P/8/8/8/8 w - - 0 1", Dtm::Draw),
+    ];
+
+    /// Longest mates of the generated tables, in moves
+    const DTM_LONGEST_MATES: [(&str, u32); 3] = [("KQvK", 10), ("KRvK", 16), ("KPvK", 28)];
+
+    /// The tables of DTM_LONGEST_MATES, generated once for every test
+    fn generate_small_tables() -> &'static DtmTablebases {
+        static TABLES: OnceLock<DtmTablebases> = OnceLock::new();
+        TABLES.get_or_init(|| {
+            let mut tables = DtmTablebases::new();
+            for (name, _) in DTM_LONGEST_MATES {
+                generate_with_successors(&Signature::parse(name).unwrap(), &mut tables);
+            }
+            tables
+        })
+    }
+
+    #[test]
+    fn longest_mates_match_known_values() {
+        let tables = generate_small_tables();
+        for (name, expected) in DTM_LONGEST_MATES {
+            let table = tables.get(&Signature::parse(name).unwrap()).unwrap();
+            let longest = table
+                .get_results()
+                .filter_map(|(_, result)| match result {
+                    Dtm::Win(plies) => Some(plies.div_ceil(2)),
+                    _ => None,
+                })
+                .max();
+            assert_eq!(longest, Some(expected), "{}", name);
+        }
+    }
+
+    #[test]
+    fn known_positions_probe_right() {
+        let tables = generate_small_tables();
+        for (fen, expected) in DTM_SUITE {
+            assert_eq!(tables.probe(MoveAPI::new(fen).get_board_ref()), Some(expected), "{}", fen);
+        }
+    }
+
+    /// Every KPK position agrees with the bitbase on whether the pawn wins
+    #[test]
+    fn kpk_table_matches_bitbase() {
+        let tables = generate_small_tables();
+        let kpk_table = tables.get(&Signature::parse("KPvK").unwrap()).unwrap();
+        let bitbase = kpk::bitbase();
+        for (index, result) in kpk_table.get_results() {
+            let board = kpk_table.get_signature().get_board(index).unwrap();
+            let square = |bitboard: u64| bitboard.leading_zeros() as usize ^ 56;
+            let is_white_to_move = board.turn == Color::White;
+            let is_win = bitbase.is_win(
+                true,
+                is_white_to_move,
+                square(board.kings & board.white_pieces),
+                square(board.kings & board.black_pieces),
+                square(board.pawns),
+            );
+            let is_table_win = match result {
+                Dtm::Win(_) => is_white_to_move,
+                Dtm::Loss(_) => !is_white_to_move,
+                Dtm::Draw => false,
+            };
+            assert_eq!(is_win, is_table_win, "{}", board.get_fen());
+        }
+    }
+}
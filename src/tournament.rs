@@ -0,0 +1,644 @@
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use crate::chess::color::Color;
+use crate::chess::variant::Outcome;
+use crate::match_runner::{self, GameRecord, GameSettings, Player, PlayerSpec};
+
+/// Virtual draws every pairing starts with, as in BayesElo. They keep the
+/// ratings of players that won or lost everything finite
+const PRIOR_DRAWS: f64 = 2.0;
+
+/// Rating iterations stop once no rating moves more than this
+const RATING_TOLERANCE: f64 = 1e-6;
+
+const MAX_RATING_ITERATIONS: usize = 10000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Schedule {
+    /// Everyone plays everyone
+    RoundRobin,
+    /// The first player plays everyone else
+    Gauntlet,
+}
+
+struct TournamentOptions {
+    specs: Vec<PlayerSpec>,
+    schedule: Schedule,
+    rounds: usize,
+    concurrency: usize,
+    openings: Vec<String>,
+    pgn_path: Option<String>,
+    settings: GameSettings,
+}
+
+/// One game of the schedule, players are indices into the player list
+#[derive(Clone)]
+struct ScheduledGame {
+    index: usize,
+    round: usize,
+    white: usize,
+    black: usize,
+    opening: String,
+}
+
+/// What the state file keeps of a finished game
+struct FinishedGame {
+    white_score: f64,
+    failed: Option<Color>,
+}
+
+fn parse_options(args: &[String]) -> Result<TournamentOptions, String> {
+    let mut options = TournamentOptions {
+        specs: Vec::new(),
+        schedule: Schedule::RoundRobin,
+        rounds: 1,
+        concurrency: 1,
+        openings: match_runner::DEFAULT_OPENINGS.iter().map(|fen| fen.to_string()).collect(),
+        pgn_path: None,
+        settings: GameSettings::default(),
+    };
+
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if !arg.starts_with("--") {
+            options.specs.push(PlayerSpec::parse(arg)?);
+            continue;
+        }
+        if arg == "--gauntlet" {
+            options.schedule = Schedule::Gauntlet;
+            continue;
+        }
+
+        let value = rest.next().ok_or(format!("{} needs a value", arg))?;
+        if options.settings.set_option(arg, value)? {
+            continue;
+        }
+        let count = || value.parse::<usize>().ok().filter(|n| *n > 0).ok_or(format!("{} needs a positive number", arg));
+        match arg.as_str() {
+            "--rounds" => options.rounds = count()?,
+            "--concurrency" => options.concurrency = count()?,
+            "--openings" => options.openings = match_runner::load_openings(value)?,
+            "--pgn" => options.pgn_path = Some(value.clone()),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if options.specs.len() < 2 {
+        return Err("a tournament needs at least two players".to_string());
+    }
+    Ok(options)
+}
+
+/// Every round plays each pairing twice from the same opening, colors
+/// swapped, and each pairing of each round gets the next opening
+fn get_schedule(options: &TournamentOptions) -> Vec<ScheduledGame> {
+    let players = options.specs.len();
+    let pairings: Vec<(usize, usize)> = match options.schedule {
+        Schedule::RoundRobin => (0..players).flat_map(|a| (a + 1..players).map(move |b| (a, b))).collect(),
+        Schedule::Gauntlet => (1..players).map(|b| (0, b)).collect(),
+    };
+
+    let mut out = Vec::new();
+    for round in 0..options.rounds {
+        for (a, b) in &pairings {
+            let opening = &options.openings[out.len() / 2 % options.openings.len()];
+            for (white, black) in [(*a, *b), (*b, *a)] {
+                out.push(ScheduledGame {
+                    index: out.len(),
+                    round,
+                    white,
+                    black,
+                    opening: opening.clone(),
+                });
+            }
+        }
+    }
+    out
+}
+
+/// What the state file holds: the arguments the tournament
+/// was started with and the games it finished, by schedule index
+struct TournamentState {
+    args: Vec<String>,
+    games: Vec<(usize, FinishedGame)>,
+}
+
+fn load_state(path: &str) -> Result<TournamentState, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let mut args = Vec::new();
+    let mut games = Vec::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let malformed = || format!("Malformed line: {}", line);
+        let (kind, rest) = line.split_once(' ').ok_or_else(malformed)?;
+        match kind {
+            "arg" => args.push(rest.to_string()),
+            "game" => {
+                let fields: Vec<&str> = rest.splitn(4, ' ').collect();
+                if fields.len() < 3 {
+                    return Err(malformed());
+                }
+                let index = fields[0].parse::<usize>().map_err(|_| malformed())?;
+                let white_score = match fields[1] {
+                    "1-0" => 1.0,
+                    "0-1" => 0.0,
+                    "1/2-1/2" => 0.5,
+                    _ => return Err(malformed()),
+                };
+                let failed = match fields[2] {
+                    "white" => Some(Color::White),
+                    "black" => Some(Color::Black),
+                    _ => None,
+                };
+                games.push((index, FinishedGame { white_score, failed }));
+            }
+            _ => return Err(malformed()),
+        }
+    }
+    Ok(TournamentState { args, games })
+}
+
+/// The games of `schedule` a state file says are finished, by schedule index
+fn get_finished(schedule: &[ScheduledGame], games: Vec<(usize, FinishedGame)>) -> Vec<Option<FinishedGame>> {
+    let mut finished: Vec<Option<FinishedGame>> = schedule.iter().map(|_| None).collect();
+    for (index, game) in games {
+        if index < finished.len() {
+            finished[index] = Some(game);
+        }
+    }
+    finished
+}
+
+/// The games of `schedule` that are not finished, in schedule order
+fn get_queue(schedule: &[ScheduledGame], finished: &[Option<FinishedGame>]) -> VecDeque<ScheduledGame> {
+    schedule.iter().filter(|game| finished[game.index].is_none()).cloned().collect()
+}
+
+fn get_state_line(game: &ScheduledGame, record: &GameRecord) -> String {
+    let failed = match record.failed {
+        Some(Color::White) => "white",
+        Some(Color::Black) => "black",
+        None => "-",
+    };
+    format!("game {} {} {} {}\n", game.index, record.get_result(), failed, record.termination)
+}
+
+/// Names to tell the players apart by, a number is added to repeated ones
+fn get_names(players: &[Box<dyn Player>]) -> Vec<String> {
+    let names: Vec<String> = players.iter().map(|player| player.get_name()).collect();
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| match names[..i].iter().filter(|other| *other == name).count() {
+            0 => name.clone(),
+            n => format!("{} ({})", name, n + 1),
+        })
+        .collect()
+}
+
+/// The player in `slot`, started first if it is not running
+fn take_player(slot: &mut Option<Box<dyn Player>>, spec: &PlayerSpec) -> Result<Box<dyn Player>, String> {
+    match slot.take() {
+        Some(player) => Ok(player),
+        None => spec.create(),
+    }
+}
+
+/// A game lost by the player of `color` before it began
+fn get_failure(game: &ScheduledGame, color: Color, msg: String) -> GameRecord {
+    GameRecord {
+        white: String::new(),
+        black: String::new(),
+        start_fen: game.opening.clone(),
+        moves: Vec::new(),
+        scores: Vec::new(),
+        outcome: Outcome::Win(color.get_opposite()),
+        termination: msg,
+        failed: Some(color),
+    }
+}
+
+/// Play games from the queue until it is empty. Each worker runs its own
+/// copy of every player. A player that failed is dropped and started again
+/// for its next game, one that cannot be started loses its games
+fn run_worker(
+    specs: &[PlayerSpec],
+    queue: &Mutex<VecDeque<ScheduledGame>>,
+    settings: &GameSettings,
+    results: &mpsc::Sender<(ScheduledGame, GameRecord)>,
+) {
+    let mut players: Vec<Option<Box<dyn Player>>> = specs.iter().map(|_| None).collect();
+
+    loop {
+        let game = match queue.lock().unwrap().pop_front() {
+            Some(game) => game,
+            None => return,
+        };
+
+        let white = take_player(&mut players[game.white], &specs[game.white]);
+        let black = take_player(&mut players[game.black], &specs[game.black]);
+        let record = match (white, black) {
+            (Ok(mut white), Ok(mut black)) => {
+                let record = match_runner::play_game(white.as_mut(), black.as_mut(), &game.opening, settings);
+                if record.failed != Some(Color::White) {
+                    players[game.white] = Some(white);
+                }
+                if record.failed != Some(Color::Black) {
+                    players[game.black] = Some(black);
+                }
+                record
+            }
+            (Err(msg), black) => {
+                players[game.black] = black.ok();
+                get_failure(&game, Color::White, msg)
+            }
+            (Ok(white), Err(msg)) => {
+                players[game.white] = Some(white);
+                get_failure(&game, Color::Black, msg)
+            }
+        };
+
+        if results.send((game, record)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Maximum likelihood ratings of a logistic model with PRIOR_DRAWS
+/// virtual draws per pairing, and the half width of their 95% intervals.
+/// The average rating is 0
+fn get_ratings(players: usize, games: &[(usize, usize, f64)]) -> Vec<(f64, f64)> {
+    // points and games of i against j, virtual draws included
+    let mut points = vec![vec![0.0; players]; players];
+    let mut counts = vec![vec![0.0; players]; players];
+    for (white, black, white_score) in games {
+        points[*white][*black] += white_score;
+        points[*black][*white] += 1.0 - white_score;
+        counts[*white][*black] += 1.0;
+        counts[*black][*white] += 1.0;
+    }
+    for i in 0..players {
+        for j in 0..players {
+            if i != j && counts[i][j] > 0.0 {
+                points[i][j] += PRIOR_DRAWS / 2.0;
+                counts[i][j] += PRIOR_DRAWS;
+            }
+        }
+    }
+
+    // Newton steps on each rating in turn, in units of ln(10) / 400 Elo
+    let scale = 10f64.ln() / 400.0;
+    let mut ratings = vec![0.0; players];
+    let mut information = vec![0.0; players];
+    for _ in 0..MAX_RATING_ITERATIONS {
+        let mut largest_step: f64 = 0.0;
+        for i in 0..players {
+            let (mut gradient, mut curvature) = (0.0, 0.0);
+            for j in 0..players {
+                if counts[i][j] == 0.0 {
+                    continue;
+                }
+                let expected = match_runner::get_expected_score(ratings[i] - ratings[j]);
+                gradient += points[i][j] - counts[i][j] * expected;
+                curvature += counts[i][j] * expected * (1.0 - expected);
+            }
+            information[i] = curvature * scale * scale;
+            if curvature > 0.0 {
+                let step = gradient / (curvature * scale);
+                ratings[i] += step;
+                largest_step = largest_step.max(step.abs());
+            }
+        }
+        if largest_step < RATING_TOLERANCE {
+            break;
+        }
+    }
+
+    let mean = ratings.iter().sum::<f64>() / players as f64;
+    ratings
+        .iter()
+        .zip(information)
+        .map(|(rating, information)| (rating - mean, 1.96 / information.sqrt()))
+        .collect()
+}
+
+fn print_standings(names: &[String], schedule: &[ScheduledGame], finished: &[Option<FinishedGame>]) {
+    let players = names.len();
+    let mut games = Vec::new();
+    let mut points = vec![vec![0.0; players]; players];
+    let mut counts = vec![vec![0; players]; players];
+    let mut draws = vec![0; players];
+    let mut failures = vec![0; players];
+
+    for (game, result) in schedule.iter().zip(finished) {
+        let Some(result) = result else { continue };
+        games.push((game.white, game.black, result.white_score));
+        points[game.white][game.black] += result.white_score;
+        points[game.black][game.white] += 1.0 - result.white_score;
+        counts[game.white][game.black] += 1;
+        counts[game.black][game.white] += 1;
+        if result.white_score == 0.5 {
+            draws[game.white] += 1;
+            draws[game.black] += 1;
+        }
+        match result.failed {
+            Some(Color::White) => failures[game.white] += 1,
+            Some(Color::Black) => failures[game.black] += 1,
+            None => {}
+        }
+    }
+
+    let ratings = get_ratings(players, &games);
+    let mut order: Vec<usize> = (0..players).collect();
+    order.sort_by(|a, b| ratings[*b].0.total_cmp(&ratings[*a].0));
+
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0).max(4);
+    println!();
+    println!("{:>4} {:<width$} {:>7} {:>6} {:>6} {:>7} {:>6} {:>6}", "Rank", "Name", "Elo", "+/-", "Games", "Score", "Draws", "Fails");
+    for (rank, i) in order.iter().enumerate() {
+        let played: i32 = counts[*i].iter().sum();
+        let score: f64 = points[*i].iter().sum();
+        let percent = |n: f64| if played > 0 { 100.0 * n / played as f64 } else { 0.0 };
+        println!(
+            "{:>4} {:<width$} {:>7.1} {:>6.1} {:>6} {:>6.1}% {:>5.1}% {:>6}",
+            rank + 1,
+            names[*i],
+            ratings[*i].0,
+            ratings[*i].1,
+            played,
+            percent(score),
+            percent(draws[*i] as f64),
+            failures[*i]
+        );
+    }
+
+    // one column per opponent, in the order of the standings
+    println!();
+    print!("{:<width$}", "", width = width + 3);
+    for j in &order {
+        print!(" {:>8}", format!("#{}", order.iter().position(|k| k == j).unwrap() + 1));
+    }
+    println!();
+    for (rank, i) in order.iter().enumerate() {
+        print!("{:<width$}", format!("#{} {}", rank + 1, names[*i]), width = width + 3);
+        for j in &order {
+            let cell = match counts[*i][*j] {
+                _ if i == j => "-".to_string(),
+                0 => "".to_string(),
+                n => format!("{}/{}", points[*i][*j], n),
+            };
+            print!(" {:>8}", cell);
+        }
+        println!();
+    }
+}
+
+/// Usage: tournament <state file> <player>... [--gauntlet] [--rounds N] [--concurrency N]
+///        [--openings file] [--pgn file] [--max-plies N] [--resign-score CP] [--resign-moves N]
+///        [--draw-score CP] [--draw-moves N] [--draw-after N]
+///
+/// Plays a round robin, or with --gauntlet the first player against all
+/// others, with `concurrency` games at a time (default 1). Players are
+/// written as for `match`. Every round plays each pairing twice from one
+/// opening with colors swapped. Finished games are appended to the state
+/// file, `tournament <state file>` alone goes on where an interrupted
+/// tournament stopped. Prints the standings with ratings on the Elo scale
+/// and a crosstable
+pub fn run(args: &[String]) {
+    if args.is_empty() {
+        println!("Usage: tournament <state file> <player>... [--gauntlet] [--rounds N] [--concurrency N] [--openings file] [--pgn file]");
+        println!("       [--max-plies N] [--resign-score CP] [--resign-moves N] [--draw-score CP] [--draw-moves N] [--draw-after N]");
+        println!("       tournament <state file>  continues a tournament");
+        return;
+    }
+    let state_path = &args[0];
+
+    let is_resumed = Path::new(state_path).exists();
+    let state = if is_resumed {
+        if args.len() > 1 {
+            println!("{} already exists, continue it with: tournament {}", state_path, state_path);
+            return;
+        }
+        match load_state(state_path) {
+            Ok(state) => state,
+            Err(msg) => {
+                println!("{}", msg);
+                return;
+            }
+        }
+    } else {
+        TournamentState {
+            args: args[1..].to_vec(),
+            games: Vec::new(),
+        }
+    };
+    let tournament_args = state.args;
+
+    let options = match parse_options(&tournament_args) {
+        Ok(options) => options,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    // start every player once, to find its name and fail early on a bad engine
+    let mut players = Vec::new();
+    for spec in &options.specs {
+        match spec.create() {
+            Ok(player) => players.push(player),
+            Err(msg) => {
+                println!("{}", msg);
+                return;
+            }
+        }
+    }
+    let names = get_names(&players);
+    drop(players);
+
+    let schedule = get_schedule(&options);
+    let mut finished = get_finished(&schedule, state.games);
+
+    let mut state_file = match OpenOptions::new().create(true).append(true).open(state_path) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("Could not open {}: {}", state_path, e);
+            return;
+        }
+    };
+    if !is_resumed {
+        let mut header = String::from("# tournament state, continue with: tournament <this file>\n");
+        for arg in &tournament_args {
+            header += &format!("arg {}\n", arg);
+        }
+        if let Err(e) = state_file.write_all(header.as_bytes()) {
+            println!("Could not write {}: {}", state_path, e);
+            return;
+        }
+    }
+    let mut pgn_file = match &options.pgn_path {
+        Some(path) => match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                println!("Could not open {}: {}", path, e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let queue = get_queue(&schedule, &finished);
+    let remaining = queue.len();
+    println!("{} of {} games to play", remaining, schedule.len());
+
+    let now = Instant::now();
+    let queue = Arc::new(Mutex::new(queue));
+    let specs = Arc::new(options.specs.clone());
+    let (sender, receiver) = mpsc::channel();
+    let mut workers = Vec::new();
+    for _ in 0..options.concurrency.min(remaining) {
+        let (queue, specs, settings, sender) = (queue.clone(), specs.clone(), options.settings.clone(), sender.clone());
+        workers.push(thread::spawn(move || run_worker(&specs, &queue, &settings, &sender)));
+    }
+    drop(sender);
+
+    let event = match options.schedule {
+        Schedule::RoundRobin => "Round robin",
+        Schedule::Gauntlet => "Gauntlet",
+    };
+    for (count, (game, mut record)) in receiver.iter().enumerate() {
+        record.white = names[game.white].clone();
+        record.black = names[game.black].clone();
+        println!(
+            "Game {}/{} (#{}): {} vs {} {} ({})",
+            count + 1,
+            remaining,
+            game.index + 1,
+            record.white,
+            record.black,
+            record.get_result(),
+            record.termination
+        );
+
+        if let Err(e) = state_file.write_all(get_state_line(&game, &record).as_bytes()) {
+            println!("Could not write {}: {}", state_path, e);
+        }
+        if let Some(file) = pgn_file.as_mut() {
+            if let Err(e) = file.write_all(record.to_pgn_game(event, game.round + 1).to_pgn().as_bytes()) {
+                println!("Could not write the PGN: {}", e);
+            }
+        }
+        finished[game.index] = Some(FinishedGame {
+            white_score: record.get_white_score(),
+            failed: record.failed,
+        });
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    print_standings(&names, &schedule, &finished);
+    println!("{:.1}s", now.elapsed().as_secs_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::fen;
+
+    fn get_options(args: &[&str]) -> TournamentOptions {
+        parse_options(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    fn get_record(outcome: Outcome, failed: Option<Color>) -> GameRecord {
+        GameRecord {
+            white: String::new(),
+            black: String::new(),
+            start_fen: fen::STARTING_BOARD.to_string(),
+            moves: Vec::new(),
+            scores: Vec::new(),
+            outcome,
+            termination: String::from("checkmate"),
+            failed,
+        }
+    }
+
+    #[test]
+    fn schedule_has_two_games_per_pairing_and_round() {
+        let round_robin = get_schedule(&get_options(&["random", "random", "random", "random", "--rounds", "3"]));
+        assert_eq!(round_robin.len(), 4 * 3 * 3);
+        for player in 0..4 {
+            assert_eq!(round_robin.iter().filter(|game| game.white == player).count(), 3 * 3);
+            assert_eq!(round_robin.iter().filter(|game| game.black == player).count(), 3 * 3);
+        }
+
+        let gauntlet = get_schedule(&get_options(&["random", "random", "random", "random", "--gauntlet", "--rounds", "2"]));
+        assert_eq!(gauntlet.len(), 3 * 2 * 2);
+        assert!(gauntlet.iter().all(|game| game.white == 0 || game.black == 0));
+        assert!(gauntlet.iter().enumerate().all(|(i, game)| game.index == i));
+    }
+
+    #[test]
+    fn ratings_follow_results() {
+        let even = get_ratings(2, &[(0, 1, 1.0), (1, 0, 1.0), (0, 1, 0.5), (1, 0, 0.5)]);
+        assert!(even[0].0.abs() < 1e-6 && even[1].0.abs() < 1e-6);
+        assert!((even[0].1 - even[1].1).abs() < 1e-6);
+
+        // the prior draws keep a clean sweep finite
+        let games = [(0, 1, 1.0), (1, 0, 0.0), (0, 2, 1.0), (2, 0, 0.0), (1, 2, 0.5), (2, 1, 0.5)];
+        let ratings = get_ratings(3, &games);
+        assert!(ratings[0].0.is_finite() && ratings[0].0 > ratings[1].0 + 100.0);
+        assert!((ratings[1].0 - ratings[2].0).abs() < 1e-6);
+        assert!(ratings.iter().map(|(rating, _)| rating).sum::<f64>().abs() < 1e-6);
+    }
+
+    /// Arguments and finished games read back as they were written,
+    /// and a resumed tournament only queues the games still to play
+    #[test]
+    fn state_round_trips_and_resumes() {
+        let args = ["random", "random", "--rounds", "2", "--openings", "my openings.txt"];
+        let schedule = get_schedule(&get_options(&args[..4]));
+
+        let mut text = String::from("# tournament state\n");
+        for arg in args {
+            text += &format!("arg {}\n", arg);
+        }
+        let results = [
+            (1, get_record(Outcome::Win(Color::White), None)),
+            (2, get_record(Outcome::Draw, None)),
+            (0, get_record(Outcome::Win(Color::Black), Some(Color::White))),
+        ];
+        for (index, record) in &results {
+            text += &get_state_line(&schedule[*index], record);
+        }
+        let path = std::env::temp_dir().join(format!("tournament-state-{}.txt", std::process::id()));
+        fs::write(&path, text).unwrap();
+        let state = load_state(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let state = state.unwrap();
+
+        assert_eq!(state.args, args);
+        assert_eq!(state.games.len(), results.len());
+        for ((index, game), (expected_index, record)) in state.games.iter().zip(&results) {
+            assert_eq!(index, expected_index);
+            assert_eq!(game.white_score, record.get_white_score());
+            assert!(game.failed == record.failed);
+        }
+
+        let finished = get_finished(&schedule, state.games);
+        let queue = get_queue(&schedule, &finished);
+        let indices: Vec<usize> = queue.iter().map(|game| game.index).collect();
+        assert_eq!(indices, [3]);
+    }
+}
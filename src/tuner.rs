@@ -0,0 +1,260 @@
+use std::fs;
+use std::time::Instant;
+
+use crate::chess::{board::Board, fen, variant::Variant};
+use crate::game::evaluator::{EvalParams, MaterialMobilityEvaluator, PARAM_COUNT, PARAM_NAMES};
+
+/// A labelled position reduced to its evaluation features
+struct Sample {
+    features: [f64; PARAM_COUNT],
+    result: f64,
+}
+
+/// Step sizes the local search walks through, coarse to fine
+const STEPS: [f64; 4] = [0.1, 0.01, 0.001, 0.0001];
+
+/// Usage: tune <positions file> <output file> [max passes]
+///
+/// Every line of the positions file holds a quiet FEN (the move counters
+/// may be omitted, as in EPD) followed by the game result, either as
+/// `1-0`, `0-1`, `1/2-1/2` or as White's score `1.0`, `0.5`, `0.0` in the
+/// last `|` separated field or in brackets.
+/// E.g. `rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - c9 "1/2-1/2";`
+/// or `rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1 | 21 | 0.5`.
+/// Lines without a result or with a bad FEN are skipped
+pub fn run(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: tune <positions file> <output file> [max passes]");
+        return;
+    }
+    let max_passes = match args.get(2) {
+        Some(passes) => passes.parse::<usize>().unwrap_or(100),
+        None => 100,
+    };
+
+    let now = Instant::now();
+    let samples = match load_samples(&args[0]) {
+        Ok(samples) => samples,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+    println!("Loaded {} positions in {:.1}s", samples.len(), now.elapsed().as_secs_f64());
+    if samples.is_empty() {
+        return;
+    }
+
+    let mut params = EvalParams::new();
+    let k = find_scaling_constant(&samples, &params);
+    println!("Scaling constant K = {:.4}", k);
+
+    let error = local_search(&samples, &mut params, k, max_passes);
+    println!("Final error {:.8}", error);
+    for (name, weight) in PARAM_NAMES.iter().zip(params.weights) {
+        println!("{} {}", name, weight);
+    }
+
+    match params.save(&args[1]) {
+        Ok(()) => println!("Wrote {}", args[1]),
+        Err(e) => println!("Could not write {}: {}", args[1], e),
+    }
+}
+
+fn load_samples(path: &str) -> Result<Vec<Sample>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let evaluator = MaterialMobilityEvaluator::new();
+
+    let mut out = Vec::new();
+    let mut without_result = 0;
+    let mut bad_fens = 0;
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some((fen, result)) = parse_line(line) else {
+            without_result += 1;
+            continue;
+        };
+        if let Err(msg) = fen::validate(&fen, Variant::Standard) {
+            if bad_fens == 0 {
+                println!("Bad FEN on line {}: {}", i + 1, msg);
+            }
+            bad_fens += 1;
+            continue;
+        }
+        out.push(Sample {
+            features: evaluator.get_features(&Board::new(&fen)),
+            result,
+        });
+    }
+    if without_result + bad_fens > 0 {
+        println!("Skipped {} lines without a result and {} with a bad FEN", without_result, bad_fens);
+    }
+    Ok(out)
+}
+
+/// White's result as a score written on its own: `1.0`, `0.5` or `0.0`
+fn parse_score(text: &str) -> Option<f64> {
+    text.trim().parse::<f64>().ok().filter(|score| [0f64, 0.5, 1f64].contains(score))
+}
+
+/// Split a line into a six field FEN and White's result,
+/// None when the line does not state a result
+pub fn parse_line(line: &str) -> Option<(String, f64)> {
+    let (position, score_field) = match line.rsplit_once('|') {
+        Some((position, score)) => (position.split('|').next()?, Some(score)),
+        None => (line, None),
+    };
+    let fields: Vec<&str> = position.split_whitespace().collect();
+    if fields.len() < 4 {
+        return None;
+    }
+
+    let token_result = fields[4..].iter().find_map(|field| {
+        match field.trim_matches(|c: char| c == '"' || c == ';' || c == '[' || c == ']') {
+            "1-0" => Some(1f64),
+            "0-1" => Some(0f64),
+            "1/2-1/2" => Some(0.5),
+            score if field.starts_with('[') && field.ends_with(']') => parse_score(score),
+            _ => None,
+        }
+    });
+    let result = token_result.or_else(|| parse_score(score_field?))?;
+
+    let mut fen = fields[..4].join(" ");
+    let has_clocks = fields.len() >= 6 && fields[4].parse::<u16>().is_ok() && fields[5].parse::<u16>().is_ok();
+    if has_clocks {
+        fen += &format!(" {} {}", fields[4], fields[5]);
+    } else {
+        fen += " 0 1";
+    }
+    Some((fen, result))
+}
+
+/// Expected score for White, the evaluation is in pawns
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1f64 / (1f64 + 10f64.powf(-k * score / 4f64))
+}
+
+fn mean_squared_error(samples: &[Sample], params: &EvalParams, k: f64) -> f64 {
+    let total: f64 = samples
+        .iter()
+        .map(|s| {
+            let diff = s.result - sigmoid(params.evaluate_features(&s.features), k);
+            diff * diff
+        })
+        .sum();
+    total / samples.len() as f64
+}
+
+/// Pick the K that best fits the starting parameters,
+/// so the tuning changes the weights instead of the scale
+fn find_scaling_constant(samples: &[Sample], params: &EvalParams) -> f64 {
+    let mut best_k = 1f64;
+    let mut best_error = mean_squared_error(samples, params, best_k);
+
+    for step in [0.1, 0.01, 0.001] {
+        let mut improved = true;
+        while improved {
+            improved = false;
+            for candidate in [best_k - step, best_k + step] {
+                if candidate <= 0f64 {
+                    continue;
+                }
+                let error = mean_squared_error(samples, params, candidate);
+                if error < best_error {
+                    best_error = error;
+                    best_k = candidate;
+                    improved = true;
+                }
+            }
+        }
+    }
+    best_k
+}
+
+/// Texel local search: nudge each weight up or down
+/// and keep any change that lowers the error
+fn local_search(samples: &[Sample], params: &mut EvalParams, k: f64, max_passes: usize) -> f64 {
+    let mut best_error = mean_squared_error(samples, params, k);
+
+    for step in STEPS {
+        for pass in 0..max_passes {
+            let mut improved = false;
+
+            for i in 0..PARAM_COUNT {
+                for delta in [step, -step] {
+                    let mut candidate = *params;
+                    candidate.weights[i] += delta;
+                    let error = mean_squared_error(samples, &candidate, k);
+                    if error < best_error {
+                        best_error = error;
+                        *params = candidate;
+                        improved = true;
+                        break;
+                    }
+                }
+            }
+
+            println!("step {} pass {} error {:.8}", step, pass + 1, best_error);
+            if !improved {
+                break;
+            }
+        }
+    }
+    best_error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFTER_E4: &str = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq -";
+
+    #[test]
+    fn reads_both_line_forms() {
+        let fen = format!("{} 0 1", AFTER_E4);
+        // FEN | score | result, as datagen writes it
+        assert_eq!(parse_line(&format!("{} 0 1 | 21 | 0.5", AFTER_E4)), Some((fen.clone(), 0.5)));
+        assert_eq!(parse_line(&format!("{} 3 9 | -40 | 0.0", AFTER_E4)), Some((format!("{} 3 9", AFTER_E4), 0.0)));
+        // FEN followed by the result, with or without move counters
+        assert_eq!(parse_line(&format!("{} 1-0", AFTER_E4)), Some((fen.clone(), 1.0)));
+        assert_eq!(parse_line(&format!("{} 0 1 0-1", AFTER_E4)), Some((fen.clone(), 0.0)));
+        assert_eq!(parse_line(&format!("{} c9 \"1/2-1/2\";", AFTER_E4)), Some((fen.clone(), 0.5)));
+        assert_eq!(parse_line(&format!("{} [1.0]", AFTER_E4)), Some((fen, 1.0)));
+
+        assert_eq!(parse_line(AFTER_E4), None);
+        assert_eq!(parse_line(&format!("{} 0 1 | 21 | 0.7", AFTER_E4)), None);
+        assert_eq!(parse_line("8/8/8 w 1-0"), None);
+    }
+
+    /// Knight odds only draw while the other pieces win,
+    /// so the knight must lose weight against the rest
+    #[test]
+    fn local_search_lowers_error() {
+        let evaluator = MaterialMobilityEvaluator::new();
+        let positions = [
+            ("4k3/pppp4/8/8/8/8/PPPP4/1N2K3 w - - 0 1", 0.5),
+            ("1n2k3/pppp4/8/8/8/8/PPPP4/4K3 w - - 0 1", 0.5),
+            ("4k3/pppp4/8/8/8/8/PPPP4/2B1K3 w - - 0 1", 1f64),
+            ("r3k3/pppp4/8/8/8/8/PPPP4/4K3 w - - 0 1", 0f64),
+            ("4k3/pppp4/8/8/8/8/PPPP4/4K3 w - - 0 1", 0.5),
+        ];
+        let samples: Vec<Sample> = positions
+            .iter()
+            .map(|(fen, result)| Sample { features: evaluator.get_features(&Board::new(fen)), result: *result })
+            .collect();
+
+        let mut params = EvalParams::new();
+        let k = find_scaling_constant(&samples, &params);
+        assert!(k > 0f64);
+        assert!(mean_squared_error(&samples, &params, k) <= mean_squared_error(&samples, &params, 1f64));
+
+        let start_error = mean_squared_error(&samples, &params, k);
+        let error = local_search(&samples, &mut params, k, 3);
+        assert!(error < start_error);
+        assert_eq!(error, mean_squared_error(&samples, &params, k));
+        assert!(params.weights[1] < EvalParams::new().weights[1]);
+    }
+}
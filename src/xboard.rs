@@ -0,0 +1,472 @@
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::api::chess_api::{self, ChessGame, GameAPI, Message};
+use crate::chess::cmove::Move;
+use crate::chess::color::Color;
+use crate::chess::dtm::{self, DtmTablebases};
+use crate::chess::fen;
+use crate::chess::move_api::MoveAPI;
+use crate::chess::pgn;
+use crate::chess::syzygy::{self, Tablebases};
+use crate::chess::variant::Variant;
+use crate::game::agent::{Agent, SearchReport, TomatoAgent};
+use crate::game::book::{self, BookSelection, OpeningBook};
+
+/// Depth the engine searches to when it knows nothing about the clock
+const DEFAULT_DEPTH: usize = 4;
+
+/// Depth limit of a search the clock stops
+const MAX_DEPTH: usize = 32;
+
+/// Centipawns the protocol reads as a mate
+const MATE_SCORE: f64 = 100000.0;
+
+/// Moves the remaining time is shared over when the time control
+/// gives the whole game
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+/// Protocol name of Chess960, played with the standard rules
+const CHESS960_NAME: &str = "fischerandom";
+
+/// Variant names of the protocol and the variants they stand for
+const VARIANT_NAMES: [(&str, Variant); 6] = [
+    ("normal", Variant::Standard),
+    ("kingofthehill", Variant::KingOfTheHill),
+    ("3check", Variant::ThreeCheck),
+    ("crazyhouse", Variant::Crazyhouse),
+    ("giveaway", Variant::Antichess),
+    ("atomic", Variant::Atomic),
+];
+
+/// A `level` time control: `moves_per_session` moves (0 for the whole game)
+/// in `base`, plus `increment` for every move
+struct TimeControl {
+    moves_per_session: u32,
+    base: Duration,
+    increment: Duration,
+}
+
+/// What the engine knows about the game and how to play it
+struct Session {
+    game: ChessGame,
+    variant: Variant,
+    /// Set by `variant fischerandom` until the next `new`
+    is_chess960: bool,
+    /// None in force mode, where the engine only keeps track of the moves
+    engine_color: Option<Color>,
+    /// Plies played since `new` or `setboard`, undone ones taken off again
+    plies: usize,
+    max_depth: Option<usize>,
+    move_time: Option<Duration>,
+    time_control: Option<TimeControl>,
+    time_left: Option<Duration>,
+    is_posting: bool,
+    book: Option<Arc<OpeningBook>>,
+    tablebases: Option<Arc<Tablebases>>,
+    dtm_tables: Option<Arc<DtmTablebases>>,
+}
+
+/// Usage: xboard
+///
+/// Speaks the Chess Engine Communication Protocol (version 2) on standard
+/// input and output, for WinBoard, XBoard and the tournament managers that
+/// drive engines through them. Commands sent while the engine thinks are
+/// read once it has moved. The opening book is read from
+/// `book::DEFAULT_BOOK_FILE`, the Syzygy tables from `syzygy::DEFAULT_SYZYGY_DIR`
+/// and the distance-to-mate tables from `dtm::DEFAULT_DTM_DIR`
+pub fn run() {
+    let mut session = Session::new();
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if !session.handle(line.trim()) {
+            break;
+        }
+        let _ = io::stdout().flush();
+    }
+}
+
+fn parse_seconds(text: &str) -> Option<Duration> {
+    let seconds = text.parse::<f64>().ok()?;
+    Some(Duration::from_secs_f64(seconds.max(0.0)))
+}
+
+/// Minutes, or minutes and seconds like 2:30
+fn parse_minutes(text: &str) -> Option<Duration> {
+    match text.split_once(':') {
+        Some((minutes, seconds)) => Some(parse_seconds(minutes)? * 60 + parse_seconds(seconds)?),
+        None => Some(parse_seconds(text)? * 60),
+    }
+}
+
+fn print_report(report: &SearchReport, color: Color) {
+    let mut pv_color = color;
+    let mut pv = Vec::new();
+    for m in &report.pv {
+        pv.push(m.get_coordinates(pv_color));
+        pv_color = pv_color.get_opposite();
+    }
+    println!(
+        "{} {} {} {} {}",
+        report.depth,
+        (report.score * 100.0).clamp(-MATE_SCORE, MATE_SCORE).round() as i64,
+        report.elapsed.as_millis() / 10,
+        report.nodes,
+        pv.join(" ")
+    );
+    let _ = io::stdout().flush();
+}
+
+impl Session {
+    fn new() -> Session {
+        let book = OpeningBook::load(book::DEFAULT_BOOK_FILE).ok().map(Arc::new);
+        let tablebases = Some(Tablebases::open(syzygy::DEFAULT_SYZYGY_DIR))
+            .filter(|t| t.len() > 0)
+            .map(Arc::new);
+        let dtm_tables = Some(DtmTablebases::open(dtm::DEFAULT_DTM_DIR))
+            .filter(|t| t.len() > 0)
+            .map(Arc::new);
+
+        Session {
+            game: ChessGame::new(MoveAPI::new(Variant::Standard.get_start_fen())),
+            variant: Variant::Standard,
+            is_chess960: false,
+            engine_color: Some(Color::Black),
+            plies: 0,
+            max_depth: None,
+            move_time: None,
+            time_control: None,
+            time_left: None,
+            is_posting: false,
+            book,
+            tablebases,
+            dtm_tables,
+        }
+    }
+
+    /// Handle one command, false once the engine should quit
+    fn handle(&mut self, line: &str) -> bool {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+
+        match command {
+            "quit" => return false,
+            "protover" => self.send_features(),
+            "new" => {
+                self.variant = Variant::Standard;
+                self.is_chess960 = false;
+                self.set_position(self.variant.get_start_fen());
+                self.engine_color = Some(Color::Black);
+                self.max_depth = None;
+                self.move_time = None;
+                self.time_left = None;
+            }
+            // the frontend may send the array with setboard next
+            "variant" if argument == CHESS960_NAME => {
+                self.variant = Variant::Standard;
+                self.is_chess960 = true;
+                self.set_position(&fen::random_chess960_start_position());
+            }
+            "variant" => match VARIANT_NAMES.iter().find(|(name, _)| *name == argument) {
+                Some((_, variant)) => {
+                    self.variant = *variant;
+                    self.set_position(variant.get_start_fen());
+                }
+                None => println!("Error (unsupported variant): {}", argument),
+            },
+            "setboard" => self.set_position(argument),
+            "force" => self.engine_color = None,
+            "go" => {
+                self.engine_color = Some(self.game.get_turn_color());
+                self.play_engine_move();
+            }
+            "playother" => self.engine_color = Some(self.game.get_turn_color().get_opposite()),
+            "white" | "black" => {
+                // protocol version 1: the side to move, the engine plays the other one
+                let color = if command == "white" { Color::White } else { Color::Black };
+                self.engine_color = Some(color.get_opposite());
+            }
+            "level" => self.set_level(argument),
+            "st" => self.move_time = parse_seconds(argument),
+            "sd" => self.max_depth = argument.parse::<usize>().ok().filter(|d| *d > 0),
+            "time" => self.time_left = argument.parse::<u64>().ok().map(|cs| Duration::from_millis(cs * 10)),
+            "usermove" => self.play_user_move(argument),
+            "undo" => self.undo(1),
+            "remove" => self.undo(2),
+            "result" => self.engine_color = None,
+            "post" => self.is_posting = true,
+            "nopost" => self.is_posting = false,
+            "ping" => println!("pong {}", argument),
+            // nothing to do for these
+            "xboard" | "accepted" | "rejected" | "otim" | "random" | "hard" | "easy" | "computer" | "name"
+            | "rating" | "ics" | "?" | "" => {}
+            _ => println!("Error (unknown command): {}", command),
+        }
+        true
+    }
+
+    fn send_features(&self) {
+        let mut variants: Vec<&str> = VARIANT_NAMES.iter().map(|(name, _)| *name).collect();
+        variants.push(CHESS960_NAME);
+        println!("feature done=0");
+        println!(
+            "feature myname=\"Tomato\" setboard=1 usermove=1 ping=1 playother=1 colors=0 analyze=0 sigint=0 sigterm=0 variants=\"{}\"",
+            variants.join(",")
+        );
+        println!("feature done=1");
+    }
+
+    /// A FEN that cannot be read keeps the old position
+    fn set_position(&mut self, fen: &str) {
+        if fen::validate(fen, self.variant).is_err() {
+            println!("tellusererror Illegal position");
+            return;
+        }
+        self.game = ChessGame::new(MoveAPI::with_variant(fen, self.variant));
+        self.plies = 0;
+    }
+
+    /// `level MPS BASE INC`, the base time may be given as minutes:seconds
+    fn set_level(&mut self, argument: &str) {
+        let fields: Vec<&str> = argument.split_whitespace().collect();
+        if fields.len() != 3 {
+            println!("Error (bad level): {}", argument);
+            return;
+        }
+        let moves_per_session = fields[0].parse::<u32>().ok();
+        let base = parse_minutes(fields[1]);
+        let increment = parse_seconds(fields[2]);
+        match (moves_per_session, base, increment) {
+            (Some(moves_per_session), Some(base), Some(increment)) => {
+                self.time_control = Some(TimeControl { moves_per_session, base, increment });
+                self.move_time = None;
+            }
+            _ => println!("Error (bad level): {}", argument),
+        }
+    }
+
+    fn play_user_move(&mut self, text: &str) {
+        // castling may come as O-O in Chess960 frontends
+        let mut move_api = MoveAPI::from_board(self.game.get_board());
+        let found = move_api
+            .find_coordinate_move(text)
+            .or_else(|| pgn::find_san_move(&mut move_api, text));
+
+        match found {
+            Some(m) => {
+                self.game.try_move(m);
+                self.plies += 1;
+                if !self.print_result() && self.engine_color == Some(self.game.get_turn_color()) {
+                    self.play_engine_move();
+                }
+            }
+            None => println!("Illegal move: {}", text),
+        }
+    }
+
+    fn undo(&mut self, plies: usize) {
+        for _ in 0..plies.min(self.plies) {
+            self.game.try_undo();
+            self.plies -= 1;
+        }
+    }
+
+    /// Time for the engine's next move from `st`, or from its clock
+    /// and the time control, never more than half of what is left
+    fn get_move_time(&self) -> Option<Duration> {
+        if self.move_time.is_some() {
+            return self.move_time;
+        }
+        let time_control = self.time_control.as_ref();
+        let time_left = self.time_left.or(time_control.map(|c| c.base))?;
+
+        let (moves_to_go, increment) = match time_control {
+            Some(c) if c.moves_per_session > 0 => (c.moves_per_session - (self.plies / 2) as u32 % c.moves_per_session, c.increment),
+            Some(c) => (DEFAULT_MOVES_TO_GO, c.increment),
+            None => (DEFAULT_MOVES_TO_GO, Duration::ZERO),
+        };
+        let budget = time_left / moves_to_go + increment * 3 / 4;
+        Some(budget.min(time_left / 2))
+    }
+
+    fn play_engine_move(&mut self) {
+        if self.print_result() {
+            return;
+        }
+
+        let move_time = self.get_move_time();
+        let max_depth = self.max_depth.unwrap_or(if move_time.is_some() { MAX_DEPTH } else { DEFAULT_DEPTH });
+        let mut agent = TomatoAgent::new(max_depth);
+        if let Some(time) = move_time {
+            agent = agent.with_move_time(time);
+        }
+        if self.is_posting {
+            let color = self.game.get_turn_color();
+            agent = agent.with_reporter(Box::new(move |report| print_report(report, color)));
+        }
+        if let Some(book) = &self.book {
+            agent = agent.with_book(book.clone(), book::DEFAULT_BOOK_DEPTH, BookSelection::WeightedRandom);
+        }
+        if let Some(tablebases) = &self.tablebases {
+            agent = agent.with_tablebases(tablebases.clone());
+        }
+        if let Some(dtm_tables) = &self.dtm_tables {
+            agent = agent.with_dtm_tables(dtm_tables.clone());
+        }
+
+        if let Some(m) = agent.getAgentMove(&mut self.game) {
+            let text = self.get_move_text(&m);
+            self.game.try_move(m);
+            self.plies += 1;
+            println!("move {}", text);
+            self.print_result();
+        }
+    }
+
+    /// How the engine sends `m`: in coordinates, except for
+    /// Chess960 castling, which the protocol writes O-O or O-O-O
+    fn get_move_text(&self, m: &Move) -> String {
+        let mut move_api = MoveAPI::from_board(self.game.get_board());
+        if self.is_chess960 {
+            let san = pgn::get_san(&mut move_api, m);
+            if san.starts_with("O-O") {
+                return san.trim_end_matches(['+', '#']).to_string();
+            }
+        }
+        m.get_coordinates(move_api.get_turn_color())
+    }
+
+    /// Tell the frontend when the game is over, true if it is
+    fn print_result(&mut self) -> bool {
+        let state = match self.game.get_outcome() {
+            Message::Info(state) => state,
+            Message::Error(_) => return false,
+        };
+        let result = match state.as_str() {
+            chess_api::NOT_OVER => return false,
+            chess_api::WHITE => "1-0 {White wins}",
+            chess_api::BLACK => "0-1 {Black wins}",
+            _ => "1/2-1/2 {Draw}",
+        };
+        println!("{}", result);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_session(commands: &[&str]) -> Session {
+        let mut session = Session::new();
+        for command in commands {
+            assert!(session.handle(command));
+        }
+        session
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_minutes("2:30"), Some(Duration::from_secs(150)));
+        assert_eq!(parse_minutes("5"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_minutes("0.5"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_minutes("x:30"), None);
+        assert_eq!(parse_seconds("1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_seconds("-3"), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn shares_clock_over_moves_to_go() {
+        // 300s for the 40 moves of the session
+        let mut session = get_session(&["new", "level 40 5 0", "time 30000"]);
+        assert_eq!(session.get_move_time(), Some(Duration::from_millis(7500)));
+        // after 10 moves of each side 30 are left
+        session.plies = 20;
+        assert_eq!(session.get_move_time(), Some(Duration::from_secs(10)));
+
+        // the whole game in 2:30 plus 5s a move
+        let session = get_session(&["new", "level 0 2:30 5", "time 15000"]);
+        assert_eq!(session.get_move_time(), Some(Duration::from_secs(5) + Duration::from_millis(3750)));
+
+        // never more than half of what is left
+        let session = get_session(&["new", "level 0 1 5", "time 400"]);
+        assert_eq!(session.get_move_time(), Some(Duration::from_secs(2)));
+
+        let session = get_session(&["new", "level 0 1 5", "st 3"]);
+        assert_eq!(session.get_move_time(), Some(Duration::from_secs(3)));
+        assert_eq!(get_session(&["new"]).get_move_time(), None);
+    }
+
+    #[test]
+    fn bad_level_keeps_old_one() {
+        let session = get_session(&["level 40 5 0", "level 40 5", "level x 5 0"]);
+        let time_control = session.time_control.as_ref().unwrap();
+        assert_eq!(time_control.moves_per_session, 40);
+        assert_eq!(time_control.base, Duration::from_secs(300));
+    }
+
+    #[test]
+    fn dispatches_commands() {
+        let mut session = get_session(&["new", "sd 1"]);
+        assert_eq!(session.max_depth, Some(1));
+        assert!(session.engine_color == Some(Color::Black));
+
+        // the engine answers as Black
+        session.handle("usermove e2e4");
+        assert_eq!(session.plies, 2);
+        assert!(session.game.get_turn_color() == Color::White);
+
+        // in force mode it only keeps track of the moves
+        session.handle("force");
+        session.handle("usermove d2d4");
+        assert_eq!(session.plies, 3);
+        assert!(session.engine_color.is_none());
+
+        session.handle("go");
+        assert_eq!(session.plies, 4);
+        assert!(session.engine_color == Some(Color::Black));
+
+        session.handle("remove");
+        assert_eq!(session.plies, 2);
+        session.handle("usermove e9e4");
+        assert_eq!(session.plies, 2);
+
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+        session.handle(&format!("setboard {}", fen));
+        assert_eq!(session.plies, 0);
+        assert_eq!(session.game.get_board().get_fen(), fen);
+        session.handle("setboard 4k3/8/8 w - - 0 1");
+        assert_eq!(session.game.get_board().get_fen(), fen);
+
+        session.handle("new");
+        assert_eq!(session.game.get_board().get_fen(), fen::STARTING_BOARD);
+        assert!(session.max_depth.is_none());
+        assert!(!session.handle("quit"));
+    }
+
+    #[test]
+    fn plays_chess960() {
+        let mut session = get_session(&["new", "variant fischerandom"]);
+        assert!(session.is_chess960);
+        let start = session.game.get_board().get_fen();
+        assert!(start.ends_with("w KQkq - 0 1") && start.contains("/pppppppp/8/8/8/8/PPPPPPPP/"));
+
+        // castling is sent as O-O, other moves in coordinates
+        session.handle("setboard 4k3/8/8/8/8/8/8/1R2K2R w HB - 0 1");
+        let mut move_api = MoveAPI::from_board(session.game.get_board());
+        let short = pgn::find_san_move(&mut move_api, "O-O").unwrap();
+        let long = pgn::find_san_move(&mut move_api, "O-O-O").unwrap();
+        let rook_move = move_api.find_coordinate_move("h1h5").unwrap();
+        assert_eq!(session.get_move_text(&short), "O-O");
+        assert_eq!(session.get_move_text(&long), "O-O-O");
+        assert_eq!(session.get_move_text(&rook_move), "h1h5");
+
+        session.handle("new");
+        assert!(!session.is_chess960);
+    }
+}